@@ -0,0 +1,69 @@
+/*
+ * C API for the mbox2db import engine (built as libmbox2db.so / .dylib / .dll).
+ *
+ *     Mbox2dbImporter *importer = mbox2db_importer_open("mail.db", "{\"fts\": true}");
+ *     while ((n = read(fd, buffer, sizeof buffer)) > 0)
+ *         if (mbox2db_importer_feed(importer, buffer, n) != 0) { ... abort ... }
+ *     int64_t imported = mbox2db_importer_finalize(importer);
+ *
+ * On failure a call returns NULL or -1 and mbox2db_last_error() says why.
+ */
+
+#ifndef MBOX2DB_H
+#define MBOX2DB_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Mbox2dbImporter Mbox2dbImporter;
+
+/*
+ * Opens or creates the database and starts an import. Messages already in an
+ * existing database are skipped. options_json may be NULL or an object with any of:
+ *   "schema": "flat" | "normalized" | "full"      "fts": bool
+ *   "include_spam", "include_trash", "include_spam_and_trash": bool
+ *   "store_signatures", "sanitize_html", "no_views", "attachments_in_db", "extract_amounts": bool
+ *   "account", "aliases" (file path), "table_prefix", "table_name": string
+ *   "config": TOML file path; only its date_formats and [headers] are used
+ *   "me": array of my own addresses, for the importance score
+ *   "busy_timeout": seconds to wait for a database another process has locked (default 30)
+ *   "keep_going": bool, log messages that fail to insert in insert_errors instead of failing
+ *   "consolidate_labels": bool, add the labels of a message already in the database to it
+ *   "max_attachment_size": bytes, or a string like "10MB"
+ *   "oversized_attachments": "skip" | "hash" | "store" (default "hash")
+ *   "chats": "email" | "table" | "skip" (default "email"), for the Chat label's messages
+ *   "prefer": "plain" | "html" | "both" (default "both"), which multipart/alternative version fills the body
+ *   "skip_calendar": bool, leave out calendar invitations, updates and replies
+ *   "ambiguous_tz": "common" | "ignore" (default "common")
+ *   "display_tz": string, a tz database zone such as "Europe/Berlin"
+ *   "warnings_file": string, a file for per-message warnings (as --warnings-file); without it they're dropped
+ *
+ * Command-line options with no key here aren't available: the import always appends
+ * (--if-exists append, never --incremental), the FTS index uses the default tokenizer,
+ * and there is no --profile, --fts-tokenizer, --fts-remove-diacritics, --pragma,
+ * --post-sql, --load-extension, --gpg-keyring, --split-by, --two-pass, --no-space-check,
+ * --summary-json, --embed, --summarize or --vec-index. Unknown keys are ignored.
+ */
+Mbox2dbImporter *mbox2db_importer_open(const char *output_path, const char *options_json);
+
+/* Feeds the next chunk of the mbox stream; chunks may split lines and messages anywhere. */
+int mbox2db_importer_feed(Mbox2dbImporter *importer, const uint8_t *data, size_t len);
+
+/* Commits the import and frees the importer. Returns the number of emails imported, or -1. */
+int64_t mbox2db_importer_finalize(Mbox2dbImporter *importer);
+
+/* Frees the importer without committing anything. */
+void mbox2db_importer_abort(Mbox2dbImporter *importer);
+
+/* Error message of the last failed call on this thread, or NULL. */
+const char *mbox2db_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
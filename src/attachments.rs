@@ -0,0 +1,219 @@
+/// A decoded MIME part that isn't one of the message bodies.
+#[derive(Debug)]
+pub struct Attachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub content_id: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Images referenced from the HTML body via `cid:` (typically multipart/related parts).
+    pub fn is_inline_image(&self) -> bool {
+        self.content_id.is_some() && self.content_type.starts_with("image/")
+    }
+
+    /// Lowercased file extension, if the attachment has a filename with one.
+    pub fn extension(&self) -> Option<String> {
+        let filename = self.filename.as_deref()?;
+        let (_, ext) = filename.rsplit_once('.')?;
+        Some(ext.to_lowercase())
+    }
+}
+
+/// A file stored inside a zip or tar attachment.
+pub struct ArchiveEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Lists the files inside a zip, tar, or gzipped tar attachment without
+/// extracting them. Returns `None` for other attachment types, when the
+/// `archives` feature is disabled, or when the archive can't be read.
+pub fn list_archive(attachment: &Attachment) -> Option<Vec<ArchiveEntry>> {
+    let filename = attachment.filename.as_deref().unwrap_or_default().to_lowercase();
+    let mime = attachment.content_type.as_str();
+
+    if filename.ends_with(".zip") || mime == "application/zip" || mime == "application/x-zip-compressed" {
+        list_zip(&attachment.data)
+    } else if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
+        list_tar(&attachment.data, true)
+    } else if filename.ends_with(".tar") || mime == "application/x-tar" {
+        list_tar(&attachment.data, false)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "archives")]
+fn list_zip(data: &[u8]) -> Option<Vec<ArchiveEntry>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).ok()?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        // Raw access reads the central directory entry without decompressing
+        let file = archive.by_index_raw(index).ok()?;
+        if !file.is_dir() {
+            entries.push(ArchiveEntry { name: file.name().to_string(), size: file.size() });
+        }
+    }
+    Some(entries)
+}
+
+#[cfg(not(feature = "archives"))]
+fn list_zip(_data: &[u8]) -> Option<Vec<ArchiveEntry>> {
+    None
+}
+
+#[cfg(feature = "archives")]
+fn list_tar(data: &[u8], gzipped: bool) -> Option<Vec<ArchiveEntry>> {
+    let reader: Box<dyn std::io::Read> = if gzipped {
+        Box::new(flate2::read::GzDecoder::new(data))
+    } else {
+        Box::new(data)
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries().ok()? {
+        let entry = entry.ok()?;
+        if entry.header().entry_type().is_file() {
+            entries.push(ArchiveEntry {
+                name: entry.path().ok()?.to_string_lossy().into_owned(),
+                size: entry.header().size().ok()?,
+            });
+        }
+    }
+    Some(entries)
+}
+
+#[cfg(not(feature = "archives"))]
+fn list_tar(_data: &[u8], _gzipped: bool) -> Option<Vec<ArchiveEntry>> {
+    None
+}
+
+/// Extracts searchable text from a PDF, DOCX or XLSX attachment, or an
+/// image run through OCR. Returns `None` for other types, when the matching
+/// feature isn't enabled, or when the document can't be read.
+pub fn extract_text(attachment: &Attachment) -> Option<String> {
+    let ext = attachment.extension().unwrap_or_default();
+    let mime = attachment.content_type.as_str();
+    let is_image = mime.starts_with("image/") || matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "tif" | "tiff" | "bmp" | "webp");
+
+    let text = if mime == "application/pdf" || ext == "pdf" {
+        extract_pdf_text(&attachment.data)
+    } else if ext == "docx" || mime == "application/vnd.openxmlformats-officedocument.wordprocessingml.document" {
+        extract_office_text(&attachment.data, OfficeKind::Docx)
+    } else if ext == "xlsx" || mime == "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" {
+        extract_office_text(&attachment.data, OfficeKind::Xlsx)
+    } else if is_image && !attachment.is_inline_image() {
+        // Inline images are logos and signature art far more often than scans
+        extract_image_text(&attachment.data)
+    } else {
+        None
+    }?;
+
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(feature = "pdf")]
+fn extract_pdf_text(data: &[u8]) -> Option<String> {
+    // pdf-extract panics on some malformed documents; treat that like any other failure
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data).ok())
+        .ok()
+        .flatten()
+}
+
+#[cfg(not(feature = "pdf"))]
+fn extract_pdf_text(_data: &[u8]) -> Option<String> {
+    None
+}
+
+/// OCR through the `tesseract` binary (image on stdin, text on stdout).
+/// `None` when tesseract isn't installed or can't read the image.
+#[cfg(feature = "ocr")]
+fn extract_image_text(data: &[u8]) -> Option<String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("tesseract")
+        .args(["stdin", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Feed stdin from a separate thread so a large image can't deadlock the pipes
+    let mut stdin = child.stdin.take()?;
+    let input = data.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().ok()?;
+    writer.join().ok()?.ok()?;
+
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(not(feature = "ocr"))]
+fn extract_image_text(_data: &[u8]) -> Option<String> {
+    None
+}
+
+enum OfficeKind {
+    Docx,
+    Xlsx,
+}
+
+#[cfg(feature = "office")]
+fn extract_office_text(data: &[u8], kind: OfficeKind) -> Option<String> {
+    use once_cell::sync::Lazy;
+    use regex::Regex;
+    use std::io::Read;
+
+    static PARAGRAPH_END: Lazy<Regex> = Lazy::new(|| Regex::new(r"</(w:p|row|si)>").unwrap());
+    static TEXT_RUN: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<(?:w:)?t(?:\s[^>]*)?>(.*?)</(?:w:)?t>|\n").unwrap());
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).ok()?;
+    let names: Vec<String> = archive
+        .file_names()
+        .filter(|name| match kind {
+            OfficeKind::Docx => {
+                name.starts_with("word/") && name.ends_with(".xml")
+                    && (name.contains("document") || name.contains("header") || name.contains("footer"))
+            }
+            OfficeKind::Xlsx => name == &"xl/sharedStrings.xml" || (name.starts_with("xl/worksheets/") && name.ends_with(".xml")),
+        })
+        .map(String::from)
+        .collect();
+
+    let mut text = String::new();
+    for name in names {
+        let mut xml = String::new();
+        archive.by_name(&name).ok()?.read_to_string(&mut xml).ok()?;
+        let xml = PARAGRAPH_END.replace_all(&xml, "\n");
+        for caps in TEXT_RUN.captures_iter(&xml) {
+            match caps.get(1) {
+                Some(run) => text.push_str(&decode_xml_entities(run.as_str())),
+                None if !text.ends_with('\n') => text.push('\n'),
+                None => {}
+            }
+        }
+        text.push('\n');
+    }
+
+    Some(text)
+}
+
+#[cfg(not(feature = "office"))]
+fn extract_office_text(_data: &[u8], _kind: OfficeKind) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "office")]
+fn decode_xml_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+/// One ARC hop (RFC 8617): a forwarder or mailing list that recorded the
+/// authentication results it saw, in ARC-Authentication-Results, and sealed
+/// them, in ARC-Seal.
+#[derive(Debug, Default)]
+pub struct ArcHop {
+    /// The hop's `i=`, 1 for the first forwarder
+    pub instance: u32,
+    /// Who did the checks (the authserv-id), e.g. "mx.google.com"
+    pub authserv_id: Option<String>,
+    /// Results by method: "pass", "fail", "none", ...
+    pub dkim: Option<String>,
+    pub spf: Option<String>,
+    pub dmarc: Option<String>,
+    pub arc: Option<String>,
+    /// The whole ARC-Authentication-Results value
+    pub results: Option<String>,
+    /// ARC-Seal's `d=`, the domain that sealed this hop
+    pub sealed_by: Option<String>,
+    /// ARC-Seal's `cv=`: whether the chain before this hop validated
+    pub chain_validation: Option<String>,
+}
+
+/// The message's ARC hops, in instance order.
+pub fn arc_hops(headers: &[(String, String)]) -> Vec<ArcHop> {
+    let mut hops: BTreeMap<u32, ArcHop> = BTreeMap::new();
+    for (name, value) in headers {
+        let is_results = name.eq_ignore_ascii_case("arc-authentication-results");
+        if !is_results && !name.eq_ignore_ascii_case("arc-seal") {
+            continue;
+        }
+        let stripped = strip_comments(value);
+        let mut parts = stripped.split(';').map(str::trim).filter(|part| !part.is_empty());
+        let Some(instance) = parts.next().and_then(|part| tag_value(part, "i")).and_then(|i| i.parse().ok()) else {
+            continue;
+        };
+        let hop = hops.entry(instance).or_insert_with(|| ArcHop { instance, ..ArcHop::default() });
+
+        if is_results {
+            add_results(hop, parts);
+            hop.results = Some(value.trim().to_string());
+        } else {
+            for part in parts {
+                if let Some(domain) = tag_value(part, "d") {
+                    hop.sealed_by = Some(domain.to_lowercase());
+                } else if let Some(cv) = tag_value(part, "cv") {
+                    hop.chain_validation = Some(cv.to_lowercase());
+                }
+            }
+        }
+    }
+    hops.into_values().collect()
+}
+
+/// Reads "authserv-id; dkim=pass header.d=...; spf=fail ..." into `hop`,
+/// keeping the first result for each method.
+fn add_results<'a>(hop: &mut ArcHop, parts: impl Iterator<Item = &'a str>) {
+    let mut parts = parts.peekable();
+    if let Some(authserv) = parts.next_if(|part| !part.contains('=')) {
+        // A version number may follow the authserv-id
+        hop.authserv_id = authserv.split_whitespace().next().map(str::to_lowercase);
+    }
+
+    for part in parts {
+        let Some((method, rest)) = part.split_once('=') else {
+            continue;
+        };
+        // "dkim/1" is dkim, version 1
+        let slot = match method.split('/').next().unwrap_or_default().trim().to_lowercase().as_str() {
+            "dkim" => &mut hop.dkim,
+            "spf" => &mut hop.spf,
+            "dmarc" => &mut hop.dmarc,
+            "arc" => &mut hop.arc,
+            _ => continue,
+        };
+        if slot.is_none() {
+            *slot = rest.split_whitespace().next().map(str::to_lowercase);
+        }
+    }
+}
+
+/// The value of `tag=value`, if `part` is that tag.
+fn tag_value<'a>(part: &'a str, tag: &str) -> Option<&'a str> {
+    let (name, value) = part.split_once('=')?;
+    name.trim().eq_ignore_ascii_case(tag).then(|| value.trim())
+}
+
+/// Drops RFC 5322 comments, "(like this)", which may nest.
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0usize;
+    let mut stripped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
@@ -0,0 +1,162 @@
+//! `mbox2db bench`: times each stage of an import on a real mbox, so
+//! performance regressions show up as numbers and the cost of options like
+//! --fts or --schema full can be measured on your own mail.
+
+use crate::import::{self, ImportOptions, Importer, Position};
+use crate::report::format_bytes;
+use crate::sources::{MboxStream, RawMessage};
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The per-message column is scaled to this many messages.
+const PER_MESSAGES: u32 = 10_000;
+
+/// Time spent in each import stage.
+#[derive(Default)]
+pub struct BenchReport {
+    pub messages: u32,
+    pub bytes: u64,
+    /// Reading the file and finding message boundaries
+    pub split: Duration,
+    /// MIME parsing and body decoding
+    pub parse: Duration,
+    /// Resolving each message's date
+    pub dates: Duration,
+    /// Filtering, decryption and body cleanup
+    pub transform: Duration,
+    /// Inserting rows
+    pub insert: Duration,
+    /// FTS index, views and the final commit
+    pub commit: Duration,
+}
+
+impl BenchReport {
+    fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("split", self.split),
+            ("parse", self.parse),
+            ("dates", self.dates),
+            ("transform", self.transform),
+            ("insert", self.insert),
+            ("commit", self.commit),
+        ]
+    }
+
+    fn total(&self) -> Duration {
+        self.stages().iter().map(|(_, time)| *time).sum()
+    }
+}
+
+/// Imports `input` with `options` into a scratch database that's deleted
+/// afterwards, stopping after `limit` messages if given. The stages run one
+/// after another in a single thread, unlike a real import, so each one's
+/// time is its own.
+pub fn run_bench(input: &Path, options: ImportOptions, limit: Option<u32>) -> Result<BenchReport> {
+    let file = File::open(input).with_context(|| format!("Failed to open input file: {}", input.display()))?;
+    let path = std::env::temp_dir().join(format!("mbox2db-bench-{}.db", std::process::id()));
+    remove_database(&path);
+    let result = bench_import(file, &path, Arc::new(options), &input.display().to_string(), limit);
+    remove_database(&path);
+    result
+}
+
+fn bench_import(mut file: File, path: &Path, options: Arc<ImportOptions>, input: &str, limit: Option<u32>) -> Result<BenchReport> {
+    let importer = Importer::begin(path, options.clone(), input, ProgressBar::hidden())?;
+    let mut bench = Bench { importer, options, report: BenchReport::default(), limit };
+
+    let started = Instant::now();
+    let mut stream = MboxStream::default();
+    let mut buffer = vec![0; 64 * 1024];
+    while !bench.is_done() {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        stream.feed(&buffer[..n], &mut |message| bench.add(message))?;
+    }
+    let digest = stream.finish(&mut |message| bench.add(message))?;
+    let report = &mut bench.report;
+    // What the other stages didn't take was reading and splitting
+    report.split = started.elapsed().saturating_sub(report.parse + report.dates + report.transform + report.insert);
+
+    let committing = Instant::now();
+    bench.importer.commit(Some(&digest))?;
+    bench.report.commit = committing.elapsed();
+    Ok(bench.report)
+}
+
+struct Bench {
+    importer: Importer,
+    options: Arc<ImportOptions>,
+    report: BenchReport,
+    limit: Option<u32>,
+}
+
+impl Bench {
+    fn is_done(&self) -> bool {
+        self.limit.is_some_and(|limit| self.report.messages >= limit)
+    }
+
+    fn add(&mut self, message: RawMessage) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        let report = &mut self.report;
+        report.messages += 1;
+        report.bytes += message.data.len() as u64;
+        let number = report.messages as usize;
+
+        let mut started = Instant::now();
+        let mut lap = |stage: &mut Duration| {
+            *stage += started.elapsed();
+            started = Instant::now();
+        };
+        let parsed = import::parse_message(message.data, message.folder, self.options.prefer);
+        lap(&mut report.parse);
+        let mut prepared = import::prepare_record(parsed, message.data, &self.options);
+        lap(&mut report.transform);
+        import::resolve_date(&mut prepared, message.data, &self.options.date_parser);
+        lap(&mut report.dates);
+        self.importer.write(message.data, prepared, Position { number, offset: message.offset })?;
+        lap(&mut report.insert);
+        Ok(())
+    }
+}
+
+/// Deletes a scratch database and its WAL files, if they exist.
+fn remove_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = std::fs::remove_file(PathBuf::from(file));
+    }
+}
+
+pub fn write_bench(out: &mut impl Write, report: &BenchReport) -> Result<()> {
+    let total = report.total();
+    let per_messages = |time: Duration| time * PER_MESSAGES / report.messages.max(1);
+
+    writeln!(out, "{} messages, {}", report.messages, format_bytes(report.bytes as i64))?;
+    writeln!(out)?;
+    writeln!(out, "{:<9}  {:>10}  {:>13}  {:>6}", "Stage", "Time", "Per 10k msgs", "Share")?;
+    for (stage, time) in report.stages() {
+        let share = if total.is_zero() { 0.0 } else { time.as_secs_f64() * 100.0 / total.as_secs_f64() };
+        writeln!(out, "{:<9}  {:>10.2?}  {:>13.2?}  {:>5.1}%", stage, time, per_messages(time), share)?;
+    }
+    writeln!(out, "{:<9}  {:>10.2?}  {:>13.2?}", "Total", total, per_messages(total))?;
+
+    let seconds = total.as_secs_f64().max(f64::EPSILON);
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:.0} messages/s, {}/s",
+        report.messages as f64 / seconds,
+        format_bytes((report.bytes as f64 / seconds) as i64)
+    )?;
+    Ok(())
+}
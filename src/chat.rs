@@ -0,0 +1,60 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// "10:23 PM Alice Smith: hey there" or "me: hi"; Google Talk only writes the
+/// time when it differs from the line before's.
+static TRANSCRIPT_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:(\d{1,2}:\d{2}(?::\d{2})?(?:\s?[AaPp]\.?[Mm]\.?)?)\s+)?([^:\s][^:]{0,59}?):\s+(.*)$").unwrap()
+});
+
+/// One line said in a chat.
+#[derive(Debug, PartialEq)]
+pub struct ChatLine {
+    /// As the transcript shows it, carried down to the lines that leave it out
+    pub time: Option<String>,
+    pub sender: String,
+    pub text: String,
+}
+
+/// Whether a message is a Google Talk or Hangouts chat that Takeout exported
+/// as a pseudo-email: it carries the "Chat" label.
+pub fn is_chat(labels: &str) -> bool {
+    labels.split(',').any(|label| label.trim().eq_ignore_ascii_case("chat"))
+}
+
+/// The lines of a chat transcript. A body that isn't one (a Hangouts message
+/// is exported on its own) is a single line said by `from`.
+pub fn transcript(body: &str, from: &str) -> Vec<ChatLine> {
+    let mut lines: Vec<ChatLine> = Vec::new();
+    let mut time = None;
+    for line in body.lines().map(str::trim_end).filter(|line| !line.trim().is_empty()) {
+        match TRANSCRIPT_LINE.captures(line.trim_start()) {
+            Some(captures) => {
+                if let Some(said_at) = captures.get(1) {
+                    time = Some(said_at.as_str().to_string());
+                }
+                lines.push(ChatLine { time: time.clone(), sender: captures[2].trim().to_string(), text: captures[3].to_string() });
+            }
+            // A message that ran over more than one line
+            None => match lines.last_mut() {
+                Some(last) => {
+                    last.text.push('\n');
+                    last.text.push_str(line.trim());
+                }
+                None => return single_line(body, from),
+            },
+        }
+    }
+    if lines.is_empty() {
+        return single_line(body, from);
+    }
+    lines
+}
+
+fn single_line(body: &str, from: &str) -> Vec<ChatLine> {
+    let text = body.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    vec![ChatLine { time: None, sender: from.to_string(), text: text.to_string() }]
+}
@@ -0,0 +1,297 @@
+//! The `--config` file: TOML settings too long or too archive-specific for
+//! the command line, and defaults for the import flags (`mbox2db init`
+//! writes those). Without `--config`, the file at [`Config::default_path`] is read
+//! if there is one.
+//!
+//! ```toml
+//! schema = "full"
+//! fts = true
+//! prefer = "plain"
+//! output = "/home/me/mail/all-mail.db"
+//! if_exists = "append"
+//!
+//! # Dates from an old corporate gateway: "20-Jul-2006 11.39.51 -0400"
+//! date_formats = ["%d-%b-%Y %H.%M.%S %z", "%Y%m%d%H%M%S"]
+//!
+//! # Which headers the headers table (--schema full) stores
+//! [headers]
+//! drop = ["Received", "ARC-*", "DKIM-Signature"]
+//!
+//! # Named profiles, chosen with --profile: their keys replace the ones above
+//! [profile.takeout]
+//! input = "/home/me/Takeout/Mail/All mail Including Spam and Trash.mbox"
+//! output = "/home/me/mail/gmail.db"
+//!
+//! [profile.work-imap]
+//! imap = { server = "imap.example.com", user = "me@example.com", folders = ["INBOX", "Sent"] }
+//! account = "work"
+//! ```
+
+use anyhow::{bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::import::{parse_size, IfExists, OversizedAttachments, Prefer};
+use crate::schema::SchemaProfile;
+
+/// A header name, or a pattern with `*` standing for any run of characters.
+static HEADER_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[!#$%&'*+.^_`|~0-9A-Za-z-]+$").unwrap());
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Extra strftime patterns for Date headers the built-in parsers can't
+    /// read, tried in order after them.
+    pub date_formats: Vec<String>,
+    pub headers: HeaderLists,
+
+    // Used when the matching flag isn't given; switches can only be turned on
+    pub output: Option<PathBuf>,
+    pub if_exists: Option<IfExists>,
+    pub schema: Option<SchemaProfile>,
+    pub include_spam: bool,
+    pub include_trash: bool,
+    pub include_spam_and_trash: bool,
+    pub skip_calendar: bool,
+    pub extract_amounts: bool,
+    pub sanitize_html: bool,
+    pub prefer: Option<Prefer>,
+    pub fts: bool,
+    pub attachments_in_db: bool,
+    /// A size like "10MB", as --max-attachment-size takes
+    pub max_attachment_size: Option<String>,
+    pub oversized_attachments: Option<OversizedAttachments>,
+    pub account: Option<String>,
+
+    // What to import when no mbox is given on the command line, for profiles
+    pub input: Option<PathBuf>,
+    pub imap: Option<ImapSettings>,
+}
+
+/// An IMAP mailbox for a profile to import from. The password or OAuth2
+/// token comes from MBOX2DB_IMAP_PASSWORD or MBOX2DB_IMAP_OAUTH2_TOKEN, as
+/// for `mbox2db imap`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImapSettings {
+    pub server: String,
+    pub port: Option<u16>,
+    pub user: String,
+    /// Default: INBOX
+    #[serde(default)]
+    pub folders: Vec<String>,
+    pub concurrency: Option<u16>,
+}
+
+/// Header names (case-insensitive, `*` wildcards) for the headers table:
+/// with `keep`, only those are stored; `drop` leaves those out.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaderLists {
+    pub keep: Vec<String>,
+    pub drop: Vec<String>,
+}
+
+impl Config {
+    /// Reads the config file, with the keys of `[profile.<name>]` in place of
+    /// the top-level ones when a profile is given.
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+
+        let profiles: BTreeMap<String, toml::Table> = match table.remove("profile") {
+            Some(profiles) => profiles
+                .try_into()
+                .with_context(|| format!("Invalid config file: {} ([profile.<name>] sections must be tables)", path.display()))?,
+            None => BTreeMap::new(),
+        };
+        if let Some(name) = profile {
+            let Some(overrides) = profiles.get(name) else {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                match names[..] {
+                    [] => bail!("{} has no profiles; add a [profile.{}] section", path.display(), name),
+                    _ => bail!("{} has no profile {:?} (it has {})", path.display(), name, names.join(", ")),
+                }
+            };
+            table.extend(overrides.clone());
+        }
+        let config: Config = table
+            .try_into()
+            .with_context(|| format!("Invalid config file: {}{}", path.display(), profile.map(|name| format!(" (profile {})", name)).unwrap_or_default()))?;
+
+        for format in &config.date_formats {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                bail!("Invalid date format in {}: {:?}", path.display(), format);
+            }
+        }
+        if let Some(size) = &config.max_attachment_size {
+            parse_size(size).map_err(|err| anyhow::anyhow!("Invalid max_attachment_size in {}: {}", path.display(), err))?;
+        }
+        for pattern in config.headers.keep.iter().chain(&config.headers.drop) {
+            if !HEADER_PATTERN.is_match(pattern) {
+                bail!("Invalid header name in {}: {:?} (use the name without a colon, and * as a wildcard)", path.display(), pattern);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The config file read when `--config` isn't given:
+    /// `$XDG_CONFIG_HOME/mbox2db/config.toml`, `~/.config/mbox2db/config.toml`,
+    /// or `%APPDATA%\mbox2db\config.toml` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("mbox2db").join("config.toml"))
+    }
+
+    /// `max_attachment_size` in bytes; [`Config::load`] has checked it parses.
+    pub fn max_attachment_size(&self) -> Option<u64> {
+        self.max_attachment_size.as_deref().and_then(|size| parse_size(size).ok())
+    }
+
+    pub fn header_filter(&self) -> HeaderFilter {
+        HeaderFilter { keep: pattern_set(&self.headers.keep), drop: pattern_set(&self.headers.drop) }
+    }
+}
+
+/// The `[headers]` lists, compiled.
+#[derive(Debug, Default)]
+pub struct HeaderFilter {
+    keep: Option<RegexSet>,
+    drop: Option<RegexSet>,
+}
+
+impl HeaderFilter {
+    /// Whether a header called `name` goes in the headers table.
+    pub fn keeps(&self, name: &str) -> bool {
+        self.keep.as_ref().is_none_or(|keep| keep.is_match(name)) && !self.drop.as_ref().is_some_and(|drop| drop.is_match(name))
+    }
+}
+
+fn pattern_set(patterns: &[String]) -> Option<RegexSet> {
+    if patterns.is_empty() {
+        return None;
+    }
+    let regexes = patterns.iter().map(|pattern| format!("^{}$", regex::escape(pattern).replace(r"\*", ".*")));
+    Some(RegexSetBuilder::new(regexes).case_insensitive(true).build().expect("escaped header patterns are valid regexes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The filter for `keep` and `drop` lists, as `[headers]` gives them.
+    fn filter(keep: &[&str], drop: &[&str]) -> HeaderFilter {
+        let strings = |patterns: &[&str]| patterns.iter().map(|pattern| pattern.to_string()).collect();
+        Config { headers: HeaderLists { keep: strings(keep), drop: strings(drop) }, ..Config::default() }.header_filter()
+    }
+
+    #[test]
+    fn header_filters() {
+        // (keep, drop, header name, kept)
+        let cases = [
+            (&[][..], &[][..], "Received", true),
+            (&[], &["Received", "ARC-*"], "received", false),
+            (&[], &["Received", "ARC-*"], "ARC-Seal", false),
+            (&[], &["Received", "ARC-*"], "arc-message-signature", false),
+            (&[], &["Received", "ARC-*"], "X-ARC-Seal", true),
+            (&[], &["Received", "ARC-*"], "Received-SPF", true),
+            (&["Subject", "X-*-Id"], &[], "x-mailer-id", true),
+            (&["Subject", "X-*-Id"], &[], "X-Id", false),
+            (&["Subject", "X-*-Id"], &[], "Subjects", false),
+            (&["List-*"], &["List-Unsubscribe*"], "List-Id", true),
+            (&["List-*"], &["List-Unsubscribe*"], "List-Unsubscribe-Post", false),
+            // Characters that mean something in a regex match only themselves
+            (&["X.Y", "A+B", "C|D", "E^F"], &[], "X.Y", true),
+            (&["X.Y", "A+B", "C|D", "E^F"], &[], "XzY", false),
+            (&["X.Y", "A+B", "C|D", "E^F"], &[], "AAB", false),
+            (&["X.Y", "A+B", "C|D", "E^F"], &[], "C", false),
+            (&["X.Y", "A+B", "C|D", "E^F"], &[], "E^F", true),
+        ];
+        for (keep, drop, name, kept) in cases {
+            assert_eq!(filter(keep, drop).keeps(name), kept, "{:?}", (keep, drop, name));
+        }
+    }
+
+    /// Loads `contents` as a config file, with `profile` applied.
+    fn load(contents: &str, profile: Option<&str>) -> Result<Config> {
+        let path = std::env::temp_dir().join(format!("mbox2db-config-{}-{:?}.toml", std::process::id(), std::thread::current().id()));
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load(&path, profile);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    const PROFILES: &str = r#"
+        fts = true
+        schema = "full"
+        prefer = "plain"
+        date_formats = ["%d-%b-%Y %H.%M.%S %z"]
+
+        [headers]
+        drop = ["Received"]
+
+        [profile.takeout]
+        input = "takeout.mbox"
+        schema = "flat"
+        include_spam_and_trash = true
+        skip_calendar = true
+
+        [profile.work]
+        account = "work"
+        extract_amounts = true
+        sanitize_html = true
+        prefer = "html"
+        headers = { keep = ["Subject"] }
+    "#;
+
+    #[test]
+    fn profiles_replace_top_level_keys() {
+        let base = load(PROFILES, None).unwrap();
+        assert_eq!((base.fts, base.schema, base.prefer, base.input.as_deref()), (true, Some(SchemaProfile::Full), Some(Prefer::Plain), None));
+        assert!(!base.include_spam_and_trash && !base.skip_calendar && !base.extract_amounts && !base.sanitize_html);
+        assert!(!base.header_filter().keeps("Received"));
+
+        let takeout = load(PROFILES, Some("takeout")).unwrap();
+        assert_eq!((takeout.fts, takeout.schema, takeout.prefer), (true, Some(SchemaProfile::Flat), Some(Prefer::Plain)));
+        assert_eq!(takeout.input, Some(PathBuf::from("takeout.mbox")));
+        assert!(takeout.include_spam_and_trash && takeout.skip_calendar);
+        assert_eq!(takeout.date_formats, ["%d-%b-%Y %H.%M.%S %z"]);
+
+        // A table replaces the top-level one as a whole, not key by key
+        let work = load(PROFILES, Some("work")).unwrap();
+        assert_eq!((work.account.as_deref(), work.prefer), (Some("work"), Some(Prefer::Html)));
+        assert!(work.extract_amounts && work.sanitize_html);
+        assert!(work.header_filter().keeps("Subject") && !work.header_filter().keeps("From"));
+        assert!(!work.header_filter().keeps("Received"));
+    }
+
+    #[test]
+    fn config_errors() {
+        let cases = [
+            ("fts = true\nftss = true\n", None, "unknown field `ftss`"),
+            ("[profile.work]\nschemas = \"flat\"\n", Some("work"), "(profile work)"),
+            ("[profile.work]\nschemas = \"flat\"\n", Some("work"), "unknown field `schemas`"),
+            ("[headers]\nkeep = [\"Subject\"]\ndrops = []\n", None, "unknown field `drops`"),
+            ("prefer = \"text\"\n", None, "unknown variant `text`"),
+            ("[profile.work]\naccount = \"work\"\n", Some("home"), "no profile \"home\" (it has work)"),
+            ("fts = true\n", Some("home"), "has no profiles"),
+            ("profile = 1\n", None, "must be tables"),
+            ("[headers]\ndrop = [\"Received:\"]\n", None, "Invalid header name"),
+            ("max_attachment_size = \"10XB\"\n", None, "Invalid max_attachment_size"),
+        ];
+        for (contents, profile, error) in cases {
+            let message = format!("{:#}", load(contents, profile).unwrap_err());
+            assert!(message.contains(error), "{:?}: {}", contents, message);
+        }
+    }
+}
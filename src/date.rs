@@ -0,0 +1,573 @@
+//! Date header parsing: a pipeline of strategies tried in order, from strict
+//! RFC 2822 to a catch-all for the malformed dates real archives are full of.
+//! The name of the strategy that succeeded is stored with every message
+//! (emails.date_strategy), so parse quality can be measured per archive.
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetName, Tz, TZ_VARIANTS};
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+/// "(Eastern Daylight Time)" and other comments.
+static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\(([^)]*)\)?").unwrap());
+static ISO_DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})(?:T|\b)").unwrap());
+/// 7/19/2005: month first, unless the first number can only be a day.
+static SLASH_DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{1,2})/(\d{1,2})/(\d{2,4})\b").unwrap());
+/// 19.07.2005: day first.
+static DOT_DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{1,2})\.(\d{1,2})\.(\d{2,4})\b").unwrap());
+/// 9:47, 21:9:7, 11:39:51.123 PM (the meridiem may be glued to an offset: "PM+0400").
+static TIME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(\d{1,2}):(\d{1,2})(?::(\d{1,2}))?(?:[.,]\d+)?(?:\s*([ap])\.?m\.?\b)?").unwrap());
+/// -0400, --0400, +04:00, -600, GMT-07:00, UTC+2; never the dashes inside "20-Jul-2006".
+static OFFSET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)(?:^|\s)(?:(?:GMT|UTC|UT)\s*)?([+-])[+-]*(\d{1,2}:\d{2}|\d{3,4}|\d{1,2})\b").unwrap());
+static TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Za-z]+|\d+").unwrap());
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Spelled-out zone names some clients write instead of an offset, in minutes east of UTC.
+const ZONE_NAMES: &[(&str, i32)] = &[
+    ("eastern daylight time", -4 * 60),
+    ("eastern standard time", -5 * 60),
+    ("central daylight time", -5 * 60),
+    ("central standard time", -6 * 60),
+    ("mountain daylight time", -6 * 60),
+    ("mountain standard time", -7 * 60),
+    ("pacific daylight time", -7 * 60),
+    ("pacific standard time", -8 * 60),
+];
+
+/// The RFC 822 zones, in minutes east of UTC; everything else is looked up in the tz database.
+const RFC822_ZONES: &[(&str, i32)] = &[
+    ("ut", 0),
+    ("utc", 0),
+    ("gmt", 0),
+    ("z", 0),
+    ("edt", -4 * 60),
+    ("est", -5 * 60),
+    ("cdt", -5 * 60),
+    ("cst", -6 * 60),
+    ("mdt", -6 * 60),
+    ("mst", -7 * 60),
+    ("pdt", -7 * 60),
+    ("pst", -8 * 60),
+];
+
+/// For abbreviations the tz database uses for more than one offset, the zone
+/// they most likely mean in an email.
+const COMMON_ZONES: &[(&str, &str)] = &[
+    ("ist", "Asia/Kolkata"),
+    ("bst", "Europe/London"),
+    ("ast", "America/Halifax"),
+    ("adt", "America/Halifax"),
+    ("sst", "Pacific/Pago_Pago"),
+    ("msk", "Europe/Moscow"),
+];
+
+/// Dates in 1970 or earlier are zeroed Unix timestamps, not real mail.
+const EARLIEST_PLAUSIBLE_YEAR: i32 = 1971;
+/// How far past the import a date may be before it's a broken clock (2038,
+/// the year 12022) rather than a sender a few hours ahead.
+const FUTURE_TOLERANCE_DAYS: i64 = 2;
+
+/// Years sampled when collecting the abbreviations each zone has used.
+const ABBREVIATION_YEARS: Range<i32> = 1970..2038;
+
+/// Every alphabetic abbreviation in the tz database (lowercase), with the zones that have used it.
+static TZ_ABBREVIATIONS: Lazy<HashMap<String, Vec<Tz>>> = Lazy::new(|| {
+    let mut table: HashMap<String, Vec<Tz>> = HashMap::new();
+    for &tz in TZ_VARIANTS.iter() {
+        for year in ABBREVIATION_YEARS {
+            for month in [1, 7] {
+                let instant = NaiveDate::from_ymd_opt(year, month, 15).unwrap().and_time(NaiveTime::MIN);
+                let offset = tz.offset_from_utc_datetime(&instant);
+                // Zones without a real abbreviation use their offset ("+06")
+                let Some(abbreviation) = offset.abbreviation().filter(|a| a.chars().all(|c| c.is_ascii_alphabetic())) else {
+                    continue;
+                };
+                let zones = table.entry(abbreviation.to_lowercase()).or_default();
+                if !zones.contains(&tz) {
+                    zones.push(tz);
+                }
+            }
+        }
+    }
+    table
+});
+
+/// How to read a zone abbreviation that stands for different offsets in
+/// different places, such as IST (India, Ireland or Israel).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum AmbiguousZones {
+    /// Its most common meaning in email: IST is India, BST British Summer Time
+    #[default]
+    Common,
+    /// Keep the time but record no offset
+    Ignore,
+}
+
+/// A date as written in a header.
+#[derive(Debug)]
+pub struct ParsedDate {
+    /// Wall-clock time in the sender's zone.
+    pub local: NaiveDateTime,
+    /// The sender's UTC offset; None when the header had none (ctime-style dates).
+    pub offset: Option<FixedOffset>,
+    /// Name of the strategy that parsed it.
+    pub strategy: &'static str,
+}
+
+impl ParsedDate {
+    /// The time in UTC. Dates without an offset are taken to be UTC already.
+    pub fn utc(&self) -> NaiveDateTime {
+        match self.offset {
+            Some(offset) => self.local - Duration::seconds(offset.local_minus_utc().into()),
+            None => self.local,
+        }
+    }
+
+    /// The SQLite-friendly UTC form stored in date_parsed, so dates from
+    /// different zones sort correctly.
+    pub fn to_sql(&self) -> String {
+        self.utc().format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+
+    /// The offset as stored in date_offset, e.g. "-04:00"; SQLite's
+    /// `datetime(date_parsed, date_offset)` turns it back into local time.
+    pub fn offset_sql(&self) -> Option<String> {
+        self.offset.map(|offset| offset.to_string())
+    }
+
+    /// False for dates no real message has: 1970 and earlier, or in the future.
+    pub fn is_plausible(&self) -> bool {
+        let utc = self.utc();
+        utc.year() >= EARLIEST_PLAUSIBLE_YEAR && utc <= Utc::now().naive_utc() + Duration::days(FUTURE_TOLERANCE_DAYS)
+    }
+
+    /// The wall-clock time in `zone`, as stored in date_display.
+    pub fn display_in(&self, zone: Tz) -> String {
+        zone.from_utc_datetime(&self.utc()).format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+}
+
+/// One way of reading a date header: the local time and, if given, the offset.
+pub trait DateStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn parse(&self, value: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)>;
+}
+
+/// The strategies, tried in order until one parses the value.
+pub struct DateParser {
+    strategies: Vec<Box<dyn DateStrategy>>,
+}
+
+impl DateParser {
+    /// RFC 2822, RFC 3339, `custom_formats` (strftime patterns, e.g.
+    /// "%d-%b-%Y %H.%M.%S %z"), then the lenient catch-all. Custom formats
+    /// come before it because it reads almost anything, if not always right.
+    pub fn new(custom_formats: Vec<String>, ambiguous_zones: AmbiguousZones) -> Self {
+        let mut strategies: Vec<Box<dyn DateStrategy>> = vec![Box::new(Rfc2822), Box::new(Rfc3339)];
+        if !custom_formats.is_empty() {
+            strategies.push(Box::new(CustomFormats(custom_formats)));
+        }
+        strategies.push(Box::new(Lenient { ambiguous_zones }));
+        Self { strategies }
+    }
+
+    pub fn parse(&self, value: &str) -> Option<ParsedDate> {
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        self.strategies.iter().find_map(|strategy| {
+            let (local, offset) = strategy.parse(value)?;
+            Some(ParsedDate { local, offset, strategy: strategy.name() })
+        })
+    }
+
+    /// A message's date and where it came from, as stored in date_source:
+    /// the Date header ("header"), or, when that's implausible, the earliest
+    /// Received header ("received") or the mbox "From " line ("envelope").
+    /// An implausible Date header with nothing better is kept.
+    ///
+    /// Without a readable Date header (common in spam and automated mail)
+    /// the topmost Received header is used instead: it was added by the
+    /// receiving server, where lower ones may be forged.
+    pub fn message_date(&self, date_header: &str, received: &[&str], envelope: Option<&str>) -> Option<(ParsedDate, &'static str)> {
+        let envelope = || envelope.and_then(|line| self.envelope_date(line));
+        let Some(header) = self.parse(date_header) else {
+            let topmost = received.iter().find_map(|value| self.received_date(value));
+            return topmost.map(|date| (date, "received")).or_else(|| envelope().map(|date| (date, "envelope")));
+        };
+        if header.is_plausible() {
+            return Some((header, "header"));
+        }
+        if let Some(received) = self.earliest_received(received) {
+            return Some((received, "received"));
+        }
+        if let Some(envelope) = envelope() {
+            return Some((envelope, "envelope"));
+        }
+        Some((header, "header"))
+    }
+
+    /// The earliest plausible timestamp among Received headers, which each
+    /// end in "; <date>". The bottom one is usually earliest, but relays
+    /// with wrong clocks and reordered headers make the minimum safer.
+    fn earliest_received(&self, received: &[&str]) -> Option<ParsedDate> {
+        received.iter().filter_map(|value| self.received_date(value)).min_by_key(ParsedDate::utc)
+    }
+
+    /// The plausible timestamp a Received header ends with, after its ";".
+    fn received_date(&self, value: &str) -> Option<ParsedDate> {
+        self.parse(value.rsplit_once(';')?.1).filter(ParsedDate::is_plausible)
+    }
+
+    /// The date on an mbox "From sender Thu Jul 20 11:39:51 2006" line.
+    fn envelope_date(&self, line: &str) -> Option<ParsedDate> {
+        let (_, date) = line.strip_prefix("From ")?.trim_start().split_once(char::is_whitespace)?;
+        self.parse(date).filter(ParsedDate::is_plausible)
+    }
+}
+
+impl Default for DateParser {
+    fn default() -> Self {
+        Self::new(Vec::new(), AmbiguousZones::default())
+    }
+}
+
+/// Well-formed Date headers: "Thu, 20 Jul 2006 11:39:51 -0400".
+struct Rfc2822;
+
+impl DateStrategy for Rfc2822 {
+    fn name(&self) -> &'static str {
+        "rfc2822"
+    }
+
+    fn parse(&self, value: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+        let date = DateTime::parse_from_rfc2822(value).ok()?;
+        Some((date.naive_local(), Some(*date.offset())))
+    }
+}
+
+/// ISO timestamps some tools write instead: "2006-07-20T11:39:51-04:00".
+struct Rfc3339;
+
+impl DateStrategy for Rfc3339 {
+    fn name(&self) -> &'static str {
+        "rfc3339"
+    }
+
+    fn parse(&self, value: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+        let date = DateTime::parse_from_rfc3339(value)
+            .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f%:z"))
+            .ok()?;
+        Some((date.naive_local(), Some(*date.offset())))
+    }
+}
+
+/// Picks the parts of a date out of whatever surrounds them, in any order:
+/// weekday and month names in full or abbreviated, a missing comma, one- or
+/// two-digit fields, 12-hour times, two-digit years, numeric dates
+/// (2006-07-20, 7/20/2006, 20.07.2006), offsets like "--0400", "-600" or
+/// "GMT-07:00", zone names and abbreviations, and trailing junk.
+struct Lenient {
+    ambiguous_zones: AmbiguousZones,
+}
+
+impl DateStrategy for Lenient {
+    fn name(&self) -> &'static str {
+        "lenient"
+    }
+
+    fn parse(&self, value: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+        let comments: Vec<String> = COMMENT.captures_iter(value).map(|caps| caps[1].trim().to_lowercase()).collect();
+        let mut text = COMMENT.replace_all(value, " ").into_owned();
+
+        let mut ymd = None;
+        if let Some(caps) = ISO_DATE.captures(&text) {
+            ymd = Some((number(&caps, 1)?, number(&caps, 2)?, number(&caps, 3)?));
+            let matched = caps.get(0).unwrap().range();
+            blank(&mut text, matched);
+        } else if let Some(caps) = SLASH_DATE.captures(&text) {
+            let (first, second, year) = (number(&caps, 1)?, number(&caps, 2)?, number(&caps, 3)?);
+            ymd = Some(if first > 12 { (year, second, first) } else { (year, first, second) });
+            let matched = caps.get(0).unwrap().range();
+            blank(&mut text, matched);
+        } else if let Some(caps) = DOT_DATE.captures(&text) {
+            ymd = Some((number(&caps, 3)?, number(&caps, 2)?, number(&caps, 1)?));
+            let matched = caps.get(0).unwrap().range();
+            blank(&mut text, matched);
+        }
+
+        let mut time = NaiveTime::MIN;
+        if let Some(caps) = TIME.captures(&text) {
+            let mut hour = number(&caps, 1)?;
+            let (minute, second) = (number(&caps, 2)?, caps.get(3).map_or(Some(0), |_| number(&caps, 3))?);
+            if let Some(meridiem) = caps.get(4).filter(|_| hour <= 12) {
+                hour = hour % 12 + if meridiem.as_str().eq_ignore_ascii_case("p") { 12 } else { 0 };
+            }
+            time = NaiveTime::from_hms_opt(hour, minute, second)?;
+            let matched = caps.get(0).unwrap().range();
+            blank(&mut text, matched);
+        }
+
+        let mut offset = None;
+        if let Some(caps) = OFFSET.captures(&text) {
+            offset = numeric_offset(&caps[1], &caps[2]);
+            if offset.is_some() {
+                let matched = caps.get(0).unwrap().range();
+                blank(&mut text, matched);
+            }
+        }
+        let lowered = text.to_lowercase();
+        if offset.is_none() {
+            offset = ZONE_NAMES
+                .iter()
+                .find(|(name, _)| lowered.contains(name) || comments.iter().any(|comment| comment == name))
+                .and_then(|&(_, minutes)| FixedOffset::east_opt(minutes * 60));
+        }
+
+        let (mut month, mut day, mut year) = (None, None, None);
+        let mut zone_words = Vec::new();
+        for token in TOKEN.find_iter(&lowered).map(|m| m.as_str()) {
+            if let Ok(n) = token.parse::<u32>() {
+                if token.len() >= 3 || n > 31 {
+                    year = year.or(Some(n));
+                } else if day.is_none() {
+                    day = Some(n);
+                } else if year.is_none() {
+                    year = Some(n);
+                }
+            } else if let Some(index) = MONTHS.iter().position(|name| token.len() >= 3 && token.starts_with(name)) {
+                month = month.or(Some(index as u32 + 1));
+            } else {
+                zone_words.push(token);
+            }
+        }
+
+        let (year, month, day) = match ymd {
+            Some(ymd) => ymd,
+            None => (year?, month?, day?),
+        };
+        let local = NaiveDate::from_ymd_opt(full_year(year) as i32, month, day)?.and_time(time);
+        if offset.is_none() {
+            offset = zone_words
+                .into_iter()
+                .chain(comments.iter().map(String::as_str))
+                .find_map(|word| zone_abbreviation(word, local, self.ambiguous_zones));
+        }
+        Some((local, offset))
+    }
+}
+
+/// Extra strftime patterns from the user (the config file's date_formats),
+/// for formats the lenient parser misreads.
+/// Patterns without %z give dates without an offset; patterns without a time
+/// give midnight.
+struct CustomFormats(Vec<String>);
+
+impl DateStrategy for CustomFormats {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn parse(&self, value: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+        self.0.iter().find_map(|format| {
+            if let Ok(date) = DateTime::parse_from_str(value, format) {
+                return Some((date.naive_local(), Some(*date.offset())));
+            }
+            if let Ok(local) = NaiveDateTime::parse_from_str(value, format) {
+                return Some((local, None));
+            }
+            let date = NaiveDate::parse_from_str(value, format).ok()?;
+            Some((date.and_time(NaiveTime::MIN), None))
+        })
+    }
+}
+
+fn number(caps: &Captures, group: usize) -> Option<u32> {
+    caps.get(group)?.as_str().parse().ok()
+}
+
+/// Removes a matched part so later patterns don't read its digits again.
+fn blank(text: &mut String, range: Range<usize>) {
+    text.replace_range(range.clone(), &" ".repeat(range.len()));
+}
+
+/// "0400", "400", "04:00" or "4" hours, with the given sign.
+fn numeric_offset(sign: &str, digits: &str) -> Option<FixedOffset> {
+    let digits = digits.replace(':', "");
+    let (hours, minutes): (i32, i32) = match digits.len() {
+        1 | 2 => (digits.parse().ok()?, 0),
+        _ => (digits[..digits.len() - 2].parse().ok()?, digits[digits.len() - 2..].parse().ok()?),
+    };
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    let seconds = (hours * 60 + minutes) * 60;
+    FixedOffset::east_opt(if sign == "-" { -seconds } else { seconds })
+}
+
+/// The offset `abbreviation` ("jst", "aest", "cest") stood for at `local`.
+fn zone_abbreviation(abbreviation: &str, local: NaiveDateTime, ambiguous: AmbiguousZones) -> Option<FixedOffset> {
+    if let Some(&(_, minutes)) = RFC822_ZONES.iter().find(|(name, _)| *name == abbreviation) {
+        return FixedOffset::east_opt(minutes * 60);
+    }
+    let zones = TZ_ABBREVIATIONS.get(abbreviation)?;
+
+    // Offsets (in seconds) meant by the abbreviation, with the zones meaning each
+    let mut offsets: BTreeMap<i32, Vec<Tz>> = BTreeMap::new();
+    let add = |offsets: &mut BTreeMap<i32, Vec<Tz>>, tz: Tz, offset: <Tz as TimeZone>::Offset| {
+        if offset.abbreviation().is_some_and(|name| name.eq_ignore_ascii_case(abbreviation)) {
+            let zones = offsets.entry(offset.fix().local_minus_utc()).or_default();
+            if !zones.contains(&tz) {
+                zones.push(tz);
+            }
+        }
+    };
+    // The zones that used it at that date (MSK was +04 in 2012)...
+    for &tz in zones {
+        if let Some(offset) = tz.offset_from_local_datetime(&local).earliest() {
+            add(&mut offsets, tz, offset);
+        }
+    }
+    // ...or, when none did (a summer abbreviation in winter), at any date
+    if offsets.is_empty() {
+        for &tz in zones {
+            for year in ABBREVIATION_YEARS {
+                for month in [1, 7] {
+                    let instant = NaiveDate::from_ymd_opt(year, month, 15).unwrap().and_time(NaiveTime::MIN);
+                    add(&mut offsets, tz, tz.offset_from_utc_datetime(&instant));
+                }
+            }
+        }
+    }
+
+    let seconds = if offsets.len() == 1 {
+        *offsets.keys().next()?
+    } else {
+        match ambiguous {
+            AmbiguousZones::Ignore => return None,
+            AmbiguousZones::Common => {
+                let common = COMMON_ZONES.iter().find(|(name, _)| *name == abbreviation).and_then(|(_, zone)| zone.parse::<Tz>().ok());
+                match common.and_then(|common| offsets.iter().find(|(_, zones)| zones.contains(&common))) {
+                    Some((&seconds, _)) => seconds,
+                    // Otherwise the offset most zones meant by it
+                    None => *offsets.iter().max_by_key(|(_, zones)| zones.len())?.0,
+                }
+            }
+        }
+    };
+    FixedOffset::east_opt(seconds)
+}
+
+/// Two-digit years: 51-99 are the 1900s, 00-50 the 2000s. Three-digit years
+/// are years since 1900, from clients with a Y2K bug ("106" is 2006).
+fn full_year(year: u32) -> u32 {
+    match year {
+        0..=50 => 2000 + year,
+        51..=999 => 1900 + year,
+        _ => year,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `value` as parsed by the default pipeline: UTC, offset and strategy.
+    fn parse(value: &str) -> Option<(String, Option<String>, &'static str)> {
+        let date = DateParser::default().parse(value)?;
+        Some((date.to_sql(), date.offset_sql(), date.strategy))
+    }
+
+    #[test]
+    fn malformed_dates_parse() {
+        let cases = [
+            // Garbage after the offset
+            ("Thu, 20 Jul 2006 11:39:51 +0000.395-508222", "2006-07-20 11:39:51", Some("+00:00")),
+            // Full and nonstandard day and month names, missing comma
+            ("Thurs, 20 Jul 2006 11:39:51 -0400", "2006-07-20 15:39:51", Some("-04:00")),
+            ("Thursday, 20 July 2006 11:39:51 -0400", "2006-07-20 15:39:51", Some("-04:00")),
+            ("Thu 20 Jul 2006 11:39:51 -0400", "2006-07-20 15:39:51", Some("-04:00")),
+            // Two-digit (and Y2K-bug three-digit) years
+            ("Thu, 11 Jun 09 10:00:00 +0000", "2009-06-11 10:00:00", Some("+00:00")),
+            ("Sat, 21 Jan 99 10:00:00 +0000", "1999-01-21 10:00:00", Some("+00:00")),
+            ("Thu, 20 Jul 106 11:39:51 +0000", "2006-07-20 11:39:51", Some("+00:00")),
+            // ctime, with no offset
+            ("Thu Jul 20 11:39:51 2006", "2006-07-20 11:39:51", None),
+            // Numeric dates and 12-hour times
+            ("7/19/2005 8:11:52 AM", "2005-07-19 08:11:52", None),
+            ("7/19/2005 8:11:52 PM", "2005-07-19 20:11:52", None),
+            ("19.07.2005 20:11", "2005-07-19 20:11:00", None),
+            ("Thu, 20 Jul 2006 11:39:51 PM+0400", "2006-07-20 19:39:51", Some("+04:00")),
+            // Single-digit fields
+            ("Thu, 20 Jul 2006 9:47:1 +0000", "2006-07-20 09:47:01", Some("+00:00")),
+            // Odd offsets
+            ("Thu, 20 Jul 2006 11:39:51 GMT-07:00", "2006-07-20 18:39:51", Some("-07:00")),
+            ("Thu, 20 Jul 2006 11:39:51 --0400", "2006-07-20 15:39:51", Some("-04:00")),
+            ("Thu, 20 Jul 2006 11:39:51 -600", "2006-07-20 17:39:51", Some("-06:00")),
+            // Zone names and abbreviations
+            ("Thu, 20 Jul 2006 11:39:51 (Eastern Daylight Time)", "2006-07-20 15:39:51", Some("-04:00")),
+            ("Thu, 20 Jul 2006 11:39:51 Pacific Standard Time", "2006-07-20 19:39:51", Some("-08:00")),
+            ("Thu, 20 Jul 2006 11:39:51 JST", "2006-07-20 02:39:51", Some("+09:00")),
+            ("Thu, 20 Jul 2006 11:39:51 CEST", "2006-07-20 09:39:51", Some("+02:00")),
+        ];
+        for (value, utc, offset) in cases {
+            let (parsed, parsed_offset, _) = parse(value).unwrap_or_else(|| panic!("{:?} didn't parse", value));
+            assert_eq!((parsed.as_str(), parsed_offset.as_deref()), (utc, offset), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn strategies_are_recorded() {
+        assert_eq!(parse("Thu, 20 Jul 2006 11:39:51 -0400").unwrap().2, "rfc2822");
+        assert_eq!(parse("2006-07-20T11:39:51-04:00").unwrap().2, "rfc3339");
+        assert_eq!(parse("Thu Jul 20 11:39:51 2006").unwrap().2, "lenient");
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("not a date"), None);
+    }
+
+    #[test]
+    fn custom_formats_come_before_lenient() {
+        let parser = DateParser::new(vec!["%d-%b-%Y %H.%M.%S %z".to_string()], AmbiguousZones::Common);
+        let date = parser.parse("20-Jul-2006 11.39.51 -0400").unwrap();
+        assert_eq!((date.to_sql().as_str(), date.strategy), ("2006-07-20 15:39:51", "custom"));
+    }
+
+    #[test]
+    fn ambiguous_abbreviations() {
+        let value = "Thu, 20 Jul 2006 11:39:51 IST";
+        let common = DateParser::new(Vec::new(), AmbiguousZones::Common).parse(value).unwrap();
+        assert_eq!(common.offset_sql().as_deref(), Some("+05:30"));
+        let ignored = DateParser::new(Vec::new(), AmbiguousZones::Ignore).parse(value).unwrap();
+        assert_eq!((ignored.to_sql().as_str(), ignored.offset), ("2006-07-20 11:39:51", None));
+    }
+
+    #[test]
+    fn message_date_falls_back() {
+        let parser = DateParser::default();
+        let received = ["by mx.example.com; Fri, 21 Jul 2006 09:00:00 +0000", "from relay; Thu, 20 Jul 2006 12:00:00 +0000"];
+        let envelope = Some("From sender@example.com Thu Jul 20 11:00:00 2006");
+        let source = |header: &str, received: &[&str], envelope: Option<&str>| {
+            parser.message_date(header, received, envelope).map(|(date, source)| (date.to_sql(), source))
+        };
+
+        let header = "Thu, 20 Jul 2006 11:39:51 +0000";
+        assert_eq!(source(header, &received, envelope), Some(("2006-07-20 11:39:51".to_string(), "header")));
+        // No Date header: the topmost Received header
+        assert_eq!(source("", &received, envelope), Some(("2006-07-21 09:00:00".to_string(), "received")));
+        // An implausible one: the earliest Received header, then the envelope
+        let zeroed = "Thu, 01 Jan 1970 00:00:00 +0000";
+        assert_eq!(source(zeroed, &received, envelope), Some(("2006-07-20 12:00:00".to_string(), "received")));
+        assert_eq!(source(zeroed, &[], envelope), Some(("2006-07-20 11:00:00".to_string(), "envelope")));
+        // ...and kept when there's nothing better
+        assert_eq!(source(zeroed, &[], None), Some(("1970-01-01 00:00:00".to_string(), "header")));
+        assert_eq!(source("", &[], None), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Message embeddings from an OpenAI-compatible `/embeddings` API (`--embed`),
+//! stored in `vectors` for similarity search.
+//!
+//! Each message's subject and clean body are embedded in batches. Vectors are
+//! keyed by a hash of that text, so duplicate messages (the same newsletter
+//! in two folders) reuse one another's vector instead of costing a request,
+//! and messages that already have a vector from the same model are skipped:
+//! an interrupted run carries on where it stopped.
+
+use anyhow::{bail, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::import;
+use crate::llm::Endpoint;
+use crate::schema::{self, Tables};
+
+pub const DEFAULT_URL: &str = "http://localhost:11434/v1";
+
+/// Longest text embedded per message, in characters; most embedding models
+/// stop reading after a few thousand tokens anyway.
+const MAX_TEXT_CHARS: usize = 8_000;
+
+pub struct Embedder {
+    endpoint: Endpoint,
+    model: String,
+    batch_size: usize,
+}
+
+#[derive(Default)]
+pub struct EmbeddingCounts {
+    pub embedded: usize,
+    /// Messages whose text already had a vector, from a duplicate
+    pub cached: usize,
+    /// Messages left for the next run after a Ctrl-C
+    pub remaining: usize,
+}
+
+impl Embedder {
+    /// `base_url` is the API root, e.g. "https://api.openai.com/v1";
+    /// `/embeddings` is appended.
+    pub fn new(base_url: &str, model: &str, api_key: Option<String>, batch_size: usize, per_minute: u32) -> Result<Self> {
+        let url = format!("{}/embeddings", base_url.trim_end_matches('/'));
+        Ok(Self { endpoint: Endpoint::new(&url, api_key, per_minute)?, model: model.to_string(), batch_size: batch_size.max(1) })
+    }
+
+    /// One vector per text, in order.
+    fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let reply = match self.endpoint.post(&json!({ "model": self.model, "input": texts }))? {
+            Ok(reply) => reply,
+            Err(refused) => bail!("{} refused a batch (HTTP {}): {}", self.endpoint.url, refused.status, refused.message),
+        };
+        let data = reply["data"].as_array().context("The embeddings response had no data array")?;
+        if data.len() != texts.len() {
+            bail!("Asked {} for {} embeddings and got {}", self.endpoint.url, texts.len(), data.len());
+        }
+
+        let mut vectors = vec![Vec::new(); texts.len()];
+        for (position, item) in data.iter().enumerate() {
+            // Results carry their input's index, and needn't come back in order
+            let index = item["index"].as_u64().map_or(position, |index| index as usize);
+            let vector = item["embedding"]
+                .as_array()
+                .context("An embedding in the response wasn't an array of numbers")?
+                .iter()
+                .map(|value| value.as_f64().map(|value| value as f32))
+                .collect::<Option<Vec<f32>>>()
+                .context("An embedding in the response wasn't an array of numbers")?;
+            *vectors.get_mut(index).context("An embedding in the response had an out-of-range index")? = vector;
+        }
+        Ok(vectors)
+    }
+}
+
+/// What's embedded for a message: its subject, then its body without quoted
+/// replies or signature.
+fn message_text(subject: &str, body: &str) -> String {
+    let mut text = format!("{}\n\n{}", subject.trim(), body.trim());
+    if text.len() > MAX_TEXT_CHARS {
+        let mut end = MAX_TEXT_CHARS;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+/// A vector as SQLite stores it: little-endian float32s, the layout
+/// sqlite-vec reads as well.
+pub fn vector_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// Creates `vectors` if it isn't there yet, with one row per message and model.
+pub fn create_vectors_table(conn: &Connection, tables: &Tables) -> Result<()> {
+    let create = |name: &str| {
+        format!(
+            "CREATE TABLE {name} (
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                model TEXT NOT NULL,
+                dimensions INTEGER NOT NULL,
+                content_hash TEXT NOT NULL,
+                embedding BLOB NOT NULL,
+                PRIMARY KEY (email_id, model)
+            )",
+            emails = tables.emails,
+        )
+    };
+    let model_in_key: Option<bool> = conn
+        .query_row("SELECT pk > 0 FROM pragma_table_info(?1) WHERE name = 'model'", [&tables.vectors], |row| row.get(0))
+        .optional()?;
+    match model_in_key {
+        None => {
+            conn.execute(&create(&tables.vectors), [])?;
+        }
+        // Keyed by email_id alone before, so a second model replaced the first's vectors
+        Some(false) => {
+            let rebuilt = format!("{}_rebuilt", tables.vectors);
+            conn.execute_batch(&format!(
+                "{create};
+                 INSERT INTO {rebuilt} SELECT email_id, model, dimensions, content_hash, embedding FROM {vectors};
+                 DROP TABLE {vectors};
+                 ALTER TABLE {rebuilt} RENAME TO {vectors};",
+                create = create(&rebuilt),
+                vectors = tables.vectors,
+            ))?;
+        }
+        Some(true) => {}
+    }
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(content_hash, model)", tables.index("vectors_content_hash"), tables.vectors),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Embeds every message in `database` that has no vector from this model yet.
+pub fn embed_messages(database: &Path, tables: &Tables, embedder: &Embedder) -> Result<EmbeddingCounts> {
+    let mut conn = Connection::open(database).with_context(|| format!("Failed to open database: {}", database.display()))?;
+    conn.busy_timeout(Duration::from_secs(30))?;
+    create_vectors_table(&conn, tables)?;
+
+    let pending: Vec<(i64, String)> = conn
+        .prepare(&format!(
+            "SELECT e.id, COALESCE(e.subject, ''), COALESCE(NULLIF(e.body_clean, ''), e.body_plain, '')
+             FROM {emails} e LEFT JOIN {vectors} v ON v.email_id = e.id AND v.model = ?1
+             WHERE v.email_id IS NULL
+             ORDER BY e.id",
+            emails = tables.emails,
+            vectors = tables.vectors,
+        ))?
+        .query_map([&embedder.model], |row| {
+            let (id, subject, body): (i64, String, String) = (row.get(0)?, row.get(1)?, row.get(2)?);
+            Ok((id, message_text(&subject, &body)))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let progress = ProgressBar::new(pending.len() as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} messages embedded ({eta})")
+            .unwrap(),
+    );
+
+    let mut counts = EmbeddingCounts::default();
+    for (done, batch) in pending.chunks(embedder.batch_size).enumerate() {
+        if import::interrupted() {
+            counts.remaining = pending.len() - done * embedder.batch_size;
+            break;
+        }
+
+        let hashes: Vec<String> = batch.iter().map(|(_, text)| format!("{:x}", Sha256::digest(text.as_bytes()))).collect();
+        let mut known: HashMap<String, (i64, Vec<u8>)> = HashMap::new();
+        for hash in &hashes {
+            if known.contains_key(hash) {
+                continue;
+            }
+            let cached = conn
+                .query_row(
+                    &format!("SELECT dimensions, embedding FROM {} WHERE content_hash = ?1 AND model = ?2 LIMIT 1", tables.vectors),
+                    params![hash, &embedder.model],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            if let Some(cached) = cached {
+                known.insert(hash.clone(), cached);
+            }
+        }
+
+        // Each distinct text not seen before is sent once
+        let mut fresh: HashSet<&str> = HashSet::new();
+        let mut to_send: Vec<(&str, &str)> = Vec::new();
+        for ((_, text), hash) in batch.iter().zip(&hashes) {
+            if !known.contains_key(hash) && fresh.insert(hash) {
+                to_send.push((hash, text));
+            }
+        }
+        if !to_send.is_empty() {
+            let texts: Vec<&str> = to_send.iter().map(|(_, text)| *text).collect();
+            let vectors = embedder.embed(&texts).with_context(|| {
+                format!("Embedding stopped after {} messages; run again to carry on from there", counts.embedded + counts.cached)
+            })?;
+            for ((hash, _), vector) in to_send.iter().zip(vectors) {
+                known.insert(hash.to_string(), (vector.len() as i64, vector_blob(&vector)));
+            }
+        }
+
+        let tx = conn.transaction()?;
+        for ((email_id, _), hash) in batch.iter().zip(&hashes) {
+            let (dimensions, embedding) = &known[hash];
+            tx.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (email_id, model, dimensions, content_hash, embedding) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    tables.vectors
+                ),
+                params![email_id, &embedder.model, dimensions, hash, embedding],
+            )?;
+            // The first message with a new text is the one it was embedded for
+            if fresh.remove(hash.as_str()) {
+                counts.embedded += 1;
+            } else {
+                counts.cached += 1;
+            }
+        }
+        tx.commit()?;
+        progress.inc(batch.len() as u64);
+    }
+    progress.finish_and_clear();
+    Ok(counts)
+}
+
+/// Rebuilds `vec_emails`, a sqlite-vec `vec0` table holding this model's
+/// vectors keyed by email id, so nearest-neighbor queries run in SQL:
+/// `WHERE embedding MATCH ? AND k = 10`. `extensions` must include sqlite-vec.
+/// Returns the number of vectors indexed.
+pub fn build_vec_index(database: &Path, tables: &Tables, model: &str, extensions: &[PathBuf]) -> Result<usize> {
+    let mut conn = Connection::open(database).with_context(|| format!("Failed to open database: {}", database.display()))?;
+    conn.busy_timeout(Duration::from_secs(30))?;
+    schema::load_extensions(&conn, extensions)?;
+    if conn.query_row("SELECT vec_version()", [], |row| row.get::<_, String>(0)).is_err() {
+        bail!("--vec-index needs the sqlite-vec extension; pass --load-extension with the path to vec0");
+    }
+
+    let dimensions: Vec<i64> = conn
+        .prepare(&format!("SELECT DISTINCT dimensions FROM {} WHERE model = ?1", tables.vectors))?
+        .query_map([model], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    let dimensions = match dimensions[..] {
+        [] => return Ok(0),
+        [dimensions] => dimensions,
+        _ => bail!("{} returned vectors of different lengths ({:?}); sqlite-vec needs one", model, dimensions),
+    };
+
+    // Rebuilt from scratch: cheap next to embedding, and never out of step with vectors
+    let tx = conn.transaction()?;
+    tx.execute(&format!("DROP TABLE IF EXISTS {}", tables.vec_emails), [])?;
+    tx.execute(
+        &format!(
+            "CREATE VIRTUAL TABLE {} USING vec0(email_id INTEGER PRIMARY KEY, embedding float[{}] distance_metric=cosine)",
+            tables.vec_emails, dimensions
+        ),
+        [],
+    )?;
+    let indexed = tx.execute(
+        &format!(
+            "INSERT INTO {} (email_id, embedding) SELECT email_id, embedding FROM {} WHERE model = ?1",
+            tables.vec_emails, tables.vectors
+        ),
+        [model],
+    )?;
+    tx.commit()?;
+    Ok(indexed)
+}
@@ -0,0 +1,436 @@
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::Connection;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::report::csv_field;
+use crate::schema::{self, Tables};
+
+static ADDRESS: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^\s<>,]+@[^\s<>,]+").unwrap());
+
+/// File written for messages that have no label at all.
+const UNLABELED: &str = "Unlabeled";
+
+/// Writes one mbox per label into `dir`, e.g. `Inbox.mbox`, `Work.mbox`, with
+/// every message that carries the label (so a message with two labels lands in
+/// both files). Needs the raw messages, i.e. a database imported with
+/// `--schema full`. Returns each file written with its message count.
+pub fn export_label_mboxes(conn: &Connection, tables: &Tables, dir: &Path) -> Result<Vec<(PathBuf, usize)>> {
+    if !schema::table_exists(conn, &tables.raw_messages)? {
+        bail!("The database has no raw messages to export; import it with --schema full");
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let mut labels: Vec<Option<String>> = conn
+        .prepare(&format!("SELECT DISTINCT label FROM {} ORDER BY label", tables.labels))?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    labels.push(None);
+
+    let mut written = Vec::new();
+    for label in labels {
+        let filter = match label {
+            Some(_) => format!("e.id IN (SELECT email_id FROM {} WHERE label = ?1)", tables.labels),
+            None => format!("e.id NOT IN (SELECT email_id FROM {})", tables.labels),
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT r.raw, COALESCE(e.from_addr, ''), e.date_parsed
+             FROM {emails} e JOIN {raw_messages} r ON r.email_id = e.id
+             WHERE {filter}
+             ORDER BY e.date_parsed, e.id",
+            emails = tables.emails,
+            raw_messages = tables.raw_messages,
+            filter = filter,
+        ))?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(&label))?;
+
+        let path = dir.join(format!("{}.mbox", file_name(label.as_deref().unwrap_or(UNLABELED))));
+        let mut out: Option<BufWriter<File>> = None;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let raw: Vec<u8> = row.get(0)?;
+            let from: String = row.get(1)?;
+            let date: Option<String> = row.get(2)?;
+
+            // Created on the first message, so an empty Unlabeled.mbox isn't left behind
+            let out = match &mut out {
+                Some(out) => out,
+                None => out.insert(BufWriter::new(
+                    File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?,
+                )),
+            };
+            write_message(out, &raw, &from, date.as_deref())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            count += 1;
+        }
+
+        if let Some(mut out) = out {
+            out.flush()?;
+            written.push((path, count));
+        }
+    }
+
+    Ok(written)
+}
+
+/// Appends one message in mboxrd format: a "From " separator line, the
+/// message with body lines that look like separators quoted with '>', and a
+/// trailing blank line. Messages read from an mbox keep their ">From " lines
+/// as they were, already quoted, so only bare "From " lines get a '>'.
+fn write_message(out: &mut impl Write, raw: &[u8], from: &str, date: Option<&str>) -> std::io::Result<()> {
+    // Messages imported from an mbox still start with their original separator
+    let body = if raw.starts_with(b"From ") {
+        let end = raw.iter().position(|&b| b == b'\n').map_or(raw.len(), |i| i + 1);
+        out.write_all(&raw[..end])?;
+        &raw[end..]
+    } else {
+        writeln!(out, "From {} {}", envelope_sender(from), asctime(date))?;
+        raw
+    };
+
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b"From ") {
+            out.write_all(b">")?;
+        }
+        out.write_all(line)?;
+    }
+    if !body.ends_with(b"\n") {
+        out.write_all(b"\n")?;
+    }
+    // Messages split out of an mbox usually kept the blank line before the next separator
+    if body.ends_with(b"\n\n") || body.ends_with(b"\r\n\r\n") {
+        return Ok(());
+    }
+    out.write_all(b"\n")
+}
+
+fn envelope_sender(from: &str) -> &str {
+    ADDRESS.find(from).map_or("MAILER-DAEMON", |address| address.as_str())
+}
+
+/// The date in the asctime() form mbox separator lines use.
+fn asctime(date_parsed: Option<&str>) -> String {
+    date_parsed
+        .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok())
+        .unwrap_or_default()
+        .format("%a %b %e %H:%M:%S %Y")
+        .to_string()
+}
+
+/// A label as a file name: path separators and characters Windows rejects become '_'.
+fn file_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    match name.trim_matches(|c: char| c == '.' || c.is_whitespace()) {
+        "" => "_".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// One row per attachment with a header row
+    Csv,
+    /// An array of objects
+    Json,
+}
+
+/// Writes every attachment's filename, type, size and SHA-256 with the message
+/// carrying it, e.g. for a malware scanner or dedup tool. Returns the number of
+/// attachments listed.
+pub fn write_attachment_manifest(conn: &Connection, tables: &Tables, out: &mut impl Write, format: ManifestFormat) -> Result<usize> {
+    if !schema::table_exists(conn, &tables.attachments)? {
+        bail!("The database has no attachments table; import it with --schema normalized or full");
+    }
+    if !schema::column_exists(conn, &tables.attachments, "sha256")? {
+        bail!("The attachments table has no sha256 column; it was imported by an older mbox2db, import it again");
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT a.sha256, a.size, a.filename, a.content_type, e.id, e.message_id, e.date_parsed, e.from_addr, e.subject
+         FROM {attachments} a JOIN {emails} e ON e.id = a.email_id
+         ORDER BY e.date_parsed, e.id, a.id",
+        attachments = tables.attachments,
+        emails = tables.emails,
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    const COLUMNS: [&str; 9] = ["sha256", "size", "filename", "content_type", "email_id", "message_id", "date", "from", "subject"];
+    match format {
+        ManifestFormat::Csv => writeln!(out, "{}", COLUMNS.join(","))?,
+        ManifestFormat::Json => write!(out, "[")?,
+    }
+
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let text = |index: usize| -> rusqlite::Result<String> { Ok(row.get::<_, Option<String>>(index)?.unwrap_or_default()) };
+        let (size, email_id): (i64, i64) = (row.get(1)?, row.get(4)?);
+        match format {
+            ManifestFormat::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                text(0)?,
+                size,
+                csv_field(&text(2)?),
+                csv_field(&text(3)?),
+                email_id,
+                csv_field(&text(5)?),
+                text(6)?,
+                csv_field(&text(7)?),
+                csv_field(&text(8)?),
+            )?,
+            ManifestFormat::Json => {
+                let entry = serde_json::json!({
+                    "sha256": row.get::<_, Option<String>>(0)?,
+                    "size": size,
+                    "filename": row.get::<_, Option<String>>(2)?,
+                    "content_type": row.get::<_, Option<String>>(3)?,
+                    "email_id": email_id,
+                    "message_id": row.get::<_, Option<String>>(5)?,
+                    "date": row.get::<_, Option<String>>(6)?,
+                    "from": row.get::<_, Option<String>>(7)?,
+                    "subject": row.get::<_, Option<String>>(8)?,
+                });
+                write!(out, "{}\n  {}", if count == 0 { "" } else { "," }, entry)?;
+            }
+        }
+        count += 1;
+    }
+
+    if format == ManifestFormat::Json {
+        writeln!(out, "{}]", if count == 0 { "" } else { "\n" })?;
+    }
+    Ok(count)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One mboxrd file per label, into --dir
+    Mbox,
+    /// NDJSON for Elasticsearch's or OpenSearch's _bulk API: an index action before each document
+    EsBulk,
+    /// NDJSON documents for Meilisearch's documents API
+    Meilisearch,
+    /// A Xapian database in --dir, one document per Message-ID
+    #[cfg(feature = "xapian")]
+    Xapian,
+}
+
+/// Writes every message as a search document (id, message_id, subject, from,
+/// date, clean body and labels), one JSON object per line, ready to POST to a
+/// search cluster. Returns the number of documents written.
+pub fn write_search_documents(conn: &Connection, tables: &Tables, out: &mut impl Write, format: ExportFormat) -> Result<usize> {
+    let mut labels = if schema::table_exists(conn, &tables.labels)? {
+        Some(conn.prepare(&format!("SELECT label FROM {} WHERE email_id = ?1 ORDER BY id", tables.labels))?)
+    } else {
+        None
+    };
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, message_id, subject, from_addr, date_parsed, COALESCE(NULLIF(body_clean, ''), body_plain)
+         FROM {} ORDER BY id",
+        tables.emails,
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let date = row
+            .get::<_, Option<String>>(4)?
+            .and_then(|date| NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").ok());
+        let labels: Vec<String> = match &mut labels {
+            Some(stmt) => stmt.query_map([id], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?,
+            None => Vec::new(),
+        };
+
+        let mut document = serde_json::json!({
+            "id": id,
+            "message_id": row.get::<_, Option<String>>(1)?,
+            "subject": row.get::<_, Option<String>>(2)?,
+            "from": row.get::<_, Option<String>>(3)?,
+            // date_parsed is UTC; RFC 3339 is what Elasticsearch's date fields read without a mapping
+            "date": date.map(|date| date.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            "body": row.get::<_, Option<String>>(5)?,
+            "labels": labels,
+        });
+        match format {
+            ExportFormat::EsBulk => writeln!(out, "{}", serde_json::json!({ "index": { "_id": id.to_string() } }))?,
+            // Meilisearch sorts and filters by numbers only, so the date is also given as a Unix timestamp
+            ExportFormat::Meilisearch => {
+                document["timestamp"] = date.map(|date| date.and_utc().timestamp()).into();
+            }
+            ExportFormat::Mbox => bail!("mbox isn't a search document format"),
+            #[cfg(feature = "xapian")]
+            ExportFormat::Xapian => bail!("xapian isn't a search document format"),
+        }
+        writeln!(out, "{}", document)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// How scriptindex turns each record into a Xapian document. Terms use
+/// Omega's prefixes (S subject, A author, K label, Q unique id, D/M/Y dates),
+/// so Omega's query parser and `quest` understand them.
+#[cfg(feature = "xapian")]
+const XAPIAN_SCRIPT: &str = "\
+messageid : field hash boolean=Q unique=Q
+emailid : field
+subject : field index=S index
+from : field index=A index
+to : index=XTO index
+date : field
+timestamp : date=unix valuenumeric=0
+label : field boolean=K
+body : index truncate=300 field=sample
+";
+
+/// Indexes every message into the Xapian database at `dir` (created if
+/// missing) by piping records to the `scriptindex` binary. Documents are keyed
+/// by Message-ID, so exporting again updates them in place. Returns the number
+/// of messages indexed.
+#[cfg(feature = "xapian")]
+pub fn write_xapian_index(conn: &Connection, tables: &Tables, dir: &Path) -> Result<usize> {
+    use std::process::{Command, Stdio};
+
+    let script = std::env::temp_dir().join(format!("mbox2db-{}.index", std::process::id()));
+    std::fs::write(&script, XAPIAN_SCRIPT).with_context(|| format!("Failed to write {}", script.display()))?;
+    let child = Command::new("scriptindex").arg("--quiet").arg(dir).arg(&script).stdin(Stdio::piped()).spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let _ = std::fs::remove_file(&script);
+            return Err(err).context("Failed to run scriptindex; install xapian-omega (e.g. apt install xapian-omega)");
+        }
+    };
+
+    let mut stdin = BufWriter::new(child.stdin.take().context("scriptindex has no stdin")?);
+    let written = write_xapian_records(conn, tables, &mut stdin);
+    // Closing stdin tells scriptindex the input is complete
+    drop(stdin);
+    let status = child.wait();
+    let _ = std::fs::remove_file(&script);
+    if !status.context("Failed to wait for scriptindex")?.success() {
+        bail!("scriptindex failed to index into {}", dir.display());
+    }
+    written
+}
+
+/// Writes scriptindex's input format: `field=value` lines, continuation lines
+/// starting with '=', and a blank line after each record.
+#[cfg(feature = "xapian")]
+fn write_xapian_records(conn: &Connection, tables: &Tables, out: &mut impl Write) -> Result<usize> {
+    let mut labels = if schema::table_exists(conn, &tables.labels)? {
+        Some(conn.prepare(&format!("SELECT label FROM {} WHERE email_id = ?1 ORDER BY id", tables.labels))?)
+    } else {
+        None
+    };
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, message_id, subject, from_addr, to_addr, date_parsed, COALESCE(NULLIF(body_clean, ''), body_plain)
+         FROM {} ORDER BY id",
+        tables.emails,
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let text = |index: usize| -> rusqlite::Result<String> { Ok(row.get::<_, Option<String>>(index)?.unwrap_or_default()) };
+        // Messages without a Message-ID still need a key of their own
+        let message_id = match text(1)?.trim().trim_start_matches('<').trim_end_matches('>') {
+            "" => format!("mbox2db-{}", id),
+            message_id => message_id.to_string(),
+        };
+        let date = text(5)?;
+
+        write_xapian_field(out, "messageid", &message_id)?;
+        write_xapian_field(out, "emailid", &id.to_string())?;
+        write_xapian_field(out, "subject", &text(2)?)?;
+        write_xapian_field(out, "from", &text(3)?)?;
+        write_xapian_field(out, "to", &text(4)?)?;
+        write_xapian_field(out, "date", &date)?;
+        if let Ok(date) = NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S") {
+            write_xapian_field(out, "timestamp", &date.and_utc().timestamp().to_string())?;
+        }
+        if let Some(stmt) = &mut labels {
+            for label in stmt.query_map([id], |row| row.get::<_, String>(0))? {
+                write_xapian_field(out, "label", &label?)?;
+            }
+        }
+        write_xapian_field(out, "body", &text(6)?)?;
+        writeln!(out)?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+#[cfg(feature = "xapian")]
+fn write_xapian_field(out: &mut impl Write, name: &str, value: &str) -> std::io::Result<()> {
+    let mut lines = value.lines().map(|line| line.trim_end_matches('\r'));
+    writeln!(out, "{}={}", name, lines.next().unwrap_or_default())?;
+    for line in lines {
+        writeln!(out, "={}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::{MboxStream, RawMessage};
+
+    /// `messages` exported, then split back out of the mbox.
+    fn round_trip(messages: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut mbox = Vec::new();
+        for raw in messages {
+            write_message(&mut mbox, raw, "a@example.com", Some("2024-01-01 00:00:00")).unwrap();
+        }
+        let mut split = Vec::new();
+        let mut stream = MboxStream::default();
+        stream
+            .feed(&mbox, &mut |message: RawMessage| {
+                split.push(message.data.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        stream
+            .finish(&mut |message: RawMessage| {
+                split.push(message.data.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        split
+    }
+
+    #[test]
+    fn from_lines_survive_round_trips() {
+        // As imported from an mbox: the separator, and a body line quoted in that mbox
+        let imported: &[u8] = b"From a@example.com Mon Jan  1 00:00:00 2024\nSubject: s\n\n>From the top\nbody\n\n";
+        let once = round_trip(&[imported]);
+        assert_eq!(once, [imported]);
+        assert_eq!(round_trip(&[&once[0]]), [imported]);
+    }
+
+    #[test]
+    fn bare_from_lines_are_quoted() {
+        // As fetched from a server, with no separator of its own
+        let fetched: &[u8] = b"Subject: s\n\nFrom the top\n>From before\n";
+        assert_eq!(
+            round_trip(&[fetched, fetched]),
+            [
+                b"From a@example.com Mon Jan  1 00:00:00 2024\nSubject: s\n\n>From the top\n>From before\n\n".to_vec(),
+                b"From a@example.com Mon Jan  1 00:00:00 2024\nSubject: s\n\n>From the top\n>From before\n\n".to_vec(),
+            ]
+        );
+    }
+}
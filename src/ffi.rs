@@ -0,0 +1,241 @@
+//! C API for embedding the importer (see `include/mbox2db.h`).
+//!
+//! The caller opens an importer on a database path, feeds it an mbox stream in
+//! chunks of any size, then finalizes it, which commits the import. Every call
+//! returns a sentinel on failure (NULL or -1); `mbox2db_last_error` then
+//! describes what went wrong.
+
+use crate::config::Config;
+use crate::date::{AmbiguousZones, DateParser};
+use crate::import::{parse_size, AliasMap, Chats, IfExists, ImportOptions, Importer, OversizedAttachments, Prefer, WarningLog};
+use crate::schema::{FtsTokenizer, SchemaProfile, Tables};
+use crate::sources::MboxStream;
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// An import in progress, opaque to C callers.
+pub struct Mbox2dbImporter {
+    importer: Importer,
+    stream: MboxStream,
+}
+
+/// Runs `f`, turning errors and panics into `fallback` plus a stored error message.
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T>) -> T {
+    let error = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(_) => "mbox2db panicked".to_string(),
+    };
+    let error = CString::new(error.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error));
+    fallback
+}
+
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<&'a str> {
+    if value.is_null() {
+        bail!("{} is NULL", name);
+    }
+    CStr::from_ptr(value).to_str().with_context(|| format!("{} is not valid UTF-8", name))
+}
+
+/// Import options from the JSON object passed to `mbox2db_importer_open`.
+/// Keys are named after the command-line flags they stand for (snake_case),
+/// and anything missing takes the CLI default. Only the options listed in
+/// `include/mbox2db.h` are read; the rest are fixed below.
+fn parse_options(json: Option<&str>) -> Result<ImportOptions> {
+    let json: serde_json::Value = match json {
+        Some(json) => serde_json::from_str(json).context("Invalid options JSON")?,
+        None => serde_json::Value::Null,
+    };
+    let flag = |key: &str| json[key].as_bool().unwrap_or(false);
+    let schema = match json["schema"].as_str() {
+        Some(name) => SchemaProfile::from_str(name, true).map_err(|e| anyhow::anyhow!("Invalid schema: {}", e))?,
+        None => SchemaProfile::Normalized,
+    };
+    let max_attachment_size = match &json["max_attachment_size"] {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(size) => Some(parse_size(size).map_err(|e| anyhow::anyhow!(e))?),
+        size => Some(size.as_u64().context("max_attachment_size must be a byte count or a size like \"10MB\"")?),
+    };
+    let oversized_attachments = match json["oversized_attachments"].as_str() {
+        Some(name) => OversizedAttachments::from_str(name, true).map_err(|e| anyhow::anyhow!("Invalid oversized_attachments: {}", e))?,
+        None => OversizedAttachments::Hash,
+    };
+    let chats = match json["chats"].as_str() {
+        Some(name) => Chats::from_str(name, true).map_err(|e| anyhow::anyhow!("Invalid chats: {}", e))?,
+        None => Chats::Email,
+    };
+    if chats == Chats::Table && schema == SchemaProfile::Flat {
+        anyhow::bail!("chats \"table\" needs the normalized or full schema");
+    }
+    if flag("extract_amounts") && schema == SchemaProfile::Flat {
+        anyhow::bail!("extract_amounts needs the normalized or full schema");
+    }
+    let me: Vec<String> = json["me"].as_array().into_iter().flatten().filter_map(|address| address.as_str().map(String::from)).collect();
+    if !me.is_empty() && schema == SchemaProfile::Flat {
+        anyhow::bail!("me needs the normalized or full schema");
+    }
+    if flag("consolidate_labels") && schema == SchemaProfile::Flat {
+        anyhow::bail!("consolidate_labels needs the normalized or full schema");
+    }
+    let prefer = match json["prefer"].as_str() {
+        Some(name) => Prefer::from_str(name, true).map_err(|e| anyhow::anyhow!("Invalid prefer: {}", e))?,
+        None => Prefer::Both,
+    };
+    let ambiguous_tz = match json["ambiguous_tz"].as_str() {
+        Some(name) => AmbiguousZones::from_str(name, true).map_err(|e| anyhow::anyhow!("Invalid ambiguous_tz: {}", e))?,
+        None => AmbiguousZones::Common,
+    };
+    let display_tz = match json["display_tz"].as_str() {
+        Some(name) => Some(name.parse::<Tz>().map_err(|_| anyhow::anyhow!("Invalid display_tz: {}", name))?),
+        None => None,
+    };
+    let config = match json["config"].as_str() {
+        Some(path) => Config::load(Path::new(path), None)?,
+        None => Config::default(),
+    };
+    let aliases = match json["aliases"].as_str() {
+        Some(path) => AliasMap::load(Path::new(path))?,
+        None => AliasMap::default(),
+    };
+
+    Ok(ImportOptions {
+        include_spam: flag("include_spam"),
+        include_trash: flag("include_trash"),
+        include_both: flag("include_spam_and_trash"),
+        chats,
+        skip_calendar: flag("skip_calendar"),
+        aliases,
+        decryptor: None,
+        store_signatures: flag("store_signatures"),
+        extract_amounts: flag("extract_amounts"),
+        fts: flag("fts"),
+        fts_tokenize: FtsTokenizer::Unicode61.spec(None)?,
+        sanitize_html: flag("sanitize_html"),
+        prefer,
+        account: json["account"].as_str().map(String::from),
+        // Re-importing into the same database skips what it already holds
+        if_exists: IfExists::Append,
+        // Messages arrive one at a time, never as a whole export to compare
+        incremental: false,
+        merge_copies: false,
+        consolidate_labels: flag("consolidate_labels"),
+        schema,
+        tables: Tables::new(json["table_prefix"].as_str().unwrap_or(""), json["table_name"].as_str())?,
+        extensions: Vec::new(),
+        pragmas: Vec::new(),
+        views: !flag("no_views"),
+        post_sql: Vec::new(),
+        split_by: None,
+        max_attachment_size,
+        oversized_attachments,
+        attachments_in_db: flag("attachments_in_db"),
+        header_filter: config.header_filter(),
+        me,
+        date_parser: DateParser::new(config.date_formats, ambiguous_tz),
+        display_tz,
+        // Messages are fed in as they arrive, so there's nothing to count ahead
+        two_pass: false,
+        // Likewise no input size to check the free space against
+        space_check: false,
+        busy_timeout: Duration::from_secs(json["busy_timeout"].as_u64().unwrap_or(30)),
+        keep_going: json["keep_going"].as_bool().unwrap_or(false),
+        // The caller has the counts from mbox2db_importer_finalize
+        summary_json: None,
+        warnings: json["warnings_file"].as_str().map(|path| WarningLog::create(Path::new(path))).transpose()?,
+    })
+}
+
+/// Opens (or creates) the database at `output_path` and starts an import.
+/// `options_json` may be NULL; see `include/mbox2db.h` for its keys.
+/// Returns NULL on failure.
+///
+/// # Safety
+///
+/// `output_path` must be a NUL-terminated string and `options_json` either NULL
+/// or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mbox2db_importer_open(output_path: *const c_char, options_json: *const c_char) -> *mut Mbox2dbImporter {
+    guard(ptr::null_mut(), || {
+        let output_path = read_str(output_path, "output_path")?;
+        let options_json = if options_json.is_null() { None } else { Some(read_str(options_json, "options_json")?) };
+        let options = parse_options(options_json)?;
+        let importer = Importer::begin(Path::new(output_path), Arc::new(options), "(C API)", ProgressBar::hidden())?;
+        Ok(Box::into_raw(Box::new(Mbox2dbImporter { importer, stream: MboxStream::default() })))
+    })
+}
+
+/// Feeds the next `len` bytes of the mbox stream. Returns 0, or -1 on failure,
+/// after which the importer should be passed to `mbox2db_importer_abort`.
+///
+/// # Safety
+///
+/// `importer` must come from `mbox2db_importer_open` and not have been
+/// finalized or aborted; `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mbox2db_importer_feed(importer: *mut Mbox2dbImporter, data: *const u8, len: usize) -> c_int {
+    guard(-1, || {
+        let handle = importer.as_mut().context("importer is NULL")?;
+        if data.is_null() && len > 0 {
+            bail!("data is NULL");
+        }
+        let chunk = if len == 0 { &[][..] } else { std::slice::from_raw_parts(data, len) };
+        let Mbox2dbImporter { importer, stream } = handle;
+        stream.feed(chunk, &mut |message| importer.add(message))?;
+        Ok(0)
+    })
+}
+
+/// Imports the last message and commits. Returns the number of emails
+/// imported, or -1 on failure (nothing is committed then). The importer is
+/// freed either way.
+///
+/// # Safety
+///
+/// `importer` must come from `mbox2db_importer_open` and not have been
+/// finalized or aborted.
+#[no_mangle]
+pub unsafe extern "C" fn mbox2db_importer_finalize(importer: *mut Mbox2dbImporter) -> i64 {
+    guard(-1, || {
+        if importer.is_null() {
+            bail!("importer is NULL");
+        }
+        let Mbox2dbImporter { mut importer, stream } = *Box::from_raw(importer);
+        let digest = stream.finish(&mut |message| importer.add(message))?;
+        importer.commit(Some(&digest))?;
+        Ok(importer.counts().imported as i64)
+    })
+}
+
+/// Frees an importer without committing, discarding everything fed to it.
+///
+/// # Safety
+///
+/// `importer` must be NULL or come from `mbox2db_importer_open` and not have
+/// been finalized or aborted.
+#[no_mangle]
+pub unsafe extern "C" fn mbox2db_importer_abort(importer: *mut Mbox2dbImporter) {
+    if !importer.is_null() {
+        drop(Box::from_raw(importer));
+    }
+}
+
+/// The error from the most recent failed call on this thread, or NULL. The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn mbox2db_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
@@ -0,0 +1,2847 @@
+//! The import engine: parses raw messages and writes them to the database.
+//! Used by the CLI for every source and by the C API in `ffi`.
+
+use crate::attachments::{self, Attachment};
+use crate::auth;
+use crate::config::HeaderFilter;
+use crate::date::{DateParser, ParsedDate};
+use crate::markup;
+use crate::chat::{self, ChatLine};
+use crate::money::{self, Receipt};
+use crate::render;
+use crate::report;
+use crate::schema::{self, SchemaProfile, Tables};
+use crate::spam;
+use crate::sources::{InputDigest, MessageSource, RawMessage, Skipped};
+use crate::text;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::Deserialize;
+use indicatif::{ProgressBar, ProgressStyle};
+use mailparse::{addrparse, parse_headers, parse_mail, DispositionType, MailAddr, MailHeaderMap};
+use once_cell::sync::Lazy;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IfExists {
+    /// Refuse to touch an existing database
+    Fail,
+    /// Delete the existing database and start over
+    Overwrite,
+    /// Add to the existing database, skipping messages it already contains
+    Append,
+}
+
+/// Largest attachment `--attachments-in-db` stores when no `--max-attachment-size` is given.
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 25 << 20;
+
+/// What happens to attachments larger than `--max-attachment-size`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OversizedAttachments {
+    /// Leave them out of the attachments table entirely
+    Skip,
+    /// Record name, type, size and SHA-256, but don't extract text, list archive contents or keep inline image data
+    Hash,
+    /// Import them like any other attachment
+    Store,
+}
+
+/// Which alternative of a multipart/alternative message fills the body columns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Prefer {
+    /// The plain text, or the HTML when there's no plain text alternative
+    Plain,
+    /// The HTML, or the plain text when there's no HTML alternative
+    Html,
+    /// Both: body_plain from the plain text and body_html from the HTML
+    Both,
+}
+
+/// What happens to the Google Talk and Hangouts chats in a Takeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Chats {
+    /// Import them like any other message
+    Email,
+    /// Store them in the chats and chat_lines tables instead of emails
+    Table,
+    /// Leave them out
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitBy {
+    /// One database per year of the message date (undated messages go to `undated-…`)
+    Year,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct EmailRecord {
+    message_hash: String,
+    from: String,
+    to: String,
+    cc: String,
+    bcc: String,
+    /// X-Original-To and Delivered-To addresses, in header order
+    original_to: Vec<String>,
+    delivered_to: Vec<String>,
+    subject: String,
+    date: String,
+    message_id: String,
+    in_reply_to: String,
+    references: String,
+    content_type: String,
+    body_plain: String,
+    body_html: String,
+    body_clean: String,
+    body_plain_raw: Option<Vec<u8>>,
+    body_html_raw: Option<Vec<u8>>,
+    signature: Option<String>,
+    gmail_labels: String,
+    headers: Vec<(String, String)>,
+    is_signed: bool,
+    is_encrypted: bool,
+    is_decrypted: bool,
+    /// Mailing list or bulk mail: List-Id, List-Unsubscribe or Precedence: bulk/list/junk
+    is_bulk: bool,
+    /// Sent by a program rather than a person: a no-reply or daemon address,
+    /// Auto-Submitted, or an empty Return-Path
+    is_automated: bool,
+    /// An out-of-office or other automatic reply
+    is_auto_reply: bool,
+    read_receipt_to: Option<String>,
+    priority: Option<&'static str>,
+    mailer: Option<String>,
+    detected_charset: Option<&'static str>,
+    attachments: Vec<Attachment>,
+    /// The date for date_parsed and its date_source, set by `resolve_date`
+    message_date: Option<(ParsedDate, &'static str)>,
+    /// Why an encrypted message couldn't be decrypted, warned about when it's written
+    decrypt_error: Option<String>,
+    /// Amounts, invoice number and due date, with --extract-amounts
+    receipt: Option<Receipt>,
+    /// Read from the HTML before --sanitize-html, which drops the JSON-LD scripts
+    orders: Vec<markup::Order>,
+    reservations: Vec<markup::Reservation>,
+    links: Vec<text::Link>,
+    /// The transcript of a chat, with --chats table
+    chat: Option<Vec<ChatLine>>,
+    /// METHOD of a calendar invitation, update or reply
+    calendar_method: Option<String>,
+}
+
+static INDENTED_HEADER: Lazy<BytesRegex> = Lazy::new(|| BytesRegex::new(r"^\s+[A-Za-z0-9-]+:").unwrap());
+static BOUNDARY_PARAM: Lazy<BytesRegex> =
+    Lazy::new(|| BytesRegex::new(r#"(?i)\bboundary\s*=\s*(?:"([^"]+)"|([^\s;"]+))"#).unwrap());
+
+fn extract_email_data(raw_email: &[u8], prefer: Prefer) -> Result<EmailRecord> {
+    // Fix malformed headers: remove leading spaces from lines that shouldn't have them.
+    // Works on bytes so 8-bit bodies reach charset decoding intact.
+    let mut in_headers = true;
+    // Of the multiparts declared so far, so a "-- " signature isn't taken for one
+    let mut boundaries: Vec<Vec<u8>> = Vec::new();
+    let fixed_email = raw_email
+        .split(|&b| b == b'\n')
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let is_blank = line.iter().all(u8::is_ascii_whitespace);
+            // Headers end at the first blank line; a MIME boundary starts a part's headers
+            if is_blank {
+                in_headers = false;
+            } else if in_headers {
+                if let Some(captures) = BOUNDARY_PARAM.captures(line) {
+                    boundaries.push(captures.get(1).or(captures.get(2)).map_or(&[][..], |m| m.as_bytes()).to_vec());
+                }
+            } else if let Some(delimiter) = line.strip_prefix(b"--") {
+                // "--boundary", but not the closing "--boundary--"
+                in_headers = boundaries
+                    .iter()
+                    .any(|boundary| delimiter.strip_prefix(boundary.as_slice()).is_some_and(|rest| rest.trim_ascii().is_empty()));
+            }
+            // In the header block, if a line starts with space but doesn't look like a
+            // continuation, just trim it. An indented line continues the header above
+            // (e.g. the filename*1*= part of a long RFC 2231 filename) unless it looks
+            // like a header. Bodies are left as they are.
+            let is_continuation = !INDENTED_HEADER.is_match(line);
+            if in_headers && line.starts_with(b" ") && !is_blank && !is_continuation {
+                line.trim_ascii_start()
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&b'\n');
+    
+    let parsed = parse_mail(&fixed_email)?;
+    let mut record = EmailRecord::default();
+
+    for header in &parsed.headers {
+        let name = header.get_key().to_lowercase();
+        let value = header.get_value();
+        record.headers.push((header.get_key(), value.clone()));
+
+        match name.as_str() {
+            "from" => record.from = value,
+            "to" => record.to = value,
+            "cc" => record.cc = value,
+            "bcc" => record.bcc = value,
+            // The address a message actually arrived at, behind aliases and forwards
+            "x-original-to" => add_addresses(&mut record.original_to, &value),
+            "delivered-to" => add_addresses(&mut record.delivered_to, &value),
+            "subject" => record.subject = value,
+            "date" => record.date = value,
+            "message-id" => record.message_id = value,
+            "in-reply-to" => record.in_reply_to = value,
+            "references" => record.references = value,
+            "content-type" => record.content_type = value,
+            "x-gmail-labels" => record.gmail_labels = value,
+            "disposition-notification-to" => record.read_receipt_to = Some(value),
+            // Pre-MDN headers some older clients still send
+            "return-receipt-to" | "x-confirm-reading-to" => {
+                record.read_receipt_to.get_or_insert(value);
+            }
+            "x-mailer" => record.mailer = Some(value),
+            "user-agent" | "x-newsreader" => {
+                record.mailer.get_or_insert(value);
+            }
+            "list-id" | "list-unsubscribe" => record.is_bulk = true,
+            // RFC 3834: anything but "no" means no person sent it
+            "auto-submitted" if !value.trim().eq_ignore_ascii_case("no") => {
+                record.is_automated = true;
+                record.is_auto_reply |= value.trim().to_lowercase().starts_with("auto-replied");
+            }
+            // What vacation programs sent before Auto-Submitted caught on
+            "x-autoreply" | "x-autorespond" | "x-autoreply-from" | "x-mail-autoreply" => {
+                record.is_automated = true;
+                record.is_auto_reply = true;
+            }
+            "precedence" if value.trim().eq_ignore_ascii_case("auto_reply") => {
+                record.is_automated = true;
+                record.is_auto_reply = true;
+            }
+            // Bounces and other notifications come from the null sender
+            "return-path" if value.trim() == "<>" => record.is_automated = true,
+            "precedence" if matches!(value.trim().to_lowercase().as_str(), "bulk" | "list" | "junk") => record.is_bulk = true,
+            "x-priority" | "importance" | "priority" | "x-msmail-priority" => {
+                record.priority = record.priority.or(normalize_priority(&value));
+            }
+            _ => {}
+        }
+    }
+
+    if is_automated_sender(&record.from) {
+        record.is_automated = true;
+    }
+    if AUTO_REPLY_SUBJECT.is_match(&record.subject) {
+        record.is_automated = true;
+        record.is_auto_reply = true;
+    }
+
+    extract_body(&parsed, &mut record, prefer);
+    detect_signed_encrypted(&parsed, &mut record);
+    record.calendar_method = calendar_method(&parsed);
+
+    // Inline PGP isn't visible in the MIME structure, only in the text itself
+    if record.body_plain.contains("-----BEGIN PGP SIGNED MESSAGE-----") {
+        record.is_signed = true;
+    }
+    if record.body_plain.contains("-----BEGIN PGP MESSAGE-----") {
+        record.is_encrypted = true;
+    }
+
+    Ok(record)
+}
+
+static AUTOMATED_SENDER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^(no[-_.]?reply|do[-_.]?not[-_.]?reply|mailer[-_.]?daemon|postmaster|bounces?|notifications?|auto[-_.]?(reply|confirm|mailer)|daemon)([-_.+].*)?$").unwrap()
+});
+
+/// Subjects vacation responders use when they set no headers to say so:
+/// Outlook's "Automatic reply: ...", "Out of Office: ...", and their
+/// translations.
+static AUTO_REPLY_SUBJECT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)^\s*(\[[^\]]*\]\s*)?(automatic reply|auto[- ]?reply|auto[- ]?response|autoresponder|out of (the )?office|away from (the )?office|vacation reply|abwesenheitsnotiz|automatische antwort|r[ée]ponse automatique|absence du bureau|respuesta autom[áa]tica|fuera de la oficina|risposta automatica|automatisch antwoord|resposta autom[áa]tica|autosvar|automaattinen vastaus|automatyczna odpowied[źz])",
+    )
+    .unwrap()
+});
+
+/// Whether the sender's mailbox name is one programs send from: noreply@,
+/// mailer-daemon@, notifications@, bounces+123@ and the like.
+fn is_automated_sender(from: &str) -> bool {
+    split_addresses(from)
+        .first()
+        .and_then(|(address, _)| address.split_once('@'))
+        .is_some_and(|(local, _)| AUTOMATED_SENDER.is_match(local))
+}
+
+/// The inbox tab Gmail sorted a message into, from its "Category ..." label.
+fn gmail_category(labels: &str) -> Option<&'static str> {
+    labels.split(',').find_map(|label| match label.trim().to_lowercase().as_str() {
+        "category personal" | "category primary" => Some("primary"),
+        "category social" => Some("social"),
+        "category promotions" => Some("promotions"),
+        "category updates" => Some("updates"),
+        "category forums" => Some("forums"),
+        _ => None,
+    })
+}
+
+/// Adds the addresses in a header value to `list`, skipping ones already there.
+fn add_addresses(list: &mut Vec<String>, header_value: &str) {
+    for (address, _) in split_addresses(header_value) {
+        if !list.contains(&address) {
+            list.push(address);
+        }
+    }
+}
+
+/// "high", "normal" or "low" from the values the priority headers use:
+/// X-Priority's 1-5 scale ("1 (Highest)"), Importance and X-MSMail-Priority's
+/// words, and Priority's "urgent"/"non-urgent" (RFC 2156).
+fn normalize_priority(value: &str) -> Option<&'static str> {
+    let value = value.trim().to_lowercase();
+    if let Some(level) = value.chars().next().and_then(|c| c.to_digit(10)) {
+        return match level {
+            1 | 2 => Some("high"),
+            3 => Some("normal"),
+            4 | 5 => Some("low"),
+            _ => None,
+        };
+    }
+    match value.split(|c: char| !c.is_alphanumeric() && c != '-').next().unwrap_or("") {
+        "high" | "highest" | "urgent" => Some("high"),
+        "normal" | "medium" => Some("normal"),
+        "low" | "lowest" | "non-urgent" => Some("low"),
+        _ => None,
+    }
+}
+
+fn detect_signed_encrypted(parsed: &mailparse::ParsedMail, record: &mut EmailRecord) {
+    match parsed.ctype.mimetype.as_str() {
+        "multipart/signed" | "application/pkcs7-signature" | "application/x-pkcs7-signature" => {
+            record.is_signed = true;
+        }
+        "multipart/encrypted" => record.is_encrypted = true,
+        "application/pkcs7-mime" | "application/x-pkcs7-mime" => {
+            // S/MIME uses the same type for opaque signatures and encryption
+            let smime_type = parsed
+                .ctype
+                .params
+                .get("smime-type")
+                .map(|t| t.to_lowercase())
+                .unwrap_or_default();
+            if smime_type == "signed-data" {
+                record.is_signed = true;
+            } else {
+                record.is_encrypted = true;
+            }
+        }
+        _ => {}
+    }
+
+    for part in &parsed.subparts {
+        detect_signed_encrypted(part, record);
+    }
+}
+
+/// The iTIP METHOD of the message's first text/calendar part (REQUEST for an
+/// invitation or update, REPLY, CANCEL, ...), from its Content-Type or, when
+/// that doesn't say, the iCalendar data.
+fn calendar_method(parsed: &mailparse::ParsedMail) -> Option<String> {
+    if matches!(parsed.ctype.mimetype.as_str(), "text/calendar" | "application/ics") {
+        let method = parsed.ctype.params.get("method").cloned().or_else(|| {
+            let body = parsed.get_body().ok()?;
+            body.lines().find_map(|line| line.strip_prefix("METHOD:").map(String::from))
+        });
+        if let Some(method) = method.map(|method| method.trim().to_uppercase()).filter(|method| !method.is_empty()) {
+            return Some(method);
+        }
+    }
+    parsed.subparts.iter().find_map(calendar_method)
+}
+
+/// Whether a calendar method is scheduling traffic: anything but PUBLISH,
+/// which is a standalone event (a ticket or booking's .ics).
+fn is_calendar_notification(method: Option<&str>) -> bool {
+    method.is_some_and(|method| method != "PUBLISH")
+}
+
+/// Returns true for leaf parts that should be stored as attachments rather than
+/// used as the message body.
+fn is_attachment(part: &mailparse::ParsedMail) -> bool {
+    let disposition = part.get_content_disposition();
+    if disposition.disposition == DispositionType::Attachment {
+        return true;
+    }
+    !matches!(part.ctype.mimetype.as_str(), "text/plain" | "text/html")
+}
+
+fn attachment_filename(part: &mailparse::ParsedMail) -> Option<String> {
+    let disposition = part.get_content_disposition();
+    disposition
+        .params
+        .get("filename")
+        .cloned()
+        .or_else(|| rfc2231_param(&disposition.params, "filename"))
+        .or_else(|| part.ctype.params.get("name").cloned())
+        .or_else(|| rfc2231_param(&part.ctype.params, "name"))
+}
+
+/// Parses a size like `10MB`, `512K` or `1048576` into bytes. Units are
+/// binary: K/KB/KiB = 1024 bytes, M = 1024 K, G = 1024 M.
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {}", value))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("invalid size unit in {} (use B, KB, MB or GB)", value)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Reassembles a parameter sent in RFC 2231 form, which mailparse may leave
+/// as separate keys: `name*=utf-8''%E2%82%AC.pdf` (encoded), or split into
+/// `name*0*=utf-8''...; name*1*=...` (continuations, each optionally encoded).
+fn rfc2231_param(params: &BTreeMap<String, String>, name: &str) -> Option<String> {
+    let mut sections: Vec<(u32, bool, &str)> = params
+        .iter()
+        .filter_map(|(key, value)| {
+            let rest = key.strip_prefix(name)?.strip_prefix('*')?;
+            let (index, encoded) = match rest.strip_suffix('*') {
+                Some(index) => (index, true),
+                None if rest.is_empty() => ("", true),
+                None => (rest, false),
+            };
+            let index = if index.is_empty() { 0 } else { index.parse().ok()? };
+            Some((index, encoded, value.as_str()))
+        })
+        .collect();
+    if sections.is_empty() {
+        return None;
+    }
+    sections.sort_by_key(|&(index, _, _)| index);
+
+    // Only the first section names the charset: charset'language'value
+    let mut charset = String::new();
+    let mut bytes = Vec::new();
+    for (n, &(_, encoded, value)) in sections.iter().enumerate() {
+        let mut value = value.trim_matches('"');
+        if n == 0 && encoded {
+            let mut fields = value.splitn(3, '\'');
+            if let (Some(set), Some(_language), Some(rest)) = (fields.next(), fields.next(), fields.next()) {
+                charset = set.to_lowercase();
+                value = rest;
+            }
+        }
+        if encoded {
+            bytes.extend(percent_decode(value));
+        } else {
+            bytes.extend_from_slice(value.as_bytes());
+        }
+    }
+
+    let decoded = match charset.as_str() {
+        // Latin-1 bytes are the first 256 code points
+        "iso-8859-1" | "latin1" | "windows-1252" => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    Some(decoded)
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // Two hex digits, which from_str_radix alone doesn't check ("+1" parses)
+        let hex = bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Whether a part, or the first text body inside it, is plain text or HTML;
+/// None when it's no body at all (an attachment, a text/calendar part).
+fn body_kind(part: &mailparse::ParsedMail) -> Option<Prefer> {
+    if part.subparts.is_empty() {
+        return match part.ctype.mimetype.as_str() {
+            _ if is_attachment(part) => None,
+            "text/html" => Some(Prefer::Html),
+            _ => Some(Prefer::Plain),
+        };
+    }
+    part.subparts.iter().find_map(body_kind)
+}
+
+/// Which parts of a multipart/alternative to take the body from, by index:
+/// the last plain text and HTML ones (the sender's best versions), or just
+/// the preferred one of them.
+fn chosen_alternatives(parts: &[mailparse::ParsedMail], prefer: Prefer) -> Vec<usize> {
+    let last = |kind: Prefer| parts.iter().rposition(|part| body_kind(part) == Some(kind));
+    let (plain, html) = (last(Prefer::Plain), last(Prefer::Html));
+    match prefer {
+        Prefer::Plain => plain.or(html).into_iter().collect(),
+        Prefer::Html => html.or(plain).into_iter().collect(),
+        Prefer::Both => plain.into_iter().chain(html).collect(),
+    }
+}
+
+fn extract_body(parsed: &mailparse::ParsedMail, record: &mut EmailRecord, prefer: Prefer) {
+    if parsed.subparts.is_empty() {
+        if is_attachment(parsed) {
+            if let Ok(data) = parsed.get_body_raw() {
+                let content_id = parsed
+                    .headers
+                    .iter()
+                    .find(|h| h.get_key().to_lowercase() == "content-id")
+                    .map(|h| h.get_value().trim().trim_start_matches('<').trim_end_matches('>').to_string())
+                    .filter(|id| !id.is_empty());
+                record.attachments.push(Attachment {
+                    filename: attachment_filename(parsed),
+                    content_type: parsed.ctype.mimetype.clone(),
+                    content_id,
+                    data,
+                });
+            }
+            return;
+        }
+
+        let content_type = parsed
+            .headers
+            .iter()
+            .find(|h| h.get_key().to_lowercase() == "content-type")
+            .map(|h| h.get_value().to_lowercase())
+            .unwrap_or_default();
+
+        if let Ok(data) = parsed.get_body_raw() {
+            let decoded = text::decode(&data, parsed.ctype.params.get("charset").map(String::as_str));
+            if record.detected_charset.is_none() {
+                record.detected_charset = decoded.detected_charset;
+            }
+            // Keep the original bytes of a body that only decoded with replacement characters
+            let raw = decoded.lossy.then_some(data);
+            if content_type.contains("text/html") {
+                record.body_html = decoded.text;
+                record.body_html_raw = raw;
+            } else {
+                record.body_plain = decoded.text;
+                record.body_plain_raw = raw;
+            }
+        }
+    } else if parsed.ctype.mimetype == "multipart/alternative" {
+        // The alternatives not chosen are left out entirely; parts that are
+        // no body (a text/calendar version) are kept as attachments
+        let chosen = chosen_alternatives(&parsed.subparts, prefer);
+        for (index, part) in parsed.subparts.iter().enumerate() {
+            if chosen.contains(&index) || body_kind(part).is_none() {
+                extract_body(part, record, prefer);
+            }
+        }
+    } else {
+        for part in &parsed.subparts {
+            extract_body(part, record, prefer);
+        }
+    }
+}
+
+/// Splits a To/Cc/Bcc header value into (address, display name) pairs.
+/// Falls back to a plain comma split when the header isn't valid RFC 5322.
+fn split_addresses(header_value: &str) -> Vec<(String, Option<String>)> {
+    if header_value.trim().is_empty() {
+        return Vec::new();
+    }
+
+    match addrparse(header_value) {
+        Ok(list) => list
+            .iter()
+            .flat_map(|addr| match addr {
+                MailAddr::Single(info) => vec![info.clone()],
+                MailAddr::Group(group) => group.addrs.clone(),
+            })
+            .map(|info| (info.addr.trim().to_lowercase(), info.display_name))
+            .filter(|(addr, _)| !addr.is_empty())
+            .collect(),
+        Err(_) => header_value
+            .split(',')
+            .map(|part| part.trim().trim_matches(|c| c == '<' || c == '>').to_lowercase())
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| (addr, None))
+            .collect(),
+    }
+}
+
+/// The lowercased domain of an address from `split_addresses`, which can still
+/// hold a display name or comment when the header didn't parse:
+/// "a@example.com (A)", "\"a@home\" <a@example.com>".
+fn address_domain(address: &str) -> Option<String> {
+    let address = address.rsplit_once('<').map_or(address, |(_, bracketed)| bracketed);
+    let mailbox = address.split(|c: char| c.is_whitespace() || c == '(').find(|part| part.contains('@'))?;
+    let (_, domain) = mailbox.rsplit_once('@')?;
+    let domain = domain.trim_end_matches(['>', ')']);
+    (!domain.is_empty()).then(|| domain.to_lowercase())
+}
+
+/// Maps alternate addresses of the same person onto one canonical address.
+#[derive(Default)]
+pub struct AliasMap {
+    canonical: HashMap<String, String>,
+}
+
+impl AliasMap {
+    /// Loads an alias file. Each non-empty line lists the addresses of one person,
+    /// separated by commas or whitespace; the first address is the canonical one.
+    /// Lines starting with `#` are comments.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read alias file: {}", path.display()))?;
+
+        let mut canonical = HashMap::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut addresses = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .map(|addr| addr.trim().to_lowercase())
+                .filter(|addr| !addr.is_empty());
+
+            if let Some(primary) = addresses.next() {
+                for alias in addresses {
+                    canonical.insert(alias, primary.clone());
+                }
+                canonical.insert(primary.clone(), primary);
+            }
+        }
+
+        Ok(Self { canonical })
+    }
+
+    fn resolve(&self, address: &str) -> String {
+        self.canonical
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| address.to_string())
+    }
+}
+
+fn insert_email(conn: &Connection, raw_email: &[u8], record: &EmailRecord, options: &ImportOptions, run_id: i64) -> Result<i64> {
+    let aliases = &options.aliases;
+    let tables = &options.tables;
+    let (date, date_source) = record.message_date.as_ref().map(|(date, source)| (date, *source)).unzip();
+    let spam = spam::spam_check(&record.headers);
+    // Inline images are part of the HTML body, not something the sender attached
+    let attachment_count = record.attachments.iter().filter(|attachment| !attachment.is_inline_image()).count() as i64;
+    let sender = split_addresses(&record.from).into_iter().next().map(|(address, _)| address);
+    let canonical_contact = sender.as_deref().map(|address| aliases.resolve(address));
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (from_addr, canonical_contact, from_domain, to_addr, cc, bcc, original_to, delivered_to, subject, date, date_parsed, date_offset, date_strategy, date_display, date_source, message_id, in_reply_to, refs, content_type, has_attachments, attachment_count, body_plain, body_html, body_plain_raw, body_html_raw, body_clean, body_word_count, body_char_count, signature, is_signed, is_encrypted, is_decrypted, is_bulk, is_automated, is_auto_reply, read_receipt_to, priority, mailer, spam_score, detected_charset, gmail_category, calendar_method, account, import_run_id, message_hash, from_folded, subject_folded)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34, ?35, ?36, ?37, ?38, ?39, ?40, ?41, ?42, ?43, ?44, ?45, ?46, ?47)",
+            tables.emails
+        ),
+        params![
+            &record.from,
+            &canonical_contact,
+            sender.as_deref().and_then(address_domain),
+            &record.to,
+            &record.cc,
+            &record.bcc,
+            (!record.original_to.is_empty()).then(|| record.original_to.join(", ")),
+            (!record.delivered_to.is_empty()).then(|| record.delivered_to.join(", ")),
+            &record.subject,
+            &record.date,
+            date.map(|date| date.to_sql()),
+            date.and_then(|date| date.offset_sql()),
+            date.map(|date| date.strategy),
+            date.zip(options.display_tz).map(|(date, zone)| date.display_in(zone)),
+            date_source,
+            &record.message_id,
+            &record.in_reply_to,
+            &record.references,
+            &record.content_type,
+            attachment_count > 0,
+            attachment_count,
+            &record.body_plain,
+            &record.body_html,
+            &record.body_plain_raw,
+            &record.body_html_raw,
+            &record.body_clean,
+            record.body_clean.split_whitespace().count() as i64,
+            record.body_clean.chars().count() as i64,
+            &record.signature,
+            record.is_signed,
+            record.is_encrypted,
+            record.is_decrypted,
+            record.is_bulk,
+            record.is_automated,
+            record.is_auto_reply,
+            &record.read_receipt_to,
+            record.priority,
+            &record.mailer,
+            spam.score,
+            record.detected_charset,
+            gmail_category(&record.gmail_labels),
+            &record.calendar_method,
+            &options.account,
+            run_id,
+            &record.message_hash,
+            text::fold(&record.from),
+            text::fold(&record.subject),
+        ],
+    )?;
+    let email_id = conn.last_insert_rowid();
+
+    if options.schema == SchemaProfile::Flat {
+        return Ok(email_id);
+    }
+
+    for (kind, header_value) in [("to", &record.to), ("cc", &record.cc), ("bcc", &record.bcc)] {
+        for (address, name) in split_addresses(header_value) {
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, kind, address, name, canonical_contact) VALUES (?1, ?2, ?3, ?4, ?5)", tables.recipients),
+                params![email_id, kind, &address, &name, aliases.resolve(&address)],
+            )?;
+        }
+    }
+
+    for label in record.gmail_labels.split(',').map(str::trim).filter(|label| !label.is_empty()) {
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, label) VALUES (?1, ?2)", tables.labels),
+            params![email_id, label],
+        )?;
+    }
+
+    conn.execute(
+        &format!("INSERT INTO {} (email_id, thread_id) VALUES (?1, ?2)", tables.threads),
+        params![email_id, thread_id(conn, tables, record)?],
+    )?;
+
+    for (position, referenced) in referenced_message_ids(record).iter().enumerate() {
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, position, referenced_message_id) VALUES (?1, ?2, ?3)", tables.message_references),
+            params![email_id, position as i64, format!("<{}>", referenced)],
+        )?;
+    }
+
+    for hop in auth::arc_hops(&record.headers) {
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (email_id, instance, authserv_id, sealed_by, chain_validation, dkim, spf, dmarc, arc, results)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                tables.arc_hops
+            ),
+            params![
+                email_id,
+                hop.instance,
+                &hop.authserv_id,
+                &hop.sealed_by,
+                &hop.chain_validation,
+                &hop.dkim,
+                &hop.spf,
+                &hop.dmarc,
+                &hop.arc,
+                &hop.results,
+            ],
+        )?;
+    }
+
+    for rule in &spam.rules {
+        conn.execute(
+            &format!("INSERT OR IGNORE INTO {} (email_id, rule, score, description) VALUES (?1, ?2, ?3, ?4)", tables.spam_rules),
+            params![email_id, &rule.name, rule.score, &rule.description],
+        )?;
+    }
+
+    for order in &record.orders {
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (email_id, merchant, order_number, total, currency, order_date, status, tracking_number, carrier)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                tables.orders
+            ),
+            params![
+                email_id,
+                &order.merchant,
+                &order.order_number,
+                order.total,
+                &order.currency,
+                &order.order_date,
+                &order.status,
+                &order.tracking_number,
+                &order.carrier,
+            ],
+        )?;
+    }
+
+    for reservation in &record.reservations {
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (email_id, kind, reservation_number, status, provider, name, passenger, flight_number, from_place, to_place, start_time, end_time, location, source)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                tables.reservations
+            ),
+            params![
+                email_id,
+                reservation.kind,
+                &reservation.reservation_number,
+                &reservation.status,
+                &reservation.provider,
+                &reservation.name,
+                &reservation.passenger,
+                &reservation.flight_number,
+                &reservation.from_place,
+                &reservation.to_place,
+                &reservation.start_time,
+                &reservation.end_time,
+                &reservation.location,
+                reservation.source,
+            ],
+        )?;
+    }
+
+    if let Some(receipt) = &record.receipt {
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, invoice_number, total, currency, due_date) VALUES (?1, ?2, ?3, ?4, ?5)", tables.receipts),
+            params![email_id, &receipt.invoice_number, receipt.total, &receipt.currency, &receipt.due_date],
+        )?;
+        for (position, amount) in receipt.amounts.iter().enumerate() {
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, position, amount, currency, label) VALUES (?1, ?2, ?3, ?4, ?5)", tables.amounts),
+                params![email_id, position as i64, amount.amount, &amount.currency, &amount.label],
+            )?;
+        }
+    }
+
+    for link in &record.links {
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, url, domain, anchor_text) VALUES (?1, ?2, ?3, ?4)", tables.links),
+            params![email_id, &link.url, &link.domain, &link.anchor_text],
+        )?;
+    }
+
+    for attachment in &record.attachments {
+        let oversized = options.max_attachment_size.is_some_and(|max| attachment.data.len() as u64 > max);
+        let policy = if oversized { options.oversized_attachments } else { OversizedAttachments::Store };
+        if policy == OversizedAttachments::Skip {
+            continue;
+        }
+
+        let sha256 = format!("{:x}", Sha256::digest(&attachment.data));
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, filename, content_type, content_id, size, sha256) VALUES (?1, ?2, ?3, ?4, ?5, ?6)", tables.attachments),
+            params![
+                email_id,
+                &attachment.filename,
+                &attachment.content_type,
+                &attachment.content_id,
+                attachment.data.len() as i64,
+                &sha256,
+            ],
+        )?;
+        if policy == OversizedAttachments::Hash {
+            continue;
+        }
+
+        // Without an explicit --max-attachment-size, a few huge files still can't balloon the database
+        let fits = options.max_attachment_size.is_some() || attachment.data.len() as u64 <= DEFAULT_MAX_BLOB_SIZE;
+        if options.attachments_in_db && fits {
+            // Keyed by hash, so the same file attached to many messages is stored once
+            conn.execute(
+                &format!("INSERT OR IGNORE INTO {} (sha256, data) VALUES (?1, ?2)", tables.attachment_blobs),
+                params![&sha256, &attachment.data],
+            )?;
+        }
+
+        if let Some(text) = attachments::extract_text(attachment) {
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, filename, content_type, text) VALUES (?1, ?2, ?3, ?4)", tables.attachment_text),
+                params![email_id, &attachment.filename, &attachment.content_type, &text],
+            )?;
+        }
+
+        if let (Some(content_id), true) = (&attachment.content_id, attachment.is_inline_image()) {
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, content_id, filename, content_type, data) VALUES (?1, ?2, ?3, ?4, ?5)", tables.inline_images),
+                params![email_id, content_id, &attachment.filename, &attachment.content_type, &attachment.data],
+            )?;
+        }
+
+        for entry in attachments::list_archive(attachment).unwrap_or_default() {
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, archive_filename, entry_name, size) VALUES (?1, ?2, ?3, ?4)", tables.attachment_manifest),
+                params![email_id, &attachment.filename, &entry.name, entry.size as i64],
+            )?;
+        }
+    }
+
+    if options.schema == SchemaProfile::Full {
+        for (position, (name, value)) in record.headers.iter().enumerate() {
+            // Positions stay those in the message, so gaps show where headers were left out
+            if !options.header_filter.keeps(name) {
+                continue;
+            }
+            conn.execute(
+                &format!("INSERT INTO {} (email_id, position, name, value) VALUES (?1, ?2, ?3, ?4)", tables.headers),
+                params![email_id, position as i64, name, value],
+            )?;
+        }
+        conn.execute(
+            &format!("INSERT INTO {} (email_id, raw) VALUES (?1, ?2)", tables.raw_messages),
+            params![email_id, raw_email],
+        )?;
+    }
+
+    Ok(email_id)
+}
+
+static MESSAGE_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"<([^<>\s]+)>").unwrap());
+
+/// Message-IDs in a Message-ID/In-Reply-To/References header, without angle brackets.
+pub(crate) fn message_ids(header_value: &str) -> Vec<String> {
+    let ids: Vec<String> = MESSAGE_ID
+        .captures_iter(header_value)
+        .map(|caps| caps[1].to_string())
+        .collect();
+    if ids.is_empty() {
+        header_value.split_whitespace().map(String::from).collect()
+    } else {
+        ids
+    }
+}
+
+/// The messages this one refers to, oldest first: the References chain, then
+/// any In-Reply-To id it doesn't already include (some clients only send that).
+fn referenced_message_ids(record: &EmailRecord) -> Vec<String> {
+    let mut ids = message_ids(&record.references);
+    for parent in message_ids(&record.in_reply_to) {
+        if !ids.contains(&parent) {
+            ids.push(parent);
+        }
+    }
+    ids
+}
+
+/// Picks the thread a message belongs to, identified by the Message-ID of the
+/// conversation's first message: the oldest entry in References, else the
+/// thread of the message it replies to (when that's already imported), else
+/// the replied-to id, else the message's own id.
+fn thread_id(conn: &Connection, tables: &Tables, record: &EmailRecord) -> Result<String> {
+    if let Some(root) = message_ids(&record.references).into_iter().next() {
+        return Ok(root);
+    }
+
+    if let Some(parent) = message_ids(&record.in_reply_to).into_iter().next() {
+        let parent_thread: Option<String> = conn
+            .query_row(
+                &format!(
+                    "SELECT t.thread_id FROM {} t JOIN {} e ON e.id = t.email_id WHERE e.message_id = ?1",
+                    tables.threads, tables.emails
+                ),
+                [format!("<{}>", parent)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        return Ok(parent_thread.unwrap_or(parent));
+    }
+
+    Ok(message_ids(&record.message_id)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| record.message_hash.clone()))
+}
+
+const PGP_BEGIN: &str = "-----BEGIN PGP MESSAGE-----";
+const PGP_END: &str = "-----END PGP MESSAGE-----";
+
+/// Decrypts PGP messages by piping them through the `gpg` binary.
+///
+/// Secret keys must either be unprotected or have their passphrase cached by gpg-agent,
+/// since decryption runs in batch mode.
+pub struct GpgDecryptor {
+    homedir: PathBuf,
+    /// The throwaway home directory an exported key was imported into,
+    /// deleted when the decryptor is dropped
+    _owned_homedir: Option<TempDir>,
+}
+
+impl GpgDecryptor {
+    /// Uses `keyring` directly when it's a GnuPG home directory, otherwise imports it
+    /// as an exported secret key into a throwaway home directory for this run.
+    pub fn new(keyring: &Path) -> Result<Self> {
+        if keyring.is_dir() {
+            return Ok(Self { homedir: keyring.to_path_buf(), _owned_homedir: None });
+        }
+
+        // A new directory with a random name, only accessible to this user, so
+        // nothing else in the shared temp directory can plant or read keys there
+        let owned = tempfile::Builder::new()
+            .prefix("mbox2db-gnupg-")
+            .tempdir()
+            .context("Failed to create a temporary GnuPG home directory")?;
+        let decryptor = Self { homedir: owned.path().to_path_buf(), _owned_homedir: Some(owned) };
+
+        let output = Command::new("gpg")
+            .arg("--homedir")
+            .arg(&decryptor.homedir)
+            .args(["--batch", "--quiet", "--import"])
+            .arg(keyring)
+            .output()
+            .context("Failed to run gpg (is GnuPG installed?)")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to import secret key {}: {}",
+                keyring.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(decryptor)
+    }
+
+    fn decrypt(&self, armored: &str) -> Result<Vec<u8>> {
+        let mut child = Command::new("gpg")
+            .arg("--homedir")
+            .arg(&self.homedir)
+            .args(["--batch", "--quiet", "--decrypt"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run gpg (is GnuPG installed?)")?;
+
+        // Feed stdin from a separate thread so a large plaintext can't deadlock the pipes
+        let mut stdin = child.stdin.take().context("Failed to open gpg stdin")?;
+        let input = armored.as_bytes().to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+        let mut plaintext = Vec::new();
+        child.stdout.take().context("Failed to open gpg stdout")?.read_to_end(&mut plaintext)?;
+        let output = child.wait_with_output()?;
+        writer.join().map_err(|_| anyhow::anyhow!("gpg stdin writer panicked"))??;
+
+        if !output.status.success() {
+            anyhow::bail!("gpg: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(plaintext)
+    }
+
+    /// Replaces the encrypted body of `record` with its plaintext.
+    /// Returns `Ok(false)` when there's no PGP block to decrypt (e.g. S/MIME).
+    fn decrypt_record(&self, record: &mut EmailRecord, prefer: Prefer) -> Result<bool> {
+        if record.content_type.to_lowercase().contains("multipart/encrypted") {
+            // PGP/MIME: the ciphertext is carried as an attachment, and the plaintext
+            // is itself a MIME entity with its own body and attachments
+            let Some(armored) = record
+                .attachments
+                .iter()
+                .map(|attachment| String::from_utf8_lossy(&attachment.data))
+                .find(|data| data.contains(PGP_BEGIN))
+            else {
+                return Ok(false);
+            };
+
+            let plaintext = self.decrypt(&armored)?;
+            let parsed = parse_mail(&plaintext)?;
+            let mut inner = EmailRecord::default();
+            extract_body(&parsed, &mut inner, prefer);
+            record.body_plain = inner.body_plain;
+            record.body_html = inner.body_html;
+            record.body_plain_raw = inner.body_plain_raw;
+            record.body_html_raw = inner.body_html_raw;
+            record.detected_charset = inner.detected_charset;
+            record.attachments = inner.attachments;
+            record.is_decrypted = true;
+            return Ok(true);
+        }
+
+        let Some(start) = record.body_plain.find(PGP_BEGIN) else {
+            return Ok(false);
+        };
+        let end = record.body_plain[start..]
+            .find(PGP_END)
+            .map(|pos| start + pos + PGP_END.len())
+            .context("Unterminated PGP message block")?;
+
+        let plaintext = self.decrypt(&record.body_plain[start..end])?;
+        let text = String::from_utf8_lossy(&plaintext).into_owned();
+        record.body_plain.replace_range(start..end, &text);
+
+        record.is_decrypted = true;
+        Ok(true)
+    }
+}
+
+pub struct ImportOptions {
+    pub include_spam: bool,
+    pub include_trash: bool,
+    pub include_both: bool,
+    pub chats: Chats,
+    /// Leave out calendar invitations, updates and replies
+    pub skip_calendar: bool,
+    pub aliases: AliasMap,
+    pub decryptor: Option<GpgDecryptor>,
+    pub store_signatures: bool,
+    /// Read amounts, invoice numbers and due dates from receipt-like messages
+    pub extract_amounts: bool,
+    pub fts: bool,
+    pub fts_tokenize: String,
+    pub sanitize_html: bool,
+    pub prefer: Prefer,
+    pub account: Option<String>,
+    pub if_exists: IfExists,
+    /// Appending a newer copy of the same export: also count the messages
+    /// earlier imports hold that this input no longer has
+    pub incremental: bool,
+    /// Copies of a message this run already imported (from another input
+    /// file) add their labels to it instead of a row of their own
+    pub merge_copies: bool,
+    /// Merge copies into the message whichever import it came from, so the
+    /// database holds each message once with the union of its labels
+    pub consolidate_labels: bool,
+    pub schema: SchemaProfile,
+    pub tables: Tables,
+    pub extensions: Vec<PathBuf>,
+    /// `PRAGMA name=value` settings for the import connection
+    pub pragmas: Vec<(String, String)>,
+    pub views: bool,
+    pub post_sql: Vec<PathBuf>,
+    pub split_by: Option<SplitBy>,
+    pub max_attachment_size: Option<u64>,
+    pub oversized_attachments: OversizedAttachments,
+    pub attachments_in_db: bool,
+    pub date_parser: DateParser,
+    pub display_tz: Option<Tz>,
+    pub two_pass: bool,
+    pub space_check: bool,
+    pub busy_timeout: Duration,
+    pub keep_going: bool,
+    pub summary_json: Option<PathBuf>,
+    pub warnings: Option<WarningLog>,
+    pub header_filter: HeaderFilter,
+    /// My own addresses, for the importance score
+    pub me: Vec<String>,
+}
+
+impl ImportOptions {
+    /// Reports a problem with one message: in the --warnings-file if there is
+    /// one, otherwise on the console if it's one worth interrupting for.
+    fn warn(&self, spinner: &ProgressBar, position: Position, message_id: &str, warning: Warning) -> Result<()> {
+        match &self.warnings {
+            Some(log) => log.write(position, message_id, &warning),
+            None => {
+                if let Some(line) = warning.console_line(position.number, &self.tables) {
+                    spinner.println(line);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Warns about what the source skipped before the message at `position`.
+fn warn_skipped(options: &ImportOptions, spinner: &ProgressBar, position: Position, skipped: &[Skipped]) -> Result<()> {
+    for skipped in skipped {
+        options.warn(spinner, position, "", Warning::SkippedInput(skipped.clone()))?;
+    }
+    Ok(())
+}
+
+/// Something wrong with one message, short of stopping the import.
+pub(crate) enum Warning {
+    ParseFailed(String),
+    DecryptFailed(String),
+    InsertFailed(String),
+    /// Dated from a Received header or the envelope instead of the Date header
+    DateFallback { source: &'static str, header: String },
+    Undated,
+    /// A body's declared charset was missing or wrong
+    DetectedCharset(&'static str),
+    /// A body still had invalid bytes after decoding
+    InvalidBytes,
+    /// The mbox had something before the message that isn't part of one
+    SkippedInput(Skipped),
+}
+
+impl Warning {
+    fn kind(&self) -> &'static str {
+        match self {
+            Warning::ParseFailed(_) => "parse_failed",
+            Warning::DecryptFailed(_) => "decrypt_failed",
+            Warning::InsertFailed(_) => "insert_failed",
+            Warning::DateFallback { .. } => "date_fallback",
+            Warning::Undated => "undated",
+            Warning::DetectedCharset(_) => "detected_charset",
+            Warning::InvalidBytes => "invalid_bytes",
+            Warning::SkippedInput(_) => "skipped_input",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Warning::ParseFailed(error) | Warning::DecryptFailed(error) | Warning::InsertFailed(error) => error.clone(),
+            Warning::DateFallback { source, header } if header.trim().is_empty() => format!("No Date header; used the {} date", source),
+            Warning::DateFallback { source, header } => format!("Used the {} date instead of the Date header {:?}", source, header),
+            Warning::Undated => "No date in the Date or Received headers or the envelope".to_string(),
+            Warning::DetectedCharset(charset) => format!("Decoded as {}, detected from the bytes", charset),
+            Warning::InvalidBytes => "Invalid bytes for its charset; the original body is kept in body_plain_raw/body_html_raw".to_string(),
+            Warning::SkippedInput(Skipped::ByteOrderMark) => "Skipped a UTF-8 byte order mark at the start of the input".to_string(),
+            Warning::SkippedInput(Skipped::Prefix { bytes, preview }) => format!("Skipped {} bytes before the first message: {:?}", bytes, preview),
+        }
+    }
+
+    /// What's printed without a --warnings-file; the lesser warnings aren't.
+    fn console_line(&self, number: usize, tables: &Tables) -> Option<String> {
+        match self {
+            Warning::ParseFailed(error) => Some(format!("Warning: Failed to parse email {}: {}", number, error)),
+            Warning::DecryptFailed(error) => Some(format!("Warning: Failed to decrypt email {}: {}", number, error)),
+            Warning::InsertFailed(error) => Some(format!("Warning: Failed to insert email {} (logged in {}): {}", number, tables.insert_errors, error)),
+            Warning::SkippedInput(_) => Some(format!("Warning: {}", self.detail())),
+            _ => None,
+        }
+    }
+}
+
+/// The --warnings-file: one JSON object per warning, per line.
+pub struct WarningLog {
+    path: PathBuf,
+    out: Mutex<BufWriter<File>>,
+    count: AtomicUsize,
+}
+
+impl WarningLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to create warnings file: {}", path.display()))?;
+        Ok(Self { path: path.to_path_buf(), out: Mutex::new(BufWriter::new(file)), count: AtomicUsize::new(0) })
+    }
+
+    fn write(&self, position: Position, message_id: &str, warning: &Warning) -> Result<()> {
+        let message_id = message_id.trim();
+        let line = serde_json::json!({
+            "message": position.number,
+            "offset": position.offset,
+            "message_id": (!message_id.is_empty()).then_some(message_id),
+            "kind": warning.kind(),
+            "detail": warning.detail(),
+        });
+        let mut out = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writeln!(out, "{}", line).with_context(|| format!("Failed to write {}", self.path.display()))?;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<usize> {
+        let mut out = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        out.flush().with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(self.count.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Default)]
+pub struct ImportCounts {
+    pub imported: usize,
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub failed: usize,
+    /// Of `duplicates`, copies whose labels were added to the imported message
+    pub merged: usize,
+    /// Stored in the chats table (--chats table) rather than imported as emails
+    pub chats: usize,
+    /// Of `skipped`, how many each filter left out
+    pub skipped_spam: usize,
+    pub skipped_trash: usize,
+    pub skipped_chats: usize,
+    pub skipped_calendar: usize,
+    /// Of `failed`, those parsed but not inserted (--keep-going)
+    pub insert_failed: usize,
+    /// Imported with no date from any header or the envelope
+    pub undated: usize,
+    /// With --incremental, messages already in the database (for the same
+    /// account) that weren't in the input
+    pub removed: usize,
+    /// Warnings short of a failure: a date from elsewhere than the Date
+    /// header or none at all, a detected charset, invalid bytes, a failed
+    /// decryption, or input skipped before a message
+    pub warnings: usize,
+}
+
+fn sqlite_timestamp() -> String {
+    Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Returns true when `table` (emails or chats) already holds this message,
+/// matched by Message-ID or, for messages without one, by the hash of the raw
+/// message.
+fn is_duplicate(conn: &Connection, table: &str, record: &EmailRecord) -> Result<bool> {
+    let exists = if record.message_id.trim().is_empty() {
+        conn.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE message_hash = ?1)", table),
+            [&record.message_hash],
+            |row| row.get(0),
+        )?
+    } else {
+        conn.query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE message_id = ?1)", table),
+            [record.message_id.trim()],
+            |row| row.get(0),
+        )?
+    };
+    Ok(exists)
+}
+
+/// The message already imported that `record` is a copy of: by the run
+/// `run_id`, or by any import when it's None.
+fn imported_copy(conn: &Connection, tables: &Tables, record: &EmailRecord, run_id: Option<i64>) -> Result<Option<i64>> {
+    let (column, key) = match record.message_id.trim() {
+        "" => ("message_hash", record.message_hash.as_str()),
+        message_id => ("message_id", message_id),
+    };
+    let email_id = conn
+        .query_row(
+            &format!("SELECT id FROM {} WHERE {} = ?1 AND (?2 IS NULL OR import_run_id = ?2) ORDER BY id LIMIT 1", tables.emails, column),
+            params![key, run_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(email_id)
+}
+
+/// Adds the labels of another copy of a message to it, and its Gmail
+/// category if it had none.
+fn merge_labels(conn: &Connection, options: &ImportOptions, email_id: i64, record: &EmailRecord) -> Result<()> {
+    let tables = &options.tables;
+    conn.execute(
+        &format!("UPDATE {} SET gmail_category = COALESCE(gmail_category, ?1) WHERE id = ?2", tables.emails),
+        params![gmail_category(&record.gmail_labels), email_id],
+    )?;
+    if options.schema == SchemaProfile::Flat {
+        return Ok(());
+    }
+    for label in record.gmail_labels.split(',').map(str::trim).filter(|label| !label.is_empty()) {
+        conn.execute(
+            &format!(
+                "INSERT INTO {labels} (email_id, label) SELECT ?1, ?2
+                 WHERE NOT EXISTS (SELECT 1 FROM {labels} WHERE email_id = ?1 AND label = ?2)",
+                labels = tables.labels
+            ),
+            params![email_id, label],
+        )?;
+    }
+    Ok(())
+}
+
+/// For --incremental: notes that the input still has the database's copies
+/// of this message.
+fn mark_seen(conn: &Connection, tables: &Tables, record: &EmailRecord) -> Result<()> {
+    if record.message_id.trim().is_empty() {
+        conn.execute(
+            &format!("INSERT OR IGNORE INTO temp.incremental_seen SELECT id FROM {} WHERE message_hash = ?1", tables.emails),
+            [&record.message_hash],
+        )?;
+    } else {
+        conn.execute(
+            &format!("INSERT OR IGNORE INTO temp.incremental_seen SELECT id FROM {} WHERE message_id = ?1", tables.emails),
+            [record.message_id.trim()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Messages from earlier imports for `account` that [`mark_seen`] didn't see.
+fn count_removed(conn: &Connection, tables: &Tables, run_id: i64, account: Option<&str>) -> Result<usize> {
+    let removed: i64 = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM {} WHERE import_run_id IS NOT ?1 AND account IS ?2
+             AND id NOT IN (SELECT email_id FROM temp.incremental_seen)",
+            tables.emails
+        ),
+        params![run_id, account],
+        |row| row.get(0),
+    )?;
+    Ok(removed as usize)
+}
+
+/// Records the start of an import in `import_runs` and returns its id.
+fn start_import_run(conn: &Connection, tables: &Tables, input: &str) -> Result<i64> {
+    let arguments = serde_json::to_string(&std::env::args().collect::<Vec<_>>())?;
+    conn.execute(
+        &format!("INSERT INTO {} (tool_version, arguments, input_path, started_at) VALUES (?1, ?2, ?3, ?4)", tables.import_runs),
+        params![env!("CARGO_PKG_VERSION"), &arguments, input, sqlite_timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn finish_import_run(conn: &Connection, tables: &Tables, run_id: i64, digest: Option<&InputDigest>, counts: &ImportCounts) -> Result<()> {
+    conn.execute(
+        &format!(
+            "UPDATE {}
+             SET input_sha256 = ?1, input_bytes = ?2, finished_at = ?3, imported_count = ?4, skipped_count = ?5, duplicate_count = ?6, failed_count = ?7
+             WHERE id = ?8",
+            tables.import_runs
+        ),
+        params![
+            digest.map(|digest| &digest.sha256),
+            digest.map(|digest| digest.bytes as i64),
+            sqlite_timestamp(),
+            counts.imported as i64,
+            counts.skipped as i64,
+            counts.duplicates as i64,
+            counts.failed as i64,
+            run_id,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Messages queued between two import stages. A slow stage (usually the
+/// writer) makes the ones before it wait, so memory use doesn't grow with the
+/// size of the input.
+const PIPELINE_DEPTH: usize = 32;
+
+/// A message read from the source, owned so it can move between stages.
+struct QueuedMessage {
+    position: Position,
+    data: Vec<u8>,
+    folder: Option<String>,
+}
+
+/// Where a message is in the input, for warnings.
+#[derive(Clone, Copy)]
+pub(crate) struct Position {
+    /// Counting from 1
+    pub number: usize,
+    /// In bytes, for mbox files
+    pub offset: Option<u64>,
+}
+
+/// What the parse and transform stages made of a message.
+pub(crate) enum Prepared {
+    Failed(anyhow::Error),
+    Skipped(Filter),
+    Ready(Box<EmailRecord>),
+}
+
+/// The filter that kept a message out of the database.
+#[derive(Clone, Copy)]
+pub(crate) enum Filter {
+    Spam,
+    Trash,
+    /// --chats skip
+    Chat,
+    /// --skip-calendar
+    Calendar,
+}
+
+/// The parse stage: headers, bodies and attachments of a raw message.
+pub(crate) fn parse_message(raw_email: &[u8], folder: Option<&str>, prefer: Prefer) -> Result<EmailRecord> {
+    let mut record = extract_email_data(raw_email, prefer)?;
+    // Folder-based sources (IMAP) have no X-Gmail-Labels; the folder stands in for it
+    if record.gmail_labels.is_empty() {
+        if let Some(folder) = folder {
+            record.gmail_labels = folder.to_string();
+        }
+    }
+    Ok(record)
+}
+
+/// The transform stage: Spam/Trash filtering, decryption and body cleanup.
+pub(crate) fn prepare_record(parsed: Result<EmailRecord>, raw_email: &[u8], options: &ImportOptions) -> Prepared {
+    let mut record = match parsed {
+        Ok(record) => record,
+        Err(e) => return Prepared::Failed(e),
+    };
+
+    if let Some(filter) = should_skip_email(&record.gmail_labels, options.include_spam, options.include_trash, options.include_both) {
+        return Prepared::Skipped(filter);
+    }
+
+    record.message_hash = format!("{:x}", Sha256::digest(raw_email));
+
+    if options.skip_calendar && is_calendar_notification(record.calendar_method.as_deref()) {
+        return Prepared::Skipped(Filter::Calendar);
+    }
+
+    if options.chats != Chats::Email && chat::is_chat(&record.gmail_labels) {
+        if options.chats == Chats::Skip {
+            return Prepared::Skipped(Filter::Chat);
+        }
+        let body = if record.body_plain.is_empty() { text::html_to_text(&record.body_html) } else { record.body_plain.clone() };
+        let sender = match split_addresses(&record.from).into_iter().next() {
+            Some((address, name)) => name.unwrap_or(address),
+            None => record.from.clone(),
+        };
+        record.chat = Some(chat::transcript(&body, &sender));
+        return Prepared::Ready(Box::new(record));
+    }
+
+    if record.is_encrypted {
+        if let Some(decryptor) = &options.decryptor {
+            if let Err(e) = decryptor.decrypt_record(&mut record, options.prefer) {
+                record.decrypt_error = Some(e.to_string());
+            }
+        }
+    }
+
+    // Before sanitizing, which strips the JSON-LD scripts along with the rest
+    if options.schema != SchemaProfile::Flat {
+        let items = markup::items(&record.body_html);
+        record.orders = markup::orders(&items);
+        record.reservations = markup::reservations(&items);
+        if record.reservations.is_empty() {
+            // Many airlines send confirmations with no markup at all
+            let body = if record.body_plain.is_empty() { text::html_to_text(&record.body_html) } else { record.body_plain.clone() };
+            let sender = split_addresses(&record.from).into_iter().next().and_then(|(_, name)| name);
+            record.reservations = markup::flight_confirmations(&body, sender.as_deref());
+        }
+        record.links = text::extract_links(&record.body_plain, &record.body_html);
+    }
+
+    if options.sanitize_html && !record.body_html.is_empty() {
+        record.body_html = render::sanitize_html(&record.body_html);
+    }
+
+    let (body_clean, signature) = text::clean_body(&record.body_plain, &record.body_html);
+    record.body_clean = body_clean;
+    if options.store_signatures {
+        record.signature = signature;
+    }
+
+    if options.extract_amounts {
+        let body = if record.body_plain.is_empty() { text::html_to_text(&record.body_html) } else { record.body_plain.clone() };
+        record.receipt = money::receipt(&record.subject, &body);
+    }
+
+    Prepared::Ready(Box::new(record))
+}
+
+/// The rest of the transform stage: the message's date, from its Date header
+/// or, when that's missing or implausible, its Received headers or envelope.
+pub(crate) fn resolve_date(prepared: &mut Prepared, raw_email: &[u8], date_parser: &DateParser) {
+    let Prepared::Ready(record) = prepared else {
+        return;
+    };
+    let received: Vec<&str> = record
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("received"))
+        .map(|(_, value)| value.as_str())
+        .collect();
+    record.message_date = date_parser.message_date(&record.date, &received, envelope_line(raw_email));
+}
+
+/// The write stage: duplicate check and insert, counted in `counts`.
+#[allow(clippy::too_many_arguments)]
+fn write_message(conn: &Connection, raw_email: &[u8], prepared: Prepared, position: Position, options: &ImportOptions, run_id: i64, counts: &mut ImportCounts, spinner: &ProgressBar) -> Result<()> {
+    let number = position.number;
+    spinner.set_position(number as u64);
+    let warn = |message_id: &str, warning: Warning| options.warn(spinner, position, message_id, warning);
+    let record = match prepared {
+        Prepared::Failed(e) => {
+            counts.failed += 1;
+            warn("", Warning::ParseFailed(e.to_string()))?;
+            return Ok(());
+        }
+        Prepared::Skipped(filter) => {
+            counts.skipped += 1;
+            match filter {
+                Filter::Spam => counts.skipped_spam += 1,
+                Filter::Trash => counts.skipped_trash += 1,
+                Filter::Chat => counts.skipped_chats += 1,
+                Filter::Calendar => counts.skipped_calendar += 1,
+            }
+            return Ok(());
+        }
+        Prepared::Ready(record) => record,
+    };
+    if let Some(error) = &record.decrypt_error {
+        counts.warnings += 1;
+        warn(&record.message_id, Warning::DecryptFailed(error.clone()))?;
+    }
+
+    if let Some(lines) = &record.chat {
+        if options.if_exists == IfExists::Append && is_duplicate(conn, &options.tables.chats, &record)? {
+            counts.duplicates += 1;
+        } else {
+            insert_chat(conn, &options.tables, &record, lines, options.account.as_deref(), run_id)?;
+            counts.chats += 1;
+        }
+        return Ok(());
+    }
+
+    if options.merge_copies || options.consolidate_labels {
+        let run = (!options.consolidate_labels).then_some(run_id);
+        if let Some(email_id) = imported_copy(conn, &options.tables, &record, run)? {
+            if options.incremental {
+                mark_seen(conn, &options.tables, &record)?;
+            }
+            merge_labels(conn, options, email_id, &record)?;
+            counts.duplicates += 1;
+            counts.merged += 1;
+            return Ok(());
+        }
+    }
+
+    if options.if_exists == IfExists::Append && is_duplicate(conn, &options.tables.emails, &record)? {
+        if options.incremental {
+            mark_seen(conn, &options.tables, &record)?;
+        }
+        counts.duplicates += 1;
+        return Ok(());
+    }
+
+    if !options.keep_going {
+        insert_email(conn, raw_email, &record, options, run_id)?;
+        count_imported(counts, &record);
+        counts.warnings += warn_imported(&record, &warn)?;
+        return Ok(());
+    }
+
+    // A savepoint per message, so a failed insert takes back just its own rows
+    conn.execute_batch("SAVEPOINT message")?;
+    match insert_email(conn, raw_email, &record, options, run_id) {
+        Ok(_) => {
+            conn.execute_batch("RELEASE message")?;
+            count_imported(counts, &record);
+            counts.warnings += warn_imported(&record, &warn)?;
+        }
+        Err(e) if schema::is_fatal(&e) => return Err(e),
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TO message; RELEASE message")?;
+            record_insert_error(conn, &options.tables, run_id, number, &record, &e)?;
+            counts.failed += 1;
+            counts.insert_failed += 1;
+            warn(&record.message_id, Warning::InsertFailed(format!("{:#}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores a chat and its transcript, in place of an email.
+fn insert_chat(conn: &Connection, tables: &Tables, record: &EmailRecord, lines: &[ChatLine], account: Option<&str>, run_id: i64) -> Result<()> {
+    let mut participants: Vec<String> = Vec::new();
+    add_addresses(&mut participants, &record.from);
+    add_addresses(&mut participants, &record.to);
+    add_addresses(&mut participants, &record.cc);
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (message_id, subject, date_parsed, participants, account, import_run_id, message_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            tables.chats
+        ),
+        params![
+            &record.message_id,
+            &record.subject,
+            record.message_date.as_ref().map(|(date, _)| date.to_sql()),
+            participants.join(", "),
+            account,
+            run_id,
+            &record.message_hash,
+        ],
+    )?;
+    let chat_id = conn.last_insert_rowid();
+    for (position, line) in lines.iter().enumerate() {
+        conn.execute(
+            &format!("INSERT INTO {} (chat_id, position, time, sender, text) VALUES (?1, ?2, ?3, ?4, ?5)", tables.chat_lines),
+            params![chat_id, position as i64, &line.time, &line.sender, &line.text],
+        )?;
+    }
+    Ok(())
+}
+
+/// Warns about the imported message's lesser problems, which only go in the
+/// --warnings-file, and returns how many it had.
+fn warn_imported(record: &EmailRecord, warn: &impl Fn(&str, Warning) -> Result<()>) -> Result<usize> {
+    let mut warnings = Vec::new();
+    match &record.message_date {
+        None => warnings.push(Warning::Undated),
+        Some((_, "header")) => {}
+        Some((_, source)) => warnings.push(Warning::DateFallback { source, header: record.date.clone() }),
+    }
+    if let Some(charset) = record.detected_charset {
+        warnings.push(Warning::DetectedCharset(charset));
+    }
+    if record.body_plain_raw.is_some() || record.body_html_raw.is_some() {
+        warnings.push(Warning::InvalidBytes);
+    }
+    let count = warnings.len();
+    for warning in warnings {
+        warn(&record.message_id, warning)?;
+    }
+    Ok(count)
+}
+
+fn count_imported(counts: &mut ImportCounts, record: &EmailRecord) {
+    counts.imported += 1;
+    if record.message_date.is_none() {
+        counts.undated += 1;
+    }
+}
+
+fn record_insert_error(conn: &Connection, tables: &Tables, run_id: i64, number: usize, record: &EmailRecord, error: &anyhow::Error) -> Result<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (import_run_id, message_number, message_id, from_addr, subject, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            tables.insert_errors
+        ),
+        params![run_id, number as i64, &record.message_id, &record.from, &record.subject, format!("{:#}", error)],
+    )?;
+    Ok(())
+}
+
+/// How often the progress line's counts and rates are refreshed.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Throughput for the progress line, measured over the last interval rather
+/// than the whole run so a slowdown shows up right away.
+struct Throughput {
+    since: Instant,
+    messages: u64,
+    bytes: u64,
+}
+
+impl Throughput {
+    fn new() -> Self {
+        Self { since: Instant::now(), messages: 0, bytes: 0 }
+    }
+
+    /// Counts a message of `bytes`; once per interval returns the messages
+    /// and bytes per second since the last time it did.
+    fn add(&mut self, bytes: usize) -> Option<(f64, f64)> {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+        let elapsed = self.since.elapsed();
+        if elapsed < PROGRESS_INTERVAL {
+            return None;
+        }
+        let seconds = elapsed.as_secs_f64();
+        let rates = (self.messages as f64 / seconds, self.bytes as f64 / seconds);
+        *self = Self::new();
+        Some(rates)
+    }
+}
+
+fn total_counts<'a>(importers: impl Iterator<Item = &'a Importer>) -> ImportCounts {
+    let mut counts = ImportCounts::default();
+    for importer in importers {
+        counts.imported += importer.counts.imported;
+        counts.skipped += importer.counts.skipped;
+        counts.duplicates += importer.counts.duplicates;
+        counts.failed += importer.counts.failed;
+        counts.skipped_spam += importer.counts.skipped_spam;
+        counts.skipped_trash += importer.counts.skipped_trash;
+        counts.skipped_chats += importer.counts.skipped_chats;
+        counts.skipped_calendar += importer.counts.skipped_calendar;
+        counts.chats += importer.counts.chats;
+        counts.insert_failed += importer.counts.insert_failed;
+        counts.undated += importer.counts.undated;
+        counts.removed += importer.counts.removed;
+        counts.merged += importer.counts.merged;
+        counts.warnings += importer.counts.warnings;
+    }
+    counts
+}
+
+/// Runs the --post-sql scripts in one transaction, so a failing script leaves
+/// the (already committed) import untouched.
+fn run_post_sql(conn: &mut Connection, scripts: &[PathBuf]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for script in scripts {
+        let sql = std::fs::read_to_string(script)
+            .with_context(|| format!("Failed to read SQL script: {}", script.display()))?;
+        tx.execute_batch(&sql).with_context(|| {
+            format!("SQL script failed: {} (the import itself was saved; no script changes were applied)", script.display())
+        })?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn should_skip_email(labels: &str, include_spam: bool, include_trash: bool, include_both: bool) -> Option<Filter> {
+    if include_both {
+        return None; // Include everything
+    }
+    
+    let labels_lower = labels.to_lowercase();
+    let is_spam = labels_lower.contains("spam");
+    let is_trash = labels_lower.contains("trash");
+    
+    if is_spam && !include_spam && !include_both {
+        return Some(Filter::Spam);
+    }
+    
+    if is_trash && !include_trash && !include_both {
+        return Some(Filter::Trash);
+    }
+    
+    None
+}
+
+/// An exclusive hold on `<database>.lock`, so imports into the same database
+/// run one after another rather than interleaving (or one overwriting the
+/// database under the other). The OS releases it if the process dies, so it
+/// can't go stale; the empty file is left in place.
+struct ImportLock {
+    _file: File,
+}
+
+impl ImportLock {
+    /// Waits for any other import into `database` to finish.
+    fn acquire(database: &Path, spinner: &ProgressBar) -> Result<Self> {
+        let mut path = database.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create lock file: {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                spinner.println(format!("Waiting for another import into {} to finish...", database.display()));
+                file.lock().with_context(|| format!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e).with_context(|| format!("Failed to lock {}", path.display())),
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// One import in progress: everything added goes into a single transaction
+/// that `commit` completes. Dropping it before then rolls the import back.
+pub struct Importer {
+    conn: Connection,
+    options: Arc<ImportOptions>,
+    run_id: i64,
+    counts: ImportCounts,
+    spinner: ProgressBar,
+    // Dropped after `conn`, so the lock outlives the transaction
+    _lock: ImportLock,
+}
+
+impl Importer {
+    /// Creates (or opens) the database, applying the --if-exists policy, and
+    /// starts an import run for `input`.
+    pub fn begin(output_path: &Path, options: Arc<ImportOptions>, input: &str, spinner: ProgressBar) -> Result<Self> {
+        let lock = ImportLock::acquire(output_path, &spinner)?;
+        prepare_output(output_path, options.if_exists)?;
+        let locked = |error: anyhow::Error| {
+            if !schema::is_busy(&error) {
+                return error;
+            }
+            error.context(format!(
+                "{} is locked by another process (waited {}s; raise --busy-timeout to wait longer)",
+                output_path.display(),
+                options.busy_timeout.as_secs()
+            ))
+        };
+        let conn = schema::create_database(output_path, &options.tables, options.schema, &options.extensions, &options.pragmas, options.busy_timeout)
+            .map_err(locked)?;
+        // Take the write lock now, where waiting for it is possible; a deferred
+        // transaction that later finds another writer fails without waiting
+        schema::retry_busy(options.busy_timeout, || conn.execute_batch("BEGIN IMMEDIATE")).map_err(|e| locked(e.into()))?;
+        let run_id = start_import_run(&conn, &options.tables, input)?;
+        if options.incremental {
+            conn.execute_batch("CREATE TEMP TABLE incremental_seen (email_id INTEGER PRIMARY KEY)")?;
+        }
+        Ok(Self { conn, options, run_id, counts: ImportCounts::default(), spinner, _lock: lock })
+    }
+
+    /// Runs every stage for one message, in the calling thread.
+    pub fn add(&mut self, message: RawMessage) -> Result<()> {
+        let counts = &self.counts;
+        let number = counts.imported + counts.chats + counts.skipped + counts.duplicates + counts.failed + 1;
+        let position = Position { number, offset: message.offset };
+        warn_skipped(&self.options, &self.spinner, position, message.skipped)?;
+        self.counts.warnings += message.skipped.len();
+        let parsed = parse_message(message.data, message.folder, self.options.prefer);
+        let mut prepared = prepare_record(parsed, message.data, &self.options);
+        resolve_date(&mut prepared, message.data, &self.options.date_parser);
+        self.write(message.data, prepared, position)
+    }
+
+    pub(crate) fn write(&mut self, raw_email: &[u8], prepared: Prepared, position: Position) -> Result<()> {
+        write_message(&self.conn, raw_email, prepared, position, &self.options, self.run_id, &mut self.counts, &self.spinner)
+    }
+
+    pub fn counts(&self) -> &ImportCounts {
+        &self.counts
+    }
+
+    /// Records the run's totals, builds the FTS index and views, and commits.
+    /// `digest` is None when the input wasn't read to the end.
+    pub fn commit(&mut self, digest: Option<&InputDigest>) -> Result<()> {
+        // Only a complete read shows what's gone from the input
+        if self.options.incremental && digest.is_some() {
+            self.counts.removed = count_removed(&self.conn, &self.options.tables, self.run_id, self.options.account.as_deref())?;
+        }
+        finish_import_run(&self.conn, &self.options.tables, self.run_id, digest, &self.counts)?;
+
+        if self.options.fts {
+            self.spinner.set_message("Building full-text index...");
+            self.spinner.tick();
+            schema::build_fts_index(&self.conn, &self.options.tables, self.options.schema, &self.options.fts_tokenize)?;
+        }
+
+        if !self.options.me.is_empty() {
+            self.spinner.set_message("Scoring importance...");
+            self.spinner.tick();
+            let me: Vec<String> = self.options.me.iter().map(|address| self.options.aliases.resolve(&address.trim().to_lowercase())).collect();
+            score_importance(&self.conn, &self.options.tables, &me)?;
+        }
+
+        if self.options.views {
+            schema::create_views(&self.conn, &self.options.tables, self.options.schema)?;
+        }
+
+        self.spinner.set_message("Committing to database...");
+        self.spinner.tick();
+        schema::retry_busy(self.options.busy_timeout, || self.conn.execute_batch("COMMIT"))?;
+        if let Some(log) = &self.options.warnings {
+            log.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Runs the --post-sql scripts, once the import has been committed.
+    pub fn run_post_sql(&mut self) -> Result<()> {
+        if !self.options.post_sql.is_empty() {
+            self.spinner.set_message("Running post-import SQL...");
+            self.spinner.tick();
+            run_post_sql(&mut self.conn, &self.options.post_sql)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets `importance`, 0-100, for every message in the database (what's just
+/// been imported changes who my contacts are and which threads I'm in):
+/// 30 if it was sent to me (15 if I was only cc'd), 25 if it's from someone
+/// I've written to, 25 if I wrote in its thread (not counting my own
+/// auto-replies), and 20 if it isn't bulk or
+/// automated mail or in one of Gmail's non-primary tabs. `me` holds canonical
+/// contacts.
+fn score_importance(conn: &Connection, tables: &Tables, me: &[String]) -> Result<()> {
+    let me_values = vec!["(?)"; me.len()].join(", ");
+    conn.execute(
+        &format!(
+            "WITH me(address) AS (VALUES {me_values}),
+             contacts AS (
+                 SELECT DISTINCT r.canonical_contact FROM {recipients} r JOIN {emails} e ON e.id = r.email_id
+                 WHERE e.canonical_contact IN me
+             ),
+             my_threads AS (
+                 SELECT DISTINCT t.thread_id FROM {threads} t JOIN {emails} e ON e.id = t.email_id
+                 WHERE e.canonical_contact IN me AND NOT e.is_auto_reply
+             )
+             UPDATE {emails} SET importance =
+                 CASE
+                     WHEN EXISTS (SELECT 1 FROM {recipients} r WHERE r.email_id = {emails}.id AND r.kind = 'to' AND r.canonical_contact IN me) THEN 30
+                     WHEN EXISTS (SELECT 1 FROM {recipients} r WHERE r.email_id = {emails}.id AND r.canonical_contact IN me) THEN 15
+                     ELSE 0
+                 END
+                 + CASE WHEN canonical_contact IN contacts AND canonical_contact NOT IN me THEN 25 ELSE 0 END
+                 + CASE WHEN (SELECT thread_id FROM {threads} WHERE email_id = {emails}.id) IN my_threads THEN 25 ELSE 0 END
+                 + CASE WHEN NOT is_bulk AND NOT is_automated AND coalesce(gmail_category, 'primary') = 'primary' THEN 20 ELSE 0 END",
+            emails = tables.emails,
+            recipients = tables.recipients,
+            threads = tables.threads,
+        ),
+        rusqlite::params_from_iter(me),
+    )?;
+    Ok(())
+}
+
+/// Applies the --if-exists policy to an existing output database before the import starts.
+fn prepare_output(output_path: &Path, policy: IfExists) -> Result<()> {
+    if !output_path.exists() {
+        return Ok(());
+    }
+
+    match policy {
+        IfExists::Fail => anyhow::bail!(
+            "Output database already exists: {} (pass --if-exists overwrite or --if-exists append)",
+            output_path.display()
+        ),
+        IfExists::Overwrite => {
+            std::fs::remove_file(output_path)
+                .with_context(|| format!("Failed to remove existing database: {}", output_path.display()))?;
+            // Stale WAL files would otherwise be replayed into the new database
+            for suffix in ["-wal", "-shm"] {
+                let mut sidecar = output_path.as_os_str().to_owned();
+                sidecar.push(suffix);
+                let _ = std::fs::remove_file(PathBuf::from(sidecar));
+            }
+            Ok(())
+        }
+        IfExists::Append => Ok(()),
+    }
+}
+
+/// Database bytes per mbox byte, roughly: the text columns and their indexes
+/// (attachment-heavy mail comes out well below this, plain text a bit above).
+const DATABASE_SIZE_RATIO: f64 = 1.0;
+/// What --fts, --schema full (raw_messages) and --attachments-in-db add to it.
+const FTS_SIZE_RATIO: f64 = 0.5;
+const RAW_MESSAGES_SIZE_RATIO: f64 = 1.0;
+const ATTACHMENT_BLOBS_SIZE_RATIO: f64 = 1.0;
+
+/// Rough size the import of `input_bytes` of mail will add to the database.
+fn estimated_database_size(input_bytes: u64, options: &ImportOptions) -> u64 {
+    let mut ratio = DATABASE_SIZE_RATIO;
+    if options.fts {
+        ratio += FTS_SIZE_RATIO;
+    }
+    if options.schema == SchemaProfile::Full {
+        ratio += RAW_MESSAGES_SIZE_RATIO;
+    }
+    if options.attachments_in_db {
+        ratio += ATTACHMENT_BLOBS_SIZE_RATIO;
+    }
+    (input_bytes as f64 * ratio) as u64
+}
+
+/// Refuses to start an import that would probably fill the disk, instead of
+/// failing hours in with SQLite's "database or disk is full", and warns when
+/// it's close: the whole import is one transaction, so until the commit the
+/// write-ahead log can take as much space again as the new rows.
+fn check_disk_space(output_path: &Path, input_bytes: u64, options: &ImportOptions, spinner: &ProgressBar) -> Result<()> {
+    // The output's directory may not exist yet
+    let Some(dir) = output_path.ancestors().skip(1).find(|dir| dir.is_dir() || dir.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Some(free) = free_space(dir) else {
+        return Ok(());
+    };
+
+    let needed = estimated_database_size(input_bytes, options);
+    if free < needed {
+        anyhow::bail!(
+            "Not enough free space in {}: the import needs roughly {} and only {} is free \
+             (free some space, write elsewhere with -o, or pass --no-space-check)",
+            dir.display(),
+            report::format_bytes(needed as i64),
+            report::format_bytes(free as i64)
+        );
+    }
+    if free < needed.saturating_mul(2) {
+        spinner.println(format!(
+            "Warning: only {} free in {} for an import of roughly {}; it may run out of space while committing",
+            report::format_bytes(free as i64),
+            dir.display(),
+            report::format_bytes(needed as i64)
+        ));
+    }
+    Ok(())
+}
+
+/// Bytes available to this user on the filesystem holding `dir`.
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is NUL-terminated and stat is a statvfs for it to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::useless_conversion)] // the field types differ between platforms
+    Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// The mbox "From sender date" line a message starts with, if it has one.
+fn envelope_line(raw_email: &[u8]) -> Option<&str> {
+    let line = raw_email.split(|&b| b == b'\n').next()?;
+    std::str::from_utf8(line).ok().filter(|line| line.starts_with("From "))
+}
+
+/// Which output database a message belongs in under --split-by, e.g. "2019".
+fn split_key(raw_email: &[u8], split_by: SplitBy, date_parser: &DateParser) -> String {
+    match split_by {
+        SplitBy::Year => parse_headers(raw_email)
+            .ok()
+            .and_then(|(headers, _)| {
+                let received = headers.get_all_values("Received");
+                let received: Vec<&str> = received.iter().map(String::as_str).collect();
+                let date = headers.get_first_value("Date").unwrap_or_default();
+                date_parser.message_date(&date, &received, envelope_line(raw_email))
+            })
+            .map(|(date, _)| date.utc().format("%Y").to_string())
+            .unwrap_or_else(|| "undated".to_string()),
+    }
+}
+
+/// `emails.db` split by year becomes `2019-emails.db`, `2020-emails.db`, …
+fn split_path(output_path: &Path, key: &str) -> PathBuf {
+    let name = output_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    output_path.with_file_name(format!("{}-{}", key, name))
+}
+
+/// How an import ended, beyond succeeding or failing outright.
+pub struct ImportOutcome {
+    pub input: String,
+    /// None if the input wasn't read to the end
+    pub input_bytes: Option<u64>,
+    pub counts: ImportCounts,
+    /// Each database written and the emails imported into it
+    pub databases: Vec<(PathBuf, usize)>,
+    pub timings: ImportTimings,
+    /// Stopped by Ctrl-C; what was read before it is committed
+    pub interrupted: bool,
+}
+
+/// Wall-clock time of each phase of `run_import`.
+#[derive(Default)]
+pub struct ImportTimings {
+    /// The --two-pass count
+    pub count: Duration,
+    /// Reading, parsing and inserting every message
+    pub import: Duration,
+    /// FTS index, views and the commit
+    pub commit: Duration,
+    pub post_sql: Duration,
+}
+
+impl ImportTimings {
+    pub fn total(&self) -> Duration {
+        self.count + self.import + self.commit + self.post_sql
+    }
+}
+
+impl ImportOutcome {
+    /// The --summary-json contents.
+    pub fn summary_json(&self) -> serde_json::Value {
+        let counts = &self.counts;
+        let read = counts.imported + counts.chats + counts.duplicates + counts.skipped + counts.failed;
+        let timings = &self.timings;
+        serde_json::json!({
+            "status": if self.interrupted { "interrupted" } else { "complete" },
+            "exit_code": self.exit_code(),
+            "input": self.input,
+            "input_bytes": self.input_bytes,
+            "databases": self.databases.iter().map(|(path, imported)| serde_json::json!({
+                "path": path.display().to_string(),
+                "imported": imported,
+            })).collect::<Vec<_>>(),
+            "counts": {
+                "read": read,
+                "imported": counts.imported,
+                "chats": counts.chats,
+                "duplicates": counts.duplicates,
+                "merged": counts.merged,
+                "skipped": counts.skipped,
+                "failed": counts.failed,
+                "undated": counts.undated,
+                "removed": counts.removed,
+                "warnings": counts.warnings,
+            },
+            "filters": {
+                "spam": counts.skipped_spam,
+                "trash": counts.skipped_trash,
+                "chat": counts.skipped_chats,
+                "calendar": counts.skipped_calendar,
+            },
+            "errors": {
+                "parse": counts.failed - counts.insert_failed,
+                "insert": counts.insert_failed,
+            },
+            "timings": {
+                "count_seconds": timings.count.as_secs_f64(),
+                "import_seconds": timings.import.as_secs_f64(),
+                "commit_seconds": timings.commit.as_secs_f64(),
+                "post_sql_seconds": timings.post_sql.as_secs_f64(),
+                "total_seconds": timings.total().as_secs_f64(),
+            },
+            "emails_per_second": read as f64 / timings.total().as_secs_f64().max(f64::EPSILON),
+        })
+    }
+
+    /// The process exit code for scripts: 0 for a clean import, otherwise the
+    /// most serious of an interruption (5), messages that failed to parse or
+    /// insert (3), messages left out by a filter that was asked for (4:
+    /// --chats skip, --skip-calendar; the default Spam/Trash filtering doesn't
+    /// count) and lesser warnings (6: see `ImportCounts::warnings`). Errors
+    /// exit with 1 and bad arguments with 2.
+    pub fn exit_code(&self) -> u8 {
+        if self.interrupted {
+            5
+        } else if self.counts.failed > 0 {
+            3
+        } else if self.counts.skipped_chats + self.counts.skipped_calendar > 0 {
+            4
+        } else if self.counts.warnings > 0 {
+            6
+        } else {
+            0
+        }
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C has been pressed, for work that runs after the import and
+/// should stop at the next safe point too.
+#[cfg(any(feature = "summarize", feature = "embeddings"))]
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The first Ctrl-C stops reading the input and commits what's been read; a
+/// second one exits at once, leaving the database as it was.
+fn handle_interrupts(spinner: &ProgressBar) {
+    let spinner = spinner.clone();
+    // Fails only if a handler is already installed, which then decides
+    let _ = ctrlc::set_handler(move || {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        spinner.println("Interrupted: finishing the emails already read (Ctrl-C again to abandon the import)");
+    });
+}
+
+pub fn run_import(source: &mut dyn MessageSource, output_path: &Path, options: ImportOptions) -> Result<ImportOutcome> {
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+            .template("{spinner:.cyan} {msg}")
+            .unwrap()
+    );
+    let mut timings = ImportTimings::default();
+    let counting = Instant::now();
+    if options.two_pass {
+        spinner.set_message("Counting messages...");
+        spinner.tick();
+        if let Some(total) = source.count_messages()? {
+            spinner.set_length(total);
+            spinner.set_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+        }
+    }
+    timings.count = counting.elapsed();
+    spinner.set_message("Starting conversion...");
+
+    if options.space_check {
+        if let Some(input_bytes) = source.input_bytes() {
+            check_disk_space(output_path, input_bytes, &options, &spinner)?;
+        }
+    }
+
+    handle_interrupts(&spinner);
+    source.set_progress(&spinner);
+
+    // Without --split-by everything goes to one database, opened up front
+    let options = Arc::new(options);
+    let input = source.describe();
+    let mut importers: BTreeMap<String, (PathBuf, Importer)> = BTreeMap::new();
+    if options.split_by.is_none() {
+        let importer = Importer::begin(output_path, options.clone(), &input, spinner.clone())?;
+        importers.insert(String::new(), (output_path.to_path_buf(), importer));
+    }
+
+    let importing = Instant::now();
+    // Warned about by the reader, which has no counts of its own
+    let mut skipped_input = 0;
+    // Split (here, as sources needn't be Send) → parse → transform → write,
+    // each stage in its own thread and connected by bounded channels
+    let digest = thread::scope(|scope| {
+        let (split_tx, split_rx) = mpsc::sync_channel::<QueuedMessage>(PIPELINE_DEPTH);
+        let (parsed_tx, parsed_rx) = mpsc::sync_channel::<(QueuedMessage, Result<EmailRecord>)>(PIPELINE_DEPTH);
+        let (prepared_tx, prepared_rx) = mpsc::sync_channel::<(QueuedMessage, Prepared)>(PIPELINE_DEPTH);
+
+        let prefer = options.prefer;
+        scope.spawn(move || {
+            for message in split_rx {
+                let parsed = parse_message(&message.data, message.folder.as_deref(), prefer);
+                if parsed_tx.send((message, parsed)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (options, spinner, input, importers) = (&options, &spinner, &input, &mut importers);
+        scope.spawn(move || {
+            for (message, parsed) in parsed_rx {
+                let mut prepared = prepare_record(parsed, &message.data, options);
+                resolve_date(&mut prepared, &message.data, &options.date_parser);
+                if prepared_tx.send((message, prepared)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let writer = scope.spawn(move || -> Result<()> {
+            let mut throughput = Throughput::new();
+            for (message, prepared) in prepared_rx {
+                let key = options.split_by.map(|split_by| split_key(&message.data, split_by, &options.date_parser)).unwrap_or_default();
+                let importer = match importers.entry(key) {
+                    Entry::Occupied(entry) => &mut entry.into_mut().1,
+                    Entry::Vacant(entry) => {
+                        let path = split_path(output_path, entry.key());
+                        let importer = Importer::begin(&path, options.clone(), input, spinner.clone())?;
+                        &mut entry.insert((path, importer)).1
+                    }
+                };
+                importer.write(&message.data, prepared, message.position)?;
+
+                if let Some((messages_per_second, bytes_per_second)) = throughput.add(message.data.len()) {
+                    let counts = total_counts(importers.values().map(|(_, importer)| importer));
+                    spinner.set_message(format!(
+                        "Processed {} emails ({} skipped, {} failed) · {:.0} emails/s · {}/s",
+                        counts.imported,
+                        counts.skipped,
+                        counts.failed,
+                        messages_per_second,
+                        report::format_bytes(bytes_per_second as i64)
+                    ));
+                }
+            }
+            Ok(())
+        });
+
+        let mut number = 0;
+        let read = source.read_messages(&mut |message| {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                anyhow::bail!("Interrupted");
+            }
+            number += 1;
+            let data = message.data.to_vec();
+            let position = Position { number, offset: message.offset };
+            warn_skipped(options, spinner, position, message.skipped)?;
+            skipped_input += message.skipped.len();
+            let queued = QueuedMessage { position, data, folder: message.folder.map(String::from) };
+            split_tx.send(queued).map_err(|_| anyhow::anyhow!("Import stopped before the input was read"))
+        });
+        drop(split_tx);
+        // A failed write stops the pipeline, so its error explains the reader's
+        writer.join().map_err(|_| anyhow::anyhow!("Import writer panicked"))??;
+        match read {
+            Err(_) if INTERRUPTED.load(Ordering::SeqCst) => Ok(None),
+            read => read.map(Some),
+        }
+    })?;
+    let interrupted = digest.is_none();
+    timings.import = importing.elapsed();
+
+    let committing = Instant::now();
+    for (_, importer) in importers.values_mut() {
+        importer.commit(digest.as_ref())?;
+    }
+    source.finish()?;
+    timings.commit = committing.elapsed();
+    let post_sql = Instant::now();
+    for (_, importer) in importers.values_mut() {
+        importer.run_post_sql()?;
+    }
+    timings.post_sql = post_sql.elapsed();
+
+    let mut counts = total_counts(importers.values().map(|(_, importer)| importer));
+    counts.warnings += skipped_input;
+    let skipped_count = counts.skipped_spam + counts.skipped_trash;
+    let skip_message = if skipped_count > 0 && !options.include_both {
+        if !options.include_spam && !options.include_trash {
+            format!("\n    {} Spam/Trash emails skipped (pass --include-spam-and-trash to include them)", skipped_count)
+        } else if !options.include_spam {
+            format!("\n    {} Spam emails skipped (pass --include-spam to include them)", skipped_count)
+        } else if !options.include_trash {
+            format!("\n    {} Trash emails skipped (pass --include-trash to include them)", skipped_count)
+        } else {
+            String::new()
+        }
+    } else {
+        String::new()
+    };
+
+    let duplicate_message = if options.incremental && !interrupted {
+        format!("\n    {} unchanged, {} no longer in the input (kept in the database)", counts.duplicates - counts.merged, counts.removed)
+    } else if counts.duplicates > counts.merged {
+        format!("\n    {} emails already in the database skipped", counts.duplicates - counts.merged)
+    } else {
+        String::new()
+    };
+    let mut merge_message = if counts.merged > 0 {
+        format!("\n    {} copies of messages already imported merged, keeping the labels of each", counts.merged)
+    } else {
+        String::new()
+    };
+    if counts.chats > 0 {
+        merge_message.push_str(&format!("\n    {} chats stored in the {} table", counts.chats, options.tables.chats));
+    }
+    if counts.skipped_chats > 0 {
+        merge_message.push_str(&format!("\n    {} chats skipped", counts.skipped_chats));
+    }
+    if counts.skipped_calendar > 0 {
+        merge_message.push_str(&format!("\n    {} calendar invitations and replies skipped", counts.skipped_calendar));
+    }
+
+    if interrupted {
+        spinner.finish_with_message(format!("Interrupted after converting {} emails to database{}{}{}", counts.imported, skip_message, duplicate_message, merge_message));
+    } else {
+        spinner.finish_with_message(format!("✓ Successfully converted {} emails to database{}{}{}", counts.imported, skip_message, duplicate_message, merge_message));
+    }
+    for (path, importer) in importers.values() {
+        if options.split_by.is_some() {
+            println!("Database written to: {} ({} emails)", path.display(), importer.counts.imported);
+        } else {
+            println!("Database written to: {}", path.display());
+        }
+    }
+    if let Some(log) = &options.warnings {
+        let count = log.finish()?;
+        if count > 0 {
+            println!("{} warnings written to: {}", count, log.path.display());
+        }
+    }
+
+    let outcome = ImportOutcome {
+        input,
+        input_bytes: digest.map(|digest| digest.bytes),
+        counts,
+        databases: importers.into_values().map(|(path, importer)| (path, importer.counts.imported)).collect(),
+        timings,
+        interrupted,
+    };
+    if let Some(path) = &options.summary_json {
+        let summary = serde_json::to_string_pretty(&outcome.summary_json())?;
+        std::fs::write(path, summary + "\n").with_context(|| format!("Failed to write summary: {}", path.display()))?;
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::AmbiguousZones;
+    use crate::schema::FtsTokenizer;
+
+    /// What a plain `mbox2db` run uses, for a database of `schema`.
+    fn options(schema: SchemaProfile) -> ImportOptions {
+        ImportOptions {
+            include_spam: false,
+            include_trash: false,
+            include_both: false,
+            chats: Chats::Email,
+            skip_calendar: false,
+            aliases: AliasMap::default(),
+            decryptor: None,
+            store_signatures: false,
+            extract_amounts: false,
+            fts: false,
+            fts_tokenize: FtsTokenizer::Unicode61.spec(None).unwrap(),
+            sanitize_html: false,
+            prefer: Prefer::Both,
+            account: None,
+            if_exists: IfExists::Append,
+            incremental: false,
+            merge_copies: false,
+            consolidate_labels: false,
+            schema,
+            tables: Tables::new("", None).unwrap(),
+            extensions: Vec::new(),
+            pragmas: Vec::new(),
+            views: false,
+            post_sql: Vec::new(),
+            split_by: None,
+            max_attachment_size: None,
+            oversized_attachments: OversizedAttachments::Hash,
+            attachments_in_db: false,
+            date_parser: DateParser::new(Vec::new(), AmbiguousZones::Common),
+            display_tz: None,
+            two_pass: false,
+            space_check: false,
+            busy_timeout: Duration::from_secs(5),
+            keep_going: false,
+            summary_json: None,
+            warnings: None,
+            header_filter: HeaderFilter::default(),
+            me: Vec::new(),
+        }
+    }
+
+    /// A fresh database in the temp directory, removed (with its lock file) when dropped.
+    struct TestDatabase(PathBuf);
+
+    impl TestDatabase {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("mbox2db-test-{}-{}.db", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        /// Imports `messages` as one complete input.
+        fn import(&self, options: ImportOptions, messages: &[&[u8]]) -> ImportCounts {
+            let mut importer = Importer::begin(&self.0, Arc::new(options), "test", ProgressBar::hidden()).unwrap();
+            for data in messages {
+                importer.add(RawMessage { data, folder: None, offset: None, skipped: &[] }).unwrap();
+            }
+            importer.commit(Some(&InputDigest { sha256: String::new(), bytes: 0 })).unwrap();
+            std::mem::take(&mut importer.counts)
+        }
+
+        fn query(&self, sql: &str) -> Vec<String> {
+            let conn = Connection::open(&self.0).unwrap();
+            let mut statement = conn.prepare(sql).unwrap();
+            let rows = statement.query_map([], |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let mut lock = self.0.clone().into_os_string();
+            lock.push(".lock");
+            let _ = std::fs::remove_file(lock);
+        }
+    }
+
+    #[test]
+    fn sender_domains() {
+        let cases = [
+            ("alice@example.com", Some("example.com")),
+            ("Alice <alice@Example.COM>", Some("example.com")),
+            ("alice@example.com (Alice Smith)", Some("example.com")),
+            ("alice@example.com (alice@home.example)", Some("example.com")),
+            ("\"alice@home.example\" <alice@example.com>", Some("example.com")),
+            ("\"Alice <alice@home.example>\" <alice@example.com>", Some("example.com")),
+            ("undisclosed-recipients", None),
+            ("", None),
+        ];
+        for (from, domain) in cases {
+            let address = split_addresses(from).into_iter().next().map(|(address, _)| address);
+            assert_eq!(address.as_deref().and_then(address_domain).as_deref(), domain, "{:?}", from);
+        }
+    }
+
+    #[test]
+    fn gmail_categories() {
+        let cases = [
+            ("Inbox,Category Promotions,Opened", Some("promotions")),
+            ("Category Personal", Some("primary")),
+            ("Category Primary", Some("primary")),
+            ("Archived, category social", Some("social")),
+            ("Category Updates,Unread", Some("updates")),
+            ("Category Forums", Some("forums")),
+            // User labels that only look like a category
+            ("Category Receipts,Categories", None),
+            ("Inbox,Important", None),
+            ("", None),
+        ];
+        for (labels, category) in cases {
+            assert_eq!(gmail_category(labels), category, "{:?}", labels);
+        }
+    }
+
+    #[test]
+    fn automated_senders() {
+        let cases = [
+            ("noreply@example.com", true),
+            ("\"Example\" <No-Reply@example.com>", true),
+            ("no_reply.billing@example.com", true),
+            ("do-not-reply@example.com", true),
+            ("DoNotReply@example.com", true),
+            ("MAILER-DAEMON@mx.example.com", true),
+            ("postmaster@example.com", true),
+            ("bounces+1234-abcd@em.example.com", true),
+            ("notifications@github.com", true),
+            ("notification-team@example.com", true),
+            ("auto-confirm@amazon.com", true),
+            ("daemon@example.com", true),
+            ("alice@example.com", false),
+            ("alice@noreply.example.com", false),
+            ("noreplyguy@example.com", false),
+            ("bouncer@example.com", false),
+            ("", false),
+        ];
+        for (from, automated) in cases {
+            assert_eq!(is_automated_sender(from), automated, "{:?}", from);
+        }
+
+        let headers = [
+            ("Auto-Submitted: auto-generated", true),
+            ("Auto-Submitted: no", false),
+            ("Return-Path: <>", true),
+            ("Return-Path: <alice@example.com>", false),
+        ];
+        for (header, automated) in headers {
+            let raw = format!("From: alice@example.com\n{header}\nSubject: Hi\n\nHi\n");
+            assert_eq!(extract_email_data(raw.as_bytes(), Prefer::Both).unwrap().is_automated, automated, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn auto_replies() {
+        let subjects = [
+            ("Automatic reply: Lunch on Friday", true),
+            ("Out of Office: Re: Budget", true),
+            ("[EXTERNAL] Out of the office until Monday", true),
+            ("AutoReply: your message", true),
+            ("Auto-Response", true),
+            ("Abwesenheitsnotiz: Termin", true),
+            ("Réponse automatique : Rendez-vous", true),
+            ("Respuesta automática: Factura", true),
+            ("Automatyczna odpowiedź: Spotkanie", true),
+            ("Re: Automatic reply settings for the team", false),
+            ("Notes from the out of office meeting", false),
+            ("Lunch on Friday", false),
+        ];
+        for (subject, auto_reply) in subjects {
+            assert_eq!(AUTO_REPLY_SUBJECT.is_match(subject), auto_reply, "{:?}", subject);
+        }
+
+        // (header, is_auto_reply, is_automated)
+        let headers = [
+            ("Auto-Submitted: auto-replied (vacation)", true, true),
+            ("Auto-Submitted: auto-generated", false, true),
+            ("X-Autoreply: yes", true, true),
+            ("X-Autorespond: Out of office", true, true),
+            ("Precedence: auto_reply", true, true),
+            ("Precedence: bulk", false, false),
+        ];
+        for (header, auto_reply, automated) in headers {
+            let raw = format!("From: alice@example.com\n{header}\nSubject: Hi\n\nHi\n");
+            let record = extract_email_data(raw.as_bytes(), Prefer::Both).unwrap();
+            assert_eq!((record.is_auto_reply, record.is_automated), (auto_reply, automated), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn priorities() {
+        let cases = [
+            ("1 (Highest)", Some("high")),
+            ("2 (High)", Some("high")),
+            ("3", Some("normal")),
+            (" 4 (Low)", Some("low")),
+            ("5 (Lowest)", Some("low")),
+            ("0", None),
+            ("High", Some("high")),
+            ("urgent", Some("high")),
+            ("Normal", Some("normal")),
+            ("medium", Some("normal")),
+            ("low", Some("low")),
+            ("non-urgent", Some("low")),
+            ("high; comment", Some("high")),
+            ("whenever", None),
+            ("", None),
+        ];
+        for (value, priority) in cases {
+            assert_eq!(normalize_priority(value), priority, "{:?}", value);
+        }
+
+        // The first priority header that says something wins
+        let raw = b"From: alice@example.com\nImportance: whenever\nX-Priority: 1 (Highest)\nImportance: low\n\nHi\n";
+        assert_eq!(extract_email_data(raw, Prefer::Both).unwrap().priority, Some("high"));
+    }
+
+    #[test]
+    fn header_repairs_stay_in_headers() {
+        let raw = b"From: a@example.com\n Subject: indented by a broken exporter\n\
+            Content-Type: multipart/mixed;\n boundary=\"xyz\"\n\n\
+            --xyz\n  Content-Type: text/plain\n\n\
+            Hi,\n\n-- \n  Alice Smith\n  Acme: Widgets\n\n--xyz--\n";
+        let record = extract_email_data(raw, Prefer::Both).unwrap();
+        assert_eq!(record.subject, "indented by a broken exporter");
+        assert_eq!(record.body_plain.trim_end(), "Hi,\n\n-- \n  Alice Smith\n  Acme: Widgets");
+    }
+
+    #[test]
+    fn markup_is_read_before_sanitizing() {
+        let raw = b"From: Acme <orders@acme.example>\nSubject: Your order\nMessage-ID: <a-1@acme.example>\n\
+            Content-Type: text/html\n\n\
+            <script type=\"application/ld+json\">{\"@context\": \"https://schema.org\", \"@type\": \"Order\", \
+            \"orderNumber\": \"A-1\", \"seller\": {\"@type\": \"Organization\", \"name\": \"Acme\"}}</script>\n\
+            <p>Thanks! <a href=\"https://acme.example/orders/A-1\" onclick=\"track()\">Your order</a></p>\n";
+        let database = TestDatabase::new("sanitize");
+        let mut options = options(SchemaProfile::Normalized);
+        options.sanitize_html = true;
+        database.import(options, &[raw]);
+        assert_eq!(database.query("SELECT order_number FROM orders"), ["A-1"]);
+        assert_eq!(database.query("SELECT anchor_text FROM links WHERE domain = 'acme.example'"), ["Your order"]);
+        let html = database.query("SELECT body_html FROM emails");
+        assert!(!html[0].contains("<script") && !html[0].contains("onclick"), "{:?}", html);
+    }
+
+    #[test]
+    fn exit_codes() {
+        // (interrupted, failed, skipped_calendar, skipped_spam, warnings, exit code)
+        let cases = [
+            (false, 0, 0, 0, 0, 0),
+            (false, 0, 0, 12, 0, 0),
+            (false, 0, 0, 0, 3, 6),
+            (false, 0, 1, 0, 3, 4),
+            (false, 2, 1, 0, 3, 3),
+            (true, 2, 1, 0, 3, 5),
+            (true, 0, 0, 0, 0, 5),
+        ];
+        for (interrupted, failed, skipped_calendar, skipped_spam, warnings, code) in cases {
+            let outcome = ImportOutcome {
+                input: String::new(),
+                input_bytes: None,
+                counts: ImportCounts { failed, skipped_calendar, skipped_spam, warnings, ..Default::default() },
+                databases: Vec::new(),
+                timings: ImportTimings::default(),
+                interrupted,
+            };
+            assert_eq!(outcome.exit_code(), code, "{:?}", (interrupted, failed, skipped_calendar, skipped_spam, warnings));
+        }
+    }
+
+    #[test]
+    fn rfc2231_filenames() {
+        let cases = [
+            (&[("filename", "plain.pdf")][..], None),
+            (&[("filename*", "utf-8''%E2%82%AC%20rate.pdf")][..], Some("€ rate.pdf")),
+            (&[("filename*", "UTF-8'en'caf%C3%A9.txt")][..], Some("café.txt")),
+            (&[("filename*", "iso-8859-1''caf%E9.txt")][..], Some("café.txt")),
+            // Continuations, in any order, encoded or not; only the first names the charset
+            (&[("filename*1*", "%C3%A9.txt"), ("filename*0*", "utf-8''caf")][..], Some("café.txt")),
+            (&[("filename*0*", "utf-8''%E2%82%AC"), ("filename*1", "\"100 (final).pdf\"")][..], Some("€100 (final).pdf")),
+            // Malformed escapes stay as they are
+            (&[("filename*", "utf-8''100%.txt")][..], Some("100%.txt")),
+            (&[("filename*", "utf-8''%zz%4%+1%é%")][..], Some("%zz%4%+1%é%")),
+        ];
+        for (params, decoded) in cases {
+            let params: BTreeMap<String, String> = params.iter().map(|&(key, value)| (key.to_string(), value.to_string())).collect();
+            assert_eq!(rfc2231_param(&params, "filename").as_deref(), decoded, "{:?}", params);
+        }
+        // Another parameter's sections aren't picked up
+        let params = BTreeMap::from([("filenamex*".to_string(), "utf-8''x".to_string())]);
+        assert_eq!(rfc2231_param(&params, "filename"), None);
+    }
+
+    #[test]
+    fn preferred_alternatives() {
+        let plain_only = b"Content-Type: multipart/alternative; boundary=\"a\"\n\n\
+            --a\nContent-Type: text/plain\n\nPlain\n--a--\n";
+        let html_only = b"Content-Type: multipart/alternative; boundary=\"a\"\n\n\
+            --a\nContent-Type: text/html\n\n<p>HTML</p>\n--a--\n";
+        // The HTML version comes with its inline image
+        let nested = b"Content-Type: multipart/alternative; boundary=\"a\"\n\n\
+            --a\nContent-Type: text/plain\n\nPlain\n\
+            --a\nContent-Type: multipart/related; boundary=\"r\"\n\n\
+            --r\nContent-Type: text/html\n\n<p>HTML</p>\n\
+            --r\nContent-Type: image/png\nContent-Disposition: inline; filename=\"logo.png\"\nContent-ID: <logo>\n\nPNG\n--r--\n\
+            --a--\n";
+        // (message, prefer, body_plain, body_html, attachments)
+        let cases = [
+            (&plain_only[..], Prefer::Plain, "Plain", "", 0),
+            (&plain_only[..], Prefer::Html, "Plain", "", 0),
+            (&plain_only[..], Prefer::Both, "Plain", "", 0),
+            (&html_only[..], Prefer::Plain, "", "<p>HTML</p>", 0),
+            (&html_only[..], Prefer::Both, "", "<p>HTML</p>", 0),
+            (&nested[..], Prefer::Plain, "Plain", "", 0),
+            (&nested[..], Prefer::Html, "", "<p>HTML</p>", 1),
+            (&nested[..], Prefer::Both, "Plain", "<p>HTML</p>", 1),
+        ];
+        for (raw, prefer, plain, html, attachments) in cases {
+            let record = extract_email_data(raw, prefer).unwrap();
+            assert_eq!(
+                (record.body_plain.trim_end(), record.body_html.trim_end(), record.attachments.len()),
+                (plain, html, attachments),
+                "{:?} {:?}",
+                prefer,
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+
+    /// A minimal message with this Message-ID.
+    fn message(id: &str) -> Vec<u8> {
+        format!("From: a@example.com\nDate: Tue, 2 Mar 2010 09:00:00 +0000\nMessage-ID: <{id}@example.com>\nSubject: {id}\n\nBody of {id}\n").into_bytes()
+    }
+
+    #[test]
+    fn incremental_imports_count_removed_messages() {
+        let (a, b, c, d) = (message("a"), message("b"), message("c"), message("d"));
+        let database = TestDatabase::new("incremental");
+        let incremental = || ImportOptions { incremental: true, ..options(SchemaProfile::Normalized) };
+
+        let first = database.import(incremental(), &[&a, &b, &c]);
+        assert_eq!((first.imported, first.duplicates, first.removed), (3, 0, 0));
+
+        // The next export dropped b and has a new message d
+        let second = database.import(incremental(), &[&a, &c, &d]);
+        assert_eq!((second.imported, second.duplicates, second.removed), (1, 2, 1));
+        assert_eq!(database.query("SELECT subject FROM emails ORDER BY subject"), ["a", "b", "c", "d"]);
+
+        // Another account's messages aren't this export's to miss
+        let other = database.import(ImportOptions { account: Some("work".to_string()), ..incremental() }, &[&d]);
+        assert_eq!((other.imported, other.duplicates, other.removed), (0, 1, 0));
+    }
+
+    #[test]
+    fn split_by_year() {
+        let parser = DateParser::new(Vec::new(), AmbiguousZones::Common);
+        let cases = [
+            (&b"Date: Tue, 2 Mar 2010 09:00:00 +0000\n\nHi\n"[..], "2010"),
+            // By the UTC date, as date_parsed stores it
+            (&b"Date: Tue, 31 Dec 2019 23:30:00 -0500\n\nHi\n"[..], "2020"),
+            (&b"Received: from mx.example.com; Mon, 1 Feb 2016 10:00:00 +0000\nSubject: No date\n\nHi\n"[..], "2016"),
+            (&b"From alice@example.com Mon Jan  1 00:00:00 2001\nSubject: No date\n\nHi\n"[..], "2001"),
+            (&b"Subject: No date anywhere\n\nHi\n"[..], "undated"),
+            (&b"Date: someday\n\nHi\n"[..], "undated"),
+        ];
+        for (raw, key) in cases {
+            assert_eq!(split_key(raw, SplitBy::Year, &parser), key, "{:?}", String::from_utf8_lossy(raw));
+        }
+
+        let paths = [
+            ("emails.db", "2019", "2019-emails.db"),
+            ("/data/mail.sqlite", "2019", "/data/2019-mail.sqlite"),
+            ("out/archive", "2019", "out/2019-archive"),
+            ("emails.db", "undated", "undated-emails.db"),
+        ];
+        for (output, key, path) in paths {
+            assert_eq!(split_path(Path::new(output), key), PathBuf::from(path), "{:?}", output);
+        }
+    }
+}
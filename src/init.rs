@@ -0,0 +1,186 @@
+//! `mbox2db init`: a few questions on the terminal, answered with a config
+//! file holding the import defaults (see [`crate::config`]).
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::import::parse_size;
+
+/// The wizard's answers, as they go into the config file.
+struct Answers {
+    schema: String,
+    include_spam: bool,
+    include_trash: bool,
+    fts: bool,
+    attachments_in_db: bool,
+    /// With what happens to larger attachments
+    max_attachment_size: Option<(String, String)>,
+    /// With what happens when the database already exists
+    output: Option<(String, String)>,
+}
+
+impl Answers {
+    /// The config file's text, with every string quoted for TOML.
+    fn to_toml(&self) -> String {
+        let string = |value: &str| toml::Value::String(value.to_string()).to_string();
+        let mut config = String::from("# Written by `mbox2db init`. Options given on the command line take precedence.\n");
+        config.push_str(&format!("schema = {}\n", string(&self.schema)));
+        config.push_str(&format!("include_spam = {}\ninclude_trash = {}\nfts = {}\n", self.include_spam, self.include_trash, self.fts));
+        config.push_str(&format!("attachments_in_db = {}\n", self.attachments_in_db));
+        if let Some((size, oversized)) = &self.max_attachment_size {
+            config.push_str(&format!("max_attachment_size = {}\noversized_attachments = {}\n", string(size), string(oversized)));
+        }
+        if let Some((output, if_exists)) = &self.output {
+            config.push_str(&format!("output = {}\nif_exists = {}\n", string(output), string(if_exists)));
+        }
+        config
+    }
+}
+
+/// Asks about the schema, filters, full-text index, attachments and output,
+/// and returns the config file's text. Enter (or the end of the input) takes
+/// the default shown in brackets.
+pub fn run_wizard(input: &mut impl BufRead, out: &mut impl Write) -> Result<String> {
+    writeln!(out, "This writes a config file with the defaults for every import. Press Enter to take the answer in [brackets].")?;
+
+    writeln!(out, "\nWhich tables should imports create?")?;
+    writeln!(out, "  flat        just the emails table")?;
+    writeln!(out, "  normalized  adds recipients, labels, attachments, links and threads")?;
+    writeln!(out, "  full        adds every header and the raw message (for `export` and `show --raw`)")?;
+    let schema = choose(input, out, "Schema", &["flat", "normalized", "full"], "normalized")?;
+
+    writeln!(out)?;
+    let include_spam = confirm(input, out, "Import messages labelled Spam?", false)?;
+    let include_trash = confirm(input, out, "Import messages in the Trash?", false)?;
+    let fts = confirm(input, out, "Build a full-text search index (FTS5)?", true)?;
+
+    writeln!(out, "\nWhat should be kept of attachments?")?;
+    writeln!(out, "  metadata  name, type, size and SHA-256 (files can be extracted from the mbox later)")?;
+    writeln!(out, "  contents  the files themselves too, for a self-contained database")?;
+    let attachments_in_db = choose(input, out, "Attachments", &["metadata", "contents"], "metadata")? == "contents";
+    let size_default = if attachments_in_db { "25MB" } else { "" };
+    let max_attachment_size = loop {
+        let size = ask(input, out, "Largest attachment to process in full, e.g. 10MB (blank for no limit)", size_default)?;
+        match parse_size(&size) {
+            _ if size.is_empty() => break None,
+            Ok(_) => break Some(size),
+            Err(err) => writeln!(out, "  {}", err)?,
+        }
+    };
+    let max_attachment_size = match max_attachment_size {
+        Some(size) => {
+            writeln!(out, "  skip: leave them out; hash: record name, size and hash only; store: import them anyway")?;
+            Some((size, choose(input, out, "Larger ones", &["skip", "hash", "store"], "hash")?))
+        }
+        None => None,
+    };
+
+    writeln!(out)?;
+    let output = ask(input, out, "Output database (blank for a new YYYY-MM-DD-emails.db in the current directory each time)", "")?;
+    let output = match output.as_str() {
+        "" => None,
+        output => {
+            // Imports run from anywhere, so a relative path is pinned to here
+            let output = std::path::absolute(Path::new(output)).with_context(|| format!("Invalid path: {}", output))?;
+            let if_exists = choose(input, out, "When it already exists", &["append", "overwrite", "fail"], "append")?;
+            Some((output.display().to_string(), if_exists))
+        }
+    };
+
+    let answers = Answers { schema, include_spam, include_trash, fts, attachments_in_db, max_attachment_size, output };
+    let config = answers.to_toml();
+    toml::from_str::<Config>(&config).context("The wizard wrote an invalid config file")?;
+    Ok(config)
+}
+
+/// Prints `question` and reads one line; the default when it's empty.
+fn ask(input: &mut impl BufRead, out: &mut impl Write, question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        write!(out, "{}: ", question)?;
+    } else {
+        write!(out, "{} [{}]: ", question, default)?;
+    }
+    out.flush()?;
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        // No more input: take the defaults, so the wizard works from a script too
+        writeln!(out)?;
+    }
+    match answer.trim() {
+        "" => Ok(default.to_string()),
+        answer => Ok(answer.to_string()),
+    }
+}
+
+/// Asks until the answer is one of `choices` (case-insensitive).
+fn choose(input: &mut impl BufRead, out: &mut impl Write, question: &str, choices: &[&str], default: &str) -> Result<String> {
+    loop {
+        let answer = ask(input, out, question, default)?.to_lowercase();
+        if choices.contains(&answer.as_str()) {
+            return Ok(answer);
+        }
+        writeln!(out, "  Please answer {}", choices.join(", "))?;
+    }
+}
+
+fn confirm(input: &mut impl BufRead, out: &mut impl Write, question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        match ask(input, out, &format!("{} [{}]", question, hint), "")?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => writeln!(out, "  Please answer y or n")?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{IfExists, OversizedAttachments};
+    use crate::schema::SchemaProfile;
+    use std::path::PathBuf;
+
+    #[test]
+    fn answers_make_a_valid_config() {
+        let defaults = Answers {
+            schema: "normalized".to_string(),
+            include_spam: false,
+            include_trash: false,
+            fts: true,
+            attachments_in_db: false,
+            max_attachment_size: None,
+            output: None,
+        };
+        let config: Config = toml::from_str(&defaults.to_toml()).unwrap();
+        assert_eq!(config.schema, Some(SchemaProfile::Normalized));
+        assert!(!config.include_spam && !config.include_trash && config.fts && !config.attachments_in_db);
+        assert_eq!(config.max_attachment_size, None);
+        assert_eq!(config.oversized_attachments, None);
+        assert_eq!(config.output, None);
+        assert_eq!(config.if_exists, None);
+
+        // Quotes, backslashes and non-ASCII in the path survive the quoting
+        let output = r#"C:\Mail\"Émile's" archive.db"#;
+        let everything = Answers {
+            schema: "full".to_string(),
+            include_spam: true,
+            include_trash: true,
+            fts: false,
+            attachments_in_db: true,
+            max_attachment_size: Some(("25MB".to_string(), "store".to_string())),
+            output: Some((output.to_string(), "overwrite".to_string())),
+        };
+        let config: Config = toml::from_str(&everything.to_toml()).unwrap();
+        assert_eq!(config.schema, Some(SchemaProfile::Full));
+        assert!(config.include_spam && config.include_trash && !config.fts && config.attachments_in_db);
+        assert_eq!(config.max_attachment_size.as_deref(), Some("25MB"));
+        assert_eq!(config.oversized_attachments, Some(OversizedAttachments::Store));
+        assert_eq!(config.output, Some(PathBuf::from(output)));
+        assert_eq!(config.if_exists, Some(IfExists::Overwrite));
+    }
+}
@@ -0,0 +1,29 @@
+//! The conversion engine behind the `mbox2db` command, also built as a C
+//! library (see [`ffi`]) for embedding in non-Rust applications.
+
+mod attachments;
+mod auth;
+pub mod bench;
+mod chat;
+pub mod config;
+pub mod date;
+#[cfg(feature = "embeddings")]
+pub mod embeddings;
+pub mod export;
+pub mod ffi;
+pub mod import;
+pub mod init;
+#[cfg(any(feature = "summarize", feature = "embeddings"))]
+mod llm;
+mod markup;
+mod money;
+pub mod render;
+pub mod report;
+pub mod schema;
+pub mod search;
+pub mod sources;
+mod spam;
+#[cfg(feature = "summarize")]
+pub mod summarize;
+mod text;
+pub mod thread;
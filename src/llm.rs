@@ -0,0 +1,92 @@
+//! Plumbing shared by the features that call an OpenAI-compatible HTTP API
+//! (`--summarize`, `--embed`): JSON POSTs with a bearer token, a cap on
+//! requests per minute, and retries when the endpoint throttles.
+
+use anyhow::{bail, Context, Result};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// How often a throttled (429/503) request is retried before giving up.
+const MAX_RETRIES: u32 = 6;
+
+pub struct Endpoint {
+    runtime: Runtime,
+    http: reqwest::Client,
+    pub url: String,
+    api_key: Option<String>,
+    /// Time to leave between the starts of two requests
+    interval: Duration,
+    last_request: Cell<Option<Instant>>,
+}
+
+/// The endpoint turned a request down (a 4xx other than 429): the input
+/// was too long, or a filter objected. Other requests may still succeed.
+pub struct Refused {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Endpoint {
+    /// `per_minute` caps the requests sent each minute.
+    pub fn new(url: &str, api_key: Option<String>, per_minute: u32) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the async runtime")?;
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(Duration::from_secs(600))
+            .build()?;
+        Ok(Self {
+            runtime,
+            http,
+            url: url.to_string(),
+            api_key,
+            interval: Duration::from_secs(60) / per_minute.max(1),
+            last_request: Cell::new(None),
+        })
+    }
+
+    /// POSTs `body` and returns the JSON response, waiting first if the last
+    /// request was too recent, and waiting out throttling (429/503 with Retry-After).
+    pub fn post(&self, body: &Value) -> Result<Result<Value, Refused>> {
+        if let Some(wait) = self.last_request.get().and_then(|last| self.interval.checked_sub(last.elapsed())) {
+            std::thread::sleep(wait);
+        }
+        self.last_request.set(Some(Instant::now()));
+
+        self.runtime.block_on(async {
+            let mut attempt = 0;
+            loop {
+                let mut request = self.http.post(&self.url).json(body);
+                if let Some(key) = &self.api_key {
+                    request = request.bearer_auth(key);
+                }
+                let response = request.send().await.with_context(|| format!("Failed to reach {}", self.url))?;
+                let status = response.status();
+                if (status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE) && attempt < MAX_RETRIES {
+                    let wait = response
+                        .headers()
+                        .get("Retry-After")
+                        .and_then(|seconds| seconds.to_str().ok()?.parse().ok())
+                        .unwrap_or(2u64.pow(attempt));
+                    tokio::time::sleep(Duration::from_secs(wait)).await;
+                    attempt += 1;
+                    continue;
+                }
+                if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                    let message = response.text().await.unwrap_or_default().trim().to_string();
+                    return Ok(Err(Refused { status: status.as_u16(), message }));
+                }
+                if !status.is_success() {
+                    bail!("{} returned HTTP {}: {}", self.url, status.as_u16(), response.text().await.unwrap_or_default().trim());
+                }
+                let reply = response.json().await.with_context(|| format!("{} didn't answer with JSON", self.url))?;
+                return Ok(Ok(reply));
+            }
+        })
+    }
+}
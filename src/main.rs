@@ -1,28 +1,71 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Local, NaiveDateTime};
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-use mailparse::parse_mail;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use rusqlite::{Connection, params};
+use chrono::Local;
+use chrono_tz::Tz;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use mbox2db::bench;
+use mbox2db::config::Config;
+use mbox2db::date::{AmbiguousZones, DateParser};
+use mbox2db::import::{parse_size, run_import, AliasMap, Chats, GpgDecryptor, IfExists, ImportOptions, OversizedAttachments, Prefer, SplitBy, WarningLog};
+use mbox2db::schema::{parse_pragma, FtsTokenizer, SchemaProfile, Tables};
+use mbox2db::sources;
+use mbox2db::report::{self, AttachmentGrouping, NetworkFormat, TableFormat};
+use mbox2db::thread::{self, ThreadFormat};
+use mbox2db::search::{self, Highlight};
+use mbox2db::export::{self, ExportFormat, ManifestFormat};
+use mbox2db::init;
+use mbox2db::render;
+#[cfg(feature = "embeddings")]
+use mbox2db::embeddings::{self, Embedder};
+#[cfg(any(feature = "summarize", feature = "embeddings"))]
+use mbox2db::import::ImportOutcome;
+#[cfg(feature = "summarize")]
+use mbox2db::summarize::{self, Summarizer};
+use rusqlite::{Connection, OpenFlags};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "mbox2db")]
 #[command(about = "Convert mbox files to SQLite database", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    #[arg(help = "Input mbox file path")]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Commands>,
 
+    #[arg(required_unless_present = "profile", help = "Input mbox file paths; a message in more than one is imported once, with the labels of each copy")]
+    input: Vec<PathBuf>,
+
+    #[command(flatten)]
+    import: ImportArgs,
+}
+
+/// Options shared by every way of importing mail (mbox file, IMAP, ...).
+#[derive(Args)]
+struct ImportArgs {
     #[arg(short, long, help = "Output database file path (default: YYYY-MM-DD-emails.db)")]
     output: Option<PathBuf>,
 
-    #[arg(short, long, help = "Overwrite existing database instead of auto-incrementing filename")]
+    #[arg(long, value_name = "DIR", env = "MBOX2DB_OUTPUT_DIR", help = "Directory for the database when no output path is given")]
+    output_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "URL", env = "MBOX2DB_DB_URL", value_parser = parse_db_url, help = "Output database as a URL instead of a path, e.g. sqlite:///data/mail.db (-o wins over it)")]
+    db_url: Option<PathBuf>,
+
+    #[arg(long, value_enum, env = "MBOX2DB_IF_EXISTS", help = "What to do when the output database already exists (default: fail)")]
+    if_exists: Option<IfExists>,
+
+    #[arg(short, long, hide = true, conflicts_with = "if_exists", help = "Deprecated alias for --if-exists overwrite")]
     destructive: bool,
 
+    #[arg(long, conflicts_with = "destructive", help = "Import a newer copy of the same export: append what's new, and report how many messages are unchanged and how many are no longer in it")]
+    incremental: bool,
+
+    #[arg(long, help = "Store each message once, with the labels of every copy: merge copies of messages already in the database instead of adding or skipping them")]
+    consolidate_labels: bool,
+
     #[arg(long, help = "Include emails marked as Spam")]
     include_spam: bool,
 
@@ -31,508 +74,1026 @@ struct Cli {
 
     #[arg(long, help = "Include both Spam and Trash emails")]
     include_spam_and_trash: bool,
-}
 
-#[derive(Debug)]
-struct EmailRecord {
-    from: String,
-    to: String,
-    cc: String,
-    bcc: String,
-    subject: String,
-    date: String,
-    message_id: String,
-    in_reply_to: String,
-    references: String,
-    content_type: String,
-    body_plain: String,
-    body_html: String,
-    gmail_labels: String,
+    #[arg(long, value_enum, default_value_t = Chats::Email, help = "What to do with Google Talk/Hangouts chats (the Chat label): import them as emails, store them in the chats table, or skip them")]
+    chats: Chats,
+
+    #[arg(long, help = "Skip calendar invitations, updates and replies (text/calendar parts with a METHOD other than PUBLISH)")]
+    skip_calendar: bool,
+
+    #[arg(long, value_name = "FILE", env = "MBOX2DB_CONFIG", help = "TOML config file with defaults for these options and extra date_formats (default: ~/.config/mbox2db/config.toml, if present)")]
+    config: Option<PathBuf>,
+
+    #[arg(long, value_name = "NAME", env = "MBOX2DB_PROFILE", help = "Use the settings of [profile.NAME] in the config file, which can also name the mbox or IMAP server to import")]
+    profile: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Alias file mapping several addresses to one contact (one person per line, canonical address first)")]
+    aliases: Option<PathBuf>,
+
+    #[arg(long, value_name = "ADDRESS", help = "Your own address (repeatable); scores each message's importance for you")]
+    me: Vec<String>,
+
+    #[arg(long, value_name = "PATH", help = "GnuPG home directory or exported secret key used to decrypt PGP messages (requires gpg)")]
+    gpg_keyring: Option<PathBuf>,
+
+    #[arg(long, help = "Store signatures stripped from body_clean in the signature column")]
+    store_signatures: bool,
+
+    #[arg(long, help = "Read amounts, invoice numbers and due dates from receipts and invoices into the receipts and amounts tables")]
+    extract_amounts: bool,
+
+    #[arg(long, help = "Build an FTS5 full-text index (emails_fts) over subjects, bodies and attachment text")]
+    fts: bool,
+
+    #[arg(long, value_enum, default_value_t = FtsTokenizer::Unicode61, requires = "fts", help = "Tokenizer for the --fts index")]
+    fts_tokenizer: FtsTokenizer,
+
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=2), requires = "fts", help = "FTS5 remove_diacritics setting: 0 keeps accents, 1 or 2 folds them (so cafe matches café)")]
+    fts_remove_diacritics: Option<u8>,
+
+    #[arg(long, help = "Strip scripts, event handlers and remote tracking images from body_html")]
+    sanitize_html: bool,
+
+    #[arg(long, value_enum, help = "Which version of a multipart/alternative message fills the body columns (the other is left out) (default: both)")]
+    prefer: Option<Prefer>,
+
+    #[arg(long, value_name = "NAME", env = "MBOX2DB_ACCOUNT", help = "Tag imported emails with this account name (account column)")]
+    account: Option<String>,
+
+    #[arg(long, value_enum, env = "MBOX2DB_SCHEMA", help = "Which tables to create (default: normalized)")]
+    schema: Option<SchemaProfile>,
+
+    #[arg(long, help = "Don't create the v_senders, v_recent and v_conversations views")]
+    no_views: bool,
+
+    #[arg(long, value_enum, help = "Write one database per year instead of one in total (2019-emails.db, 2020-emails.db, ...)")]
+    split_by: Option<SplitBy>,
+
+    #[arg(long, help = "Store attachment contents in the attachment_blobs table, for a self-contained database (up to 25MB each unless --max-attachment-size is set)")]
+    attachments_in_db: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, help = "Attachments larger than this (e.g. 10MB) get the --oversized-attachments treatment")]
+    max_attachment_size: Option<u64>,
+
+    #[arg(long, value_enum, requires = "max_attachment_size", help = "What to do with attachments over --max-attachment-size (default: hash)")]
+    oversized_attachments: Option<OversizedAttachments>,
+
+    #[arg(long, value_enum, default_value_t = AmbiguousZones::Common, help = "How to read Date headers with a time zone abbreviation used in several places, like IST")]
+    ambiguous_tz: AmbiguousZones,
+
+    #[arg(long, value_name = "ZONE", help = "Also store each date as local time in this time zone (date_display), e.g. Europe/Berlin")]
+    display_tz: Option<Tz>,
+
+    #[arg(long, value_name = "SECONDS", default_value_t = 30, env = "MBOX2DB_BUSY_TIMEOUT", help = "How long to wait for a database another process has locked before giving up")]
+    busy_timeout: u64,
+
+    #[arg(long, help = "Start the import even if the output's disk looks too full for it")]
+    no_space_check: bool,
+
+    #[arg(long, help = "Log messages that fail to insert in the insert_errors table and carry on, instead of abandoning the import")]
+    keep_going: bool,
+
+    #[arg(long, value_name = "FILE", help = "Write the import's counts, filter and error totals and timings to this file as JSON")]
+    summary_json: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE", help = "Write per-message warnings (failed parses, date fallbacks, charset guesses) to this file as NDJSON instead of the console")]
+    warnings_file: Option<PathBuf>,
+
+    #[arg(long, help = "Count the messages first (a quick extra read of the mbox) to show a progress bar with an ETA")]
+    two_pass: bool,
+
+    #[arg(long, value_name = "FILE", help = "Run this SQL script after the import, in its own transaction (repeatable, run in order)")]
+    post_sql: Vec<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Load a SQLite extension before creating the schema (repeatable)")]
+    load_extension: Vec<PathBuf>,
+
+    #[arg(long, value_name = "NAME=VALUE", env = "MBOX2DB_PRAGMAS", value_delimiter = ';', value_parser = parse_pragma, help = "Set a SQLite pragma on the import connection, e.g. synchronous=OFF (repeatable; the variable takes a ;-separated list)")]
+    pragma: Vec<(String, String)>,
+
+    #[cfg(feature = "summarize")]
+    #[command(flatten)]
+    summarize: SummarizeArgs,
+
+    #[cfg(feature = "embeddings")]
+    #[command(flatten)]
+    embeddings: EmbeddingArgs,
+
+    #[command(flatten)]
+    tables: TableArgs,
 }
 
-impl Default for EmailRecord {
-    fn default() -> Self {
-        Self {
-            from: String::new(),
-            to: String::new(),
-            cc: String::new(),
-            bcc: String::new(),
-            subject: String::new(),
-            date: String::new(),
-            message_id: String::new(),
-            in_reply_to: String::new(),
-            references: String::new(),
-            content_type: String::new(),
-            body_plain: String::new(),
-            body_html: String::new(),
-            gmail_labels: String::new(),
-        }
-    }
+/// Thread summaries written once the import has been committed.
+#[cfg(feature = "summarize")]
+#[derive(Args)]
+struct SummarizeArgs {
+    #[arg(long, requires = "llm_model", help = "After the import, summarize every thread of two or more messages with an LLM into thread_summaries (resumable)")]
+    summarize: bool,
+
+    #[arg(long, value_name = "URL", default_value = summarize::DEFAULT_URL, help = "OpenAI-compatible chat completions endpoint for --summarize (default: a local Ollama)")]
+    llm_url: String,
+
+    #[arg(long, value_name = "MODEL", help = "Model for --summarize, e.g. llama3.1")]
+    llm_model: Option<String>,
+
+    #[arg(long, env = "MBOX2DB_LLM_API_KEY", hide_env_values = true, help = "API key for --llm-url, sent as a bearer token (prefer the environment variable)")]
+    llm_api_key: Option<String>,
+
+    #[arg(long, value_name = "N", default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..), help = "Most requests --summarize sends per minute")]
+    summarize_rate: u32,
 }
 
-fn extract_email_data(raw_email: &[u8]) -> Result<EmailRecord> {
-    // Fix malformed headers: remove leading spaces from lines that shouldn't have them
-    let raw_str = String::from_utf8_lossy(raw_email);
-    let fixed_email = raw_str
-        .lines()
-        .map(|line| {
-            // If line starts with space but doesn't look like a continuation (no previous header context),
-            // just trim it. This is a simple heuristic.
-            if line.starts_with(' ') && !line.trim_start().is_empty() {
-                line.trim_start()
-            } else {
-                line
+#[cfg(feature = "summarize")]
+impl SummarizeArgs {
+    /// Summarizes the threads of every database the import wrote to.
+    fn run(&self, tables: &Tables, outcome: &ImportOutcome) -> Result<()> {
+        let (true, Some(model)) = (self.summarize, &self.llm_model) else {
+            return Ok(());
+        };
+        if outcome.interrupted {
+            return Ok(());
+        }
+        let summarizer = Summarizer::new(&self.llm_url, model, self.llm_api_key.clone(), self.summarize_rate)?;
+        for (database, _) in &outcome.databases {
+            let counts = summarize::summarize_threads(database, tables, &summarizer)?;
+            println!("Summarized {} threads in {}", counts.summarized, database.display());
+            if counts.failed > 0 {
+                println!("{} threads were refused by the endpoint", counts.failed);
+            }
+            if counts.remaining > 0 {
+                println!("Interrupted with {} threads to go; run again to summarize them", counts.remaining);
+                break;
             }
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-    
-    let parsed = parse_mail(fixed_email.as_bytes())?;
-    let mut record = EmailRecord::default();
-
-    for header in &parsed.headers {
-        let name = header.get_key().to_lowercase();
-        let value = header.get_value();
-
-        match name.as_str() {
-            "from" => record.from = value,
-            "to" => record.to = value,
-            "cc" => record.cc = value,
-            "bcc" => record.bcc = value,
-            "subject" => record.subject = value,
-            "date" => record.date = value,
-            "message-id" => record.message_id = value,
-            "in-reply-to" => record.in_reply_to = value,
-            "references" => record.references = value,
-            "content-type" => record.content_type = value,
-            "x-gmail-labels" => record.gmail_labels = value,
-            _ => {}
         }
+        Ok(())
     }
+}
 
-    extract_body(&parsed, &mut record);
+#[derive(Args)]
+struct TableArgs {
+    #[arg(long, value_name = "PREFIX", default_value = "", help = "Prefix for every table and index name, e.g. gmail_ (for sharing an existing database)")]
+    table_prefix: String,
 
-    Ok(record)
+    #[arg(long, value_name = "NAME", help = "Name of the main table instead of emails (the prefix still applies)")]
+    table_name: Option<String>,
 }
 
-fn extract_body(parsed: &mailparse::ParsedMail, record: &mut EmailRecord) {
-    if parsed.subparts.is_empty() {
-        let content_type = parsed
-            .headers
-            .iter()
-            .find(|h| h.get_key().to_lowercase() == "content-type")
-            .map(|h| h.get_value().to_lowercase())
-            .unwrap_or_default();
-
-        if let Ok(body) = parsed.get_body() {
-            if content_type.contains("text/html") {
-                record.body_html = body;
-            } else {
-                record.body_plain = body;
-            }
-        }
-    } else {
-        for part in &parsed.subparts {
-            extract_body(part, record);
-        }
+impl TableArgs {
+    fn tables(&self) -> Result<Tables> {
+        Tables::new(&self.table_prefix, self.table_name.as_deref())
     }
 }
 
-fn create_database(db_path: &PathBuf) -> Result<Connection> {
-    if let Some(parent) = db_path.parent() {
-        if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)
-                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
-        }
-    }
+#[derive(Subcommand)]
+enum Commands {
+    /// Search subjects, senders and bodies, best matches first (uses the --fts index when there is one)
+    Search {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
 
-    let conn = Connection::open(db_path)
-        .with_context(|| format!("Failed to create database: {}", db_path.display()))?;
-
-    conn.execute_batch(
-        "PRAGMA journal_mode=WAL;
-         PRAGMA synchronous=NORMAL;
-         PRAGMA cache_size=-64000;
-         PRAGMA temp_store=MEMORY;
-         PRAGMA mmap_size=30000000000;"
-    )?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS emails (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            from_addr TEXT,
-            to_addr TEXT,
-            cc TEXT,
-            bcc TEXT,
-            subject TEXT,
-            date TEXT,
-            date_parsed TEXT,
-            message_id TEXT,
-            in_reply_to TEXT,
-            refs TEXT,
-            content_type TEXT,
-            body_plain TEXT,
-            body_html TEXT
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_from ON emails(from_addr)",
-        [],
-    )?;
-    
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_date ON emails(date)",
-        [],
-    )?;
-    
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_date_parsed ON emails(date_parsed)",
-        [],
-    )?;
-    
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_subject ON emails(subject)",
-        [],
-    )?;
+        #[arg(help = "Words to look for; a message must contain all of them")]
+        query: String,
+
+        #[arg(long, default_value_t = 20, help = "Show at most this many messages")]
+        limit: usize,
+
+        #[arg(long, help = "Tolerate typos: match words that look alike in subjects, senders and recipients (bodies aren't searched)")]
+        fuzzy: bool,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Render a stored message as standalone HTML with inline (cid:) images resolved
+    Render {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(help = "Email id (emails.id)")]
+        id: i64,
+
+        #[arg(short, long, help = "Write the HTML to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Print a stored message in the terminal: headers, the wrapped clean body and its attachments
+    Show {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(help = "Email id (emails.id)")]
+        id: i64,
+
+        #[arg(long, help = "Print the original message bytes instead (needs a database imported with --schema full)")]
+        raw: bool,
+
+        #[arg(long, value_name = "COLUMNS", default_value_t = 80, value_parser = clap::value_parser!(u16).range(20..), help = "Wrap the body at this width")]
+        width: u16,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Open a stored message in the web browser (rendered like `render`, with scripts and remote images removed)
+    Open {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(help = "Email id (emails.id)")]
+        id: i64,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Print a whole conversation, e.g. as Markdown for an issue tracker or as a reply tree
+    Thread {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(help = "Any message of the thread (its Message-ID or email id), or the thread id from the threads table")]
+        id: String,
+
+        #[arg(long, value_enum, default_value_t = ThreadFormat::Md, help = "Output format")]
+        format: ThreadFormat,
+
+        #[arg(short, long, help = "Write to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Export the sender/recipient network with message counts, e.g. for Gephi
+    Network {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = NetworkFormat::Graphml, help = "Output format")]
+        format: NetworkFormat,
+
+        #[arg(short, long, help = "Write to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Show how quickly you and each correspondent answer each other (median reply time)
+    Latency {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, required = true, value_name = "ADDRESS", help = "Your own address (repeatable, for every address you send from)")]
+        me: Vec<String>,
+
+        #[arg(long, default_value_t = 50, help = "Show at most this many correspondents, busiest first")]
+        limit: usize,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Count messages by hour of the day and day of the week
+    Activity {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_name = "ADDRESS", help = "Your own address (repeatable); splits the counts into sent and received")]
+        me: Vec<String>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Summarize attachments by type: how many there are and how much space they take
+    Attachments {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = AttachmentGrouping::Type, help = "Group by MIME type or by file extension")]
+        by: AttachmentGrouping,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// List correspondents by message volume, with first/last contact and sent/received counts
+    Contacts {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_name = "ADDRESS", help = "Your own address (repeatable); leaves you out and counts only your messages as sent")]
+        me: Vec<String>,
+
+        #[arg(long, default_value_t = 100, help = "Show at most this many contacts (0 for all)")]
+        limit: usize,
+
+        #[arg(long, value_enum, default_value_t = TableFormat::Table, help = "Output format")]
+        format: TableFormat,
+
+        #[arg(short, long, help = "Write to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// List messages that reply to or reference mail missing from the archive (incomplete conversations)
+    Orphans {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Write one mbox file per label (needs --schema full), or NDJSON documents for Elasticsearch or Meilisearch
+    Export {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::Mbox, help = "Output format")]
+        format: ExportFormat,
+
+        #[arg(long, value_name = "DIR", help = "Directory for the .mbox files or the Xapian database (created if missing)")]
+        dir: Option<PathBuf>,
+
+        #[arg(short, long, help = "Write the es-bulk or meilisearch documents to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// List every attachment with its size, SHA-256 and message, for scanners and dedup tools
+    Manifest {
+        #[arg(help = "Database created by mbox2db")]
+        database: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ManifestFormat::Csv, help = "Output format")]
+        format: ManifestFormat,
+
+        #[arg(short, long, help = "Write to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        tables: TableArgs,
+    },
+
+    /// Time each import stage (split, parse, dates, transform, insert) on an mbox, into a scratch database
+    Bench {
+        #[arg(help = "Input mbox file path")]
+        input: PathBuf,
+
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help = "Stop after this many messages")]
+        limit: Option<u32>,
+
+        #[command(flatten)]
+        import: Box<ImportArgs>,
+    },
+
+    /// Answer a few questions (schema, filters, FTS, attachments, output) and write a config file with import defaults
+    Init {
+        #[arg(help = "Where to write the config file (default: ~/.config/mbox2db/config.toml, which imports read without --config)")]
+        path: Option<PathBuf>,
+
+        #[arg(long, help = "Replace the config file if there already is one")]
+        force: bool,
+    },
+
+    /// Print a shell completion script (bash, zsh, fish, elvish, powershell)
+    Completions {
+        #[arg(value_enum, help = "Shell to generate completions for")]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the man page, or write one page per subcommand into a directory
+    Manpage {
+        #[arg(long, value_name = "DIR", help = "Write mbox2db.1, mbox2db-render.1, ... into this directory instead of printing mbox2db.1")]
+        dir: Option<PathBuf>,
+    },
+
+    /// Fetch messages straight from an IMAP server (over TLS) instead of an mbox file
+    #[cfg(feature = "imap")]
+    Imap {
+        #[arg(long, help = "IMAP server host name, e.g. imap.gmail.com")]
+        server: String,
+
+        #[arg(long, default_value_t = 993, help = "IMAPS port")]
+        port: u16,
+
+        #[arg(long, help = "Login name, usually the email address")]
+        user: String,
+
+        #[arg(long, env = "MBOX2DB_IMAP_PASSWORD", hide_env_values = true, help = "Password or app password (prefer the environment variable)")]
+        password: Option<String>,
+
+        #[arg(long, env = "MBOX2DB_IMAP_OAUTH2_TOKEN", hide_env_values = true, conflicts_with = "password", help = "OAuth2 access token, sent with SASL XOAUTH2")]
+        oauth2_token: Option<String>,
+
+        #[arg(long, default_value = "INBOX", help = "Folder to fetch (repeatable); it's recorded as the message's label")]
+        folder: Vec<String>,
+
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..), help = "Folders to download at once, each over its own connection")]
+        concurrency: u16,
+
+        #[command(flatten)]
+        import: Box<ImportArgs>,
+    },
+
+    /// Download a mailbox from a POP3 server (over TLS) instead of reading an mbox file
+    #[cfg(feature = "pop3")]
+    Pop3 {
+        #[arg(long, help = "POP3 server host name, e.g. pop.example.com")]
+        server: String,
+
+        #[arg(long, default_value_t = 995, help = "POP3S port")]
+        port: u16,
+
+        #[arg(long, help = "Login name, usually the email address")]
+        user: String,
+
+        #[arg(long, env = "MBOX2DB_POP3_PASSWORD", hide_env_values = true, help = "Password (prefer the environment variable)")]
+        password: String,
+
+        #[arg(long, help = "Delete messages from the server once the import has been committed (default: leave them)")]
+        delete_after_import: bool,
+
+        #[command(flatten)]
+        import: Box<ImportArgs>,
+    },
+
+    /// Pull mail from Exchange Online / Microsoft 365 through Microsoft Graph
+    #[cfg(feature = "graph")]
+    Graph {
+        #[arg(long, help = "Mailbox to read, e.g. user@contoso.com (default: the token's own mailbox)")]
+        mailbox: Option<String>,
+
+        #[arg(long, help = "Folder to fetch, by id or well-known name such as inbox or sentitems (repeatable; default: every message)")]
+        folder: Vec<String>,
+
+        #[arg(long, env = "MBOX2DB_GRAPH_TOKEN", hide_env_values = true, help = "Access token with Mail.Read (prefer the environment variable)")]
+        access_token: Option<String>,
+
+        #[arg(long, requires_all = ["client_id", "client_secret"], conflicts_with = "access_token", help = "Azure AD tenant id, for app-only access with an app registration")]
+        tenant_id: Option<String>,
+
+        #[arg(long, requires = "tenant_id", help = "Application (client) id of the app registration")]
+        client_id: Option<String>,
+
+        #[arg(long, env = "MBOX2DB_GRAPH_CLIENT_SECRET", hide_env_values = true, help = "Client secret of the app registration")]
+        client_secret: Option<String>,
 
-    Ok(conn)
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..), help = "Messages to download at once (lower it if Graph keeps throttling)")]
+        concurrency: u16,
+
+        #[command(flatten)]
+        import: Box<ImportArgs>,
+    },
 }
 
-static GMT_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"GMT([+-])(\d{2}):?(\d{2})").unwrap());
-static TZ_3DIGIT: Lazy<Regex> = Lazy::new(|| Regex::new(r"([+-])(\d{3})\s*$").unwrap());
-static SINGLE_DIGIT_TIME: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d):(\d{2}):(\d{2})\b").unwrap());
-static SINGLE_DIGIT_MIN_SEC: Lazy<Regex> = Lazy::new(|| Regex::new(r":(\d)\b").unwrap());
 
-fn parse_email_date(date_str: &str) -> Option<String> {
-    let mut cleaned = date_str.trim().to_string();
-    
-    // Skip empty dates
-    if cleaned.is_empty() {
-        return None;
-    }
-    
-    // Fix: Double-dash timezone (e.g., "--0400" -> "-0400")
-    cleaned = cleaned.replace("--", "-");
-    
-    // Fix: Strip garbage after timezone (e.g., "+0000.395-508222")
-    if let Some(tz_pos) = cleaned.rfind(|c: char| c == '+' || c == '-') {
-        if tz_pos > 0 && tz_pos + 5 < cleaned.len() {
-            let after_tz = &cleaned[tz_pos + 5..];
-            if after_tz.chars().any(|c| !c.is_whitespace()) {
-                cleaned = cleaned[..tz_pos + 5].to_string();
-            }
-        }
+fn get_output_path(cli_output: Option<PathBuf>, output_dir: Option<&Path>, split_by: Option<SplitBy>, destructive: bool) -> PathBuf {
+    if let Some(path) = cli_output {
+        return path;
     }
-    
-    // Fix: Strip timezone name in parentheses (e.g., "(Eastern Daylight Time)")
-    if cleaned.contains('(') {
-        cleaned = cleaned.split('(').next().unwrap_or(&cleaned).trim().to_string();
+    let dir = output_dir.unwrap_or(Path::new(""));
+    // Prefixed per split, e.g. 2019-emails.db; the deprecated -d always wrote
+    // emails.db, and scripts using it still expect it there
+    if split_by.is_some() || destructive {
+        return dir.join("emails.db");
     }
     
-    // Fix: GMT timezones with regex (GMT-07:00, GMT-0700, etc.)
-    cleaned = GMT_PATTERN.replace_all(&cleaned, "$1$2$3").to_string();
-    
-    // Fix: Replace long timezone names and abbreviations
-    cleaned = cleaned
-        .replace("Eastern Daylight Time", "-0400")
-        .replace("Eastern Standard Time", "-0500")
-        .replace("Pacific Daylight Time", "-0700")
-        .replace("Pacific Standard Time", "-0800")
-        .replace("Central Daylight Time", "-0500")
-        .replace("Central Standard Time", "-0600")
-        .replace("Mountain Daylight Time", "-0600")
-        .replace("Mountain Standard Time", "-0700")
-        .replace(" UTC", " +0000")
-        .replace(" GMT", " +0000")
-        .replace(" EDT", " -0400")
-        .replace(" EST", " -0500")
-        .replace(" CDT", " -0500")
-        .replace(" CST", " -0600")
-        .replace(" PDT", " -0700")
-        .replace(" PST", " -0800")
-        .replace(" CET", " +0100");
-    
-    // Fix: 3-digit timezone without leading zero (e.g., "-600" -> "-0600")
-    cleaned = TZ_3DIGIT.replace_all(&cleaned, "${1}0$2").to_string();
-    
-    // Fix: Single-digit hour (e.g., "9:47:11" -> "09:47:11")
-    cleaned = SINGLE_DIGIT_TIME.replace_all(&cleaned, "0$1:$2:$3").to_string();
-    
-    // Fix: Single-digit minute/second (e.g., "21:9:7" -> "21:09:07")
-    cleaned = SINGLE_DIGIT_MIN_SEC.replace_all(&cleaned, ":0$1").to_string();
-    
-    // Fix: PM/AM with timezone (e.g., "PM+0400" or "PM CDT")
-    cleaned = cleaned.replace("PM+", " +").replace("PM-", " -").replace("AM+", " +").replace("AM-", " -").replace(" PM ", " ").replace(" AM ", " ");
-    
-    // Fix: Full day names (e.g., "Thursday" -> "Thu", "Thurs" -> "Thu")
-    cleaned = cleaned
-        .replace("Monday", "Mon")
-        .replace("Tuesday", "Tue")
-        .replace("Wednesday", "Wed")
-        .replace("Thursday", "Thu")
-        .replace("Thurs,", "Thu,")
-        .replace("Friday", "Fri")
-        .replace("Saturday", "Sat")
-        .replace("Sunday", "Sun");
-    
-    // Fix: Full month names (e.g., "March" -> "Mar")
-    cleaned = cleaned
-        .replace("January", "Jan")
-        .replace("February", "Feb")
-        .replace("March", "Mar")
-        .replace("April", "Apr")
-        .replace("June", "Jun")
-        .replace("July", "Jul")
-        .replace("August", "Aug")
-        .replace("September", "Sep")
-        .replace("October", "Oct")
-        .replace("November", "Nov")
-        .replace("December", "Dec");
-    
-    // Try standard RFC2822
-    if let Ok(dt) = DateTime::parse_from_rfc2822(&cleaned) {
-        return Some(dt.format("%Y-%m-%d %H:%M:%S").to_string());
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    dir.join(format!("{}-emails.db", today))
+}
+
+/// The path in a `sqlite:` or `file:` URL, as container setups pass databases
+/// around: "sqlite:///data/mail.db" is /data/mail.db, "sqlite://mail.db" and
+/// "sqlite:mail.db" are relative. Query parameters are ignored.
+fn parse_db_url(url: &str) -> std::result::Result<PathBuf, String> {
+    let path = ["sqlite:", "file:"]
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+        .ok_or_else(|| format!("{:?} isn't a sqlite: or file: URL (mbox2db only writes SQLite)", url))?;
+    let path = path.strip_prefix("//").unwrap_or(path);
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    if path.is_empty() {
+        return Err(format!("{:?} has no database path", url));
     }
-    
-    // Fix: Missing comma after day-of-week (e.g., "Tue 02 Mar" -> "Tue, 02 Mar")
-    if let Some(first_word) = cleaned.split_whitespace().next() {
-        if first_word.len() == 3 && !cleaned.starts_with(&format!("{},", first_word)) {
-            let with_comma = cleaned.replacen(first_word, &format!("{},", first_word), 1);
-            if let Ok(dt) = DateTime::parse_from_rfc2822(&with_comma) {
-                return Some(dt.format("%Y-%m-%d %H:%M:%S").to_string());
-            }
+    Ok(PathBuf::from(path))
+}
+
+impl ImportArgs {
+    /// The --config file, else the default one if it exists, with --profile applied.
+    fn config(&self) -> Result<Config> {
+        match self.config.clone().or_else(|| Config::default_path().filter(|path| path.is_file())) {
+            Some(path) => Config::load(&path, self.profile.as_deref()),
+            None if self.profile.is_some() => anyhow::bail!("--profile needs a config file with [profile.<name>] sections; run mbox2db init or pass --config"),
+            None => Ok(Config::default()),
         }
     }
-    
-    // Fix: Two-digit year (e.g., "Thu, 11 Jun 09" -> "Thu, 11 Jun 2009")
-    let parts: Vec<&str> = cleaned.split_whitespace().collect();
-    if parts.len() >= 4 {
-        if let Some(year_part) = parts.get(3) {
-            if year_part.len() == 2 && year_part.chars().all(|c| c.is_ascii_digit()) {
-                if let Ok(year) = year_part.parse::<u32>() {
-                    let full_year = if year > 50 { 1900 + year } else { 2000 + year };
-                    let fixed = cleaned.replace(&format!(" {} ", year_part), &format!(" {} ", full_year));
-                    if let Ok(dt) = DateTime::parse_from_rfc2822(&fixed) {
-                        return Some(dt.format("%Y-%m-%d %H:%M:%S").to_string());
-                    }
-                }
-            }
+
+    /// Resolves the output path and the import options.
+    fn options(&self) -> Result<(PathBuf, ImportOptions)> {
+        let tables = self.tables.tables()?;
+        let fts_tokenize = self.fts_tokenizer.spec(self.fts_remove_diacritics)?;
+        let config = self.config()?;
+        let output = self.output.clone().or(self.db_url.clone()).or(config.output.clone());
+        let output_path = get_output_path(output, self.output_dir.as_deref(), self.split_by, self.destructive);
+        let schema = self.schema.or(config.schema).unwrap_or(SchemaProfile::Normalized);
+        if self.chats == Chats::Table && schema == SchemaProfile::Flat {
+            anyhow::bail!("--chats table needs the chats table; use --schema normalized or full");
         }
-    }
-    
-    // Fix: ctime format without timezone (e.g., "Thu Jul 20 11:39:51 2006")
-    if parts.len() == 5 {
-        let format_str = format!("{} {} {} {} {}", parts[0], parts[1], parts[2], parts[3], parts[4]);
-        if let Ok(naive) = NaiveDateTime::parse_from_str(&format_str, "%a %b %d %H:%M:%S %Y") {
-            return Some(naive.format("%Y-%m-%d %H:%M:%S").to_string());
+        let extract_amounts = self.extract_amounts || config.extract_amounts;
+        if extract_amounts && schema == SchemaProfile::Flat {
+            anyhow::bail!("--extract-amounts needs the receipts and amounts tables; use --schema normalized or full");
         }
-    }
-    
-    // Try M/D/YYYY format (e.g., "7/19/2005 8:11:52 AM")
-    if cleaned.contains('/') {
-        // Try various formats
-        let formats = [
-            "%m/%d/%Y %I:%M:%S %p",
-            "%m/%d/%Y %H:%M:%S",
-            "%m/%d/%Y",
-        ];
-        for fmt in &formats {
-            if let Ok(naive) = NaiveDateTime::parse_from_str(&cleaned, fmt) {
-                return Some(naive.format("%Y-%m-%d %H:%M:%S").to_string());
-            }
+        if !self.me.is_empty() && schema == SchemaProfile::Flat {
+            anyhow::bail!("--me scores importance from the recipients and threads tables; use --schema normalized or full");
         }
+        if self.consolidate_labels && schema == SchemaProfile::Flat {
+            anyhow::bail!("--consolidate-labels keeps the labels in the labels table; use --schema normalized or full");
+        }
+        let if_exists = match (self.destructive, self.if_exists.or(config.if_exists)) {
+            (true, _) => IfExists::Overwrite,
+            (false, _) if self.incremental => match self.if_exists {
+                None | Some(IfExists::Append) => IfExists::Append,
+                Some(_) => anyhow::bail!("--incremental appends to the existing database; it can only be combined with --if-exists append"),
+            },
+            (false, if_exists) => if_exists.unwrap_or(IfExists::Fail),
+        };
+        let aliases = match &self.aliases {
+            Some(path) => AliasMap::load(path)?,
+            None => AliasMap::default(),
+        };
+        let decryptor = match &self.gpg_keyring {
+            Some(path) => Some(GpgDecryptor::new(path)?),
+            None => None,
+        };
+
+        let options = ImportOptions {
+            include_spam: self.include_spam || config.include_spam,
+            include_trash: self.include_trash || config.include_trash,
+            include_both: self.include_spam_and_trash || config.include_spam_and_trash,
+            chats: self.chats,
+            skip_calendar: self.skip_calendar || config.skip_calendar,
+            aliases,
+            decryptor,
+            store_signatures: self.store_signatures,
+            extract_amounts,
+            fts: self.fts || config.fts,
+            fts_tokenize,
+            sanitize_html: self.sanitize_html || config.sanitize_html,
+            prefer: self.prefer.or(config.prefer).unwrap_or(Prefer::Both),
+            account: self.account.clone().or(config.account.clone()),
+            if_exists,
+            incremental: self.incremental,
+            merge_copies: false,
+            consolidate_labels: self.consolidate_labels,
+            schema,
+            tables,
+            extensions: self.load_extension.clone(),
+            pragmas: self.pragma.clone(),
+            views: !self.no_views,
+            post_sql: self.post_sql.clone(),
+            split_by: self.split_by,
+            max_attachment_size: self.max_attachment_size.or(config.max_attachment_size()),
+            oversized_attachments: self.oversized_attachments.or(config.oversized_attachments).unwrap_or(OversizedAttachments::Hash),
+            attachments_in_db: self.attachments_in_db || config.attachments_in_db,
+            header_filter: config.header_filter(),
+            me: self.me.clone(),
+            date_parser: DateParser::new(config.date_formats, self.ambiguous_tz),
+            display_tz: self.display_tz,
+            two_pass: self.two_pass,
+            space_check: !self.no_space_check,
+            busy_timeout: Duration::from_secs(self.busy_timeout),
+            keep_going: self.keep_going,
+            summary_json: self.summary_json.clone(),
+            warnings: self.warnings_file.as_deref().map(WarningLog::create).transpose()?,
+        };
+
+        Ok((output_path, options))
     }
-    
-    None
 }
 
-fn should_skip_email(labels: &str, include_spam: bool, include_trash: bool, include_both: bool) -> bool {
-    if include_both {
-        return false; // Include everything
-    }
-    
-    let labels_lower = labels.to_lowercase();
-    let is_spam = labels_lower.contains("spam");
-    let is_trash = labels_lower.contains("trash");
-    
-    if is_spam && !include_spam && !include_both {
-        return true;
+fn render_command(database: &Path, tables: &Tables, id: i64, output: Option<&Path>) -> Result<()> {
+    let conn = open_database(database)?;
+    let html = render::render_message(&conn, tables, id, false)?;
+
+    match output {
+        Some(path) => std::fs::write(path, html)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => print!("{}", html),
     }
-    
-    if is_trash && !include_trash && !include_both {
-        return true;
+
+    Ok(())
+}
+
+fn show_command(database: &Path, tables: &Tables, id: i64, raw: bool, width: u16) -> Result<()> {
+    let conn = open_database(database)?;
+    let mut out = std::io::stdout().lock();
+    if raw {
+        out.write_all(&render::raw_message(&conn, tables, id)?)?;
+    } else {
+        out.write_all(render::show_message(&conn, tables, id, width as usize)?.as_bytes())?;
     }
-    
-    false
+    out.flush()?;
+    Ok(())
 }
 
-fn process_mbox(input_path: &PathBuf, output_path: &PathBuf, include_spam: bool, include_trash: bool, include_both: bool) -> Result<()> {
-    let file = File::open(input_path)
-        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
-    let reader = BufReader::new(file);
-
-    let mut conn = create_database(output_path)?;
-
-    let tx = conn.transaction()?;
-
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
-            .template("{spinner:.cyan} {msg}")
-            .unwrap()
-    );
-    spinner.set_message("Starting conversion...");
-
-    let mut current_email = Vec::new();
-    let mut email_count = 0;
-    let mut skipped_count = 0;
-
-    for line in reader.lines() {
-        let line = line?;
-
-        if line.starts_with("From ") && !current_email.is_empty() {
-            match extract_email_data(&current_email) {
-                Ok(record) => {
-                    if should_skip_email(&record.gmail_labels, include_spam, include_trash, include_both) {
-                        skipped_count += 1;
-                    } else {
-                        let date_parsed = parse_email_date(&record.date);
-                        tx.execute(
-                            "INSERT INTO emails (from_addr, to_addr, cc, bcc, subject, date, date_parsed, message_id, in_reply_to, refs, content_type, body_plain, body_html)
-                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
-                            params![
-                                &record.from,
-                                &record.to,
-                                &record.cc,
-                                &record.bcc,
-                                &record.subject,
-                                &record.date,
-                                &date_parsed,
-                                &record.message_id,
-                                &record.in_reply_to,
-                                &record.references,
-                                &record.content_type,
-                                &record.body_plain,
-                                &record.body_html,
-                            ],
-                        )?;
-                        email_count += 1;
-                        if email_count % 100 == 0 {
-                            spinner.set_message(format!("Processed {} emails ({} skipped)", email_count, skipped_count));
-                            spinner.tick();
-                        }
-                    }
-                }
-                Err(e) => {
-                    spinner.println(format!("Warning: Failed to parse email {}: {}", email_count + skipped_count + 1, e));
-                }
-            }
-            current_email.clear();
+/// Writes the rendered message to a temporary file and hands it to the
+/// browser in $BROWSER, or the system's default handler for HTML files.
+fn open_command(database: &Path, tables: &Tables, id: i64) -> Result<()> {
+    let conn = open_database(database)?;
+    let html = render::render_message(&conn, tables, id, true)?;
+
+    // Created exclusively under a random name so a file or symlink planted in
+    // the shared temp directory can't be written through. Left in place: the
+    // browser may only read it after we've exited
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("mbox2db-{}-", id))
+        .suffix(".html")
+        .tempfile()
+        .context("Failed to create a temporary file")?;
+    file.write_all(html.as_bytes())
+        .with_context(|| format!("Failed to write {}", file.path().display()))?;
+    let (_, path) = file.keep().context("Failed to keep the temporary file")?;
+
+    let mut command = match std::env::var("BROWSER") {
+        Ok(browser) if !browser.trim().is_empty() => std::process::Command::new(browser),
+        _ if cfg!(target_os = "macos") => std::process::Command::new("open"),
+        _ if cfg!(windows) => {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
         }
+        _ => std::process::Command::new("xdg-open"),
+    };
+    let status = command
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to start a browser; the message is in {}", path.display()))?;
+    if !status.success() {
+        anyhow::bail!("The browser exited with {}; the message is in {}", status, path.display());
+    }
+    println!("Opened {}", path.display());
+    Ok(())
+}
+
+fn open_database(database: &Path) -> Result<Connection> {
+    Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open database: {}", database.display()))
+}
 
-        current_email.extend_from_slice(line.as_bytes());
-        current_email.push(b'\n');
+/// The file given with --output, or stdout.
+fn create_output(output: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn thread_command(database: &Path, tables: &Tables, id: &str, format: ThreadFormat, output: Option<&Path>) -> Result<()> {
+    let conn = open_database(database)?;
+    let rendered = thread::render_thread(&conn, tables, id, format)?;
+
+    match output {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => print!("{}", rendered),
     }
 
-    if !current_email.is_empty() {
-        match extract_email_data(&current_email) {
-            Ok(record) => {
-                if should_skip_email(&record.gmail_labels, include_spam, include_trash, include_both) {
-                    skipped_count += 1;
-                } else {
-                    let date_parsed = parse_email_date(&record.date);
-                    tx.execute(
-                        "INSERT INTO emails (from_addr, to_addr, cc, bcc, subject, date, date_parsed, message_id, in_reply_to, refs, content_type, body_plain, body_html)
-                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
-                        params![
-                            &record.from,
-                            &record.to,
-                            &record.cc,
-                            &record.bcc,
-                            &record.subject,
-                            &record.date,
-                            &date_parsed,
-                            &record.message_id,
-                            &record.in_reply_to,
-                            &record.references,
-                            &record.content_type,
-                            &record.body_plain,
-                            &record.body_html,
-                        ],
-                    )?;
-                    email_count += 1;
-                }
+    Ok(())
+}
+
+fn export_command(database: &Path, tables: &Tables, format: ExportFormat, dir: Option<&Path>, output: Option<&Path>) -> Result<()> {
+    let conn = open_database(database)?;
+
+    match (format, dir, output) {
+        (ExportFormat::Mbox, Some(dir), None) => {
+            for (path, count) in export::export_label_mboxes(&conn, tables, dir)? {
+                println!("{} ({} emails)", path.display(), count);
             }
-            Err(e) => {
-                spinner.println(format!("Warning: Failed to parse email {}: {}", email_count + skipped_count + 1, e));
+        }
+        #[cfg(feature = "xapian")]
+        (ExportFormat::Xapian, Some(dir), None) => {
+            let count = export::write_xapian_index(&conn, tables, dir)?;
+            println!("Indexed {} messages into {}", count, dir.display());
+        }
+        (ExportFormat::Mbox, _, _) => anyhow::bail!("--format mbox writes one file per label into --dir (not --output)"),
+        #[cfg(feature = "xapian")]
+        (ExportFormat::Xapian, _, _) => anyhow::bail!("--format xapian writes a Xapian database into --dir (not --output)"),
+        (_, Some(_), _) => anyhow::bail!("--dir is for --format mbox and xapian; pass --output, or nothing for stdout"),
+        (format, None, output) => {
+            let mut out = create_output(output)?;
+            let count = export::write_search_documents(&conn, tables, &mut out, format)?;
+            out.flush()?;
+            if output.is_some() {
+                println!("{} documents written", count);
             }
         }
     }
+    Ok(())
+}
 
-    spinner.set_message("Committing to database...");
-    spinner.tick();
-    tx.commit()?;
-
-    let skip_message = if skipped_count > 0 && !include_both {
-        if !include_spam && !include_trash {
-            format!("\n    {} Spam/Trash emails skipped (pass --include-spam-and-trash to include them)", skipped_count)
-        } else if !include_spam {
-            format!("\n    {} Spam emails skipped (pass --include-spam to include them)", skipped_count)
-        } else if !include_trash {
-            format!("\n    {} Trash emails skipped (pass --include-trash to include them)", skipped_count)
-        } else {
-            String::new()
-        }
+fn search_command(database: &Path, tables: &Tables, query: &str, limit: usize, fuzzy: bool) -> Result<()> {
+    let conn = open_database(database)?;
+    // Bold on a terminal; brackets when piped, so the matches still stand out
+    let highlight = if std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+        Highlight { start: "\x1b[1m", end: "\x1b[0m" }
     } else {
-        String::new()
+        Highlight { start: "[", end: "]" }
     };
+    let hits = if fuzzy {
+        search::fuzzy_search(&conn, tables, query, limit, highlight)?
+    } else {
+        search::search(&conn, tables, query, limit, highlight)?
+    };
+    if hits.is_empty() {
+        eprintln!("No messages match {:?}", query);
+        return Ok(());
+    }
+    search::write_hits(&mut std::io::stdout().lock(), &hits)
+}
 
-    spinner.finish_with_message(format!("✓ Successfully converted {} emails to database{}", email_count, skip_message));
-    println!("Database written to: {}", output_path.display());
+fn init_command(path: Option<&Path>, force: bool) -> Result<()> {
+    let default_path = Config::default_path();
+    let path = path
+        .map(Path::to_path_buf)
+        .or_else(|| default_path.clone())
+        .context("Can't tell where your config directory is; give the config file's path")?;
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists; pass --force to replace it", path.display());
+    }
+
+    let config = init::run_wizard(&mut std::io::stdin().lock(), &mut std::io::stdout())?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    std::fs::write(&path, config).with_context(|| format!("Failed to write {}", path.display()))?;
 
+    println!("\nWrote {}", path.display());
+    if Some(&path) == default_path.as_ref() {
+        println!("Imports use it unless --config names another file");
+    } else {
+        println!("Use it with: mbox2db <mbox> --config {}", path.display());
+    }
     Ok(())
 }
 
-fn get_output_path(cli_output: Option<PathBuf>, destructive: bool) -> PathBuf {
-    if let Some(path) = cli_output {
-        return path;
-    }
-    
-    if destructive {
-        return PathBuf::from("emails.db");
-    }
-    
-    let today = Local::now().format("%Y-%m-%d").to_string();
-    
-    let base_file = PathBuf::from(format!("{}-emails.db", today));
-    if !base_file.exists() {
-        return base_file;
+fn manpage_command(dir: Option<&Path>) -> Result<()> {
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+            clap_mangen::generate_to(Cli::command(), dir)
+                .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+        }
+        None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?,
     }
-    
-    for counter in 1..10000 {
-        let numbered_file = PathBuf::from(format!("{}-emails-{:04}.db", today, counter));
-        if !numbered_file.exists() {
-            return numbered_file;
+    Ok(())
+}
+
+/// Message embeddings written once the import has been committed.
+#[cfg(feature = "embeddings")]
+#[derive(Args)]
+struct EmbeddingArgs {
+    #[arg(long, requires = "embedding_model", help = "After the import, embed every message's subject and clean body into the vectors table (resumable)")]
+    embed: bool,
+
+    #[arg(long, value_name = "URL", default_value = embeddings::DEFAULT_URL, help = "Base URL of an OpenAI-compatible API for --embed; /embeddings is appended (default: a local Ollama)")]
+    embedding_url: String,
+
+    #[arg(long, value_name = "MODEL", help = "Embedding model for --embed, e.g. nomic-embed-text or text-embedding-3-small")]
+    embedding_model: Option<String>,
+
+    #[arg(long, env = "MBOX2DB_EMBEDDING_API_KEY", hide_env_values = true, help = "API key for --embedding-url, sent as a bearer token (prefer the environment variable)")]
+    embedding_api_key: Option<String>,
+
+    #[arg(long, value_name = "N", default_value_t = 64, value_parser = clap::value_parser!(u16).range(1..), help = "Messages embedded per request")]
+    embedding_batch_size: u16,
+
+    #[arg(long, value_name = "N", default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..), help = "Most requests --embed sends per minute")]
+    embedding_rate: u32,
+
+    #[arg(long, requires = "embed", help = "Also (re)build vec_emails, a sqlite-vec table for nearest-neighbor queries (needs --load-extension with sqlite-vec)")]
+    vec_index: bool,
+}
+
+#[cfg(feature = "embeddings")]
+impl EmbeddingArgs {
+    /// Embeds the messages of every database the import wrote to.
+    fn run(&self, tables: &Tables, extensions: &[PathBuf], outcome: &ImportOutcome) -> Result<()> {
+        let (true, Some(model)) = (self.embed, &self.embedding_model) else {
+            return Ok(());
+        };
+        if outcome.interrupted {
+            return Ok(());
         }
+        let embedder = Embedder::new(
+            &self.embedding_url,
+            model,
+            self.embedding_api_key.clone(),
+            self.embedding_batch_size as usize,
+            self.embedding_rate,
+        )?;
+        for (database, _) in &outcome.databases {
+            let counts = embeddings::embed_messages(database, tables, &embedder)?;
+            println!("Embedded {} messages in {} ({} reused a duplicate's vector)", counts.embedded + counts.cached, database.display(), counts.cached);
+            if counts.remaining > 0 {
+                println!("Interrupted with {} messages to go; run again to embed them", counts.remaining);
+                break;
+            }
+            if self.vec_index {
+                let indexed = embeddings::build_vec_index(database, tables, model, extensions)?;
+                println!("Indexed {} vectors in {}", indexed, tables.vec_emails);
+            }
+        }
+        Ok(())
     }
-    
-    base_file
 }
 
-fn main() -> Result<()> {
+/// Imports from `source`, then runs what works on the finished database.
+fn import_command(source: &mut dyn sources::MessageSource, import: &ImportArgs) -> Result<ExitCode> {
+    let (output_path, mut options) = import.options()?;
+    options.merge_copies = source.has_copies();
+    let outcome = run_import(source, &output_path, options)?;
+    #[cfg(feature = "summarize")]
+    import.summarize.run(&import.tables.tables()?, &outcome)?;
+    #[cfg(feature = "embeddings")]
+    import.embeddings.run(&import.tables.tables()?, &import.load_extension, &outcome)?;
+    Ok(ExitCode::from(outcome.exit_code()))
+}
+
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
-    let output_path = get_output_path(cli.output, cli.destructive);
 
-    process_mbox(
-        &cli.input, 
-        &output_path, 
-        cli.include_spam, 
-        cli.include_trash, 
-        cli.include_spam_and_trash
-    )?;
+    if let Some(command) = &cli.command {
+        return match command {
+            Commands::Search { database, query, limit, fuzzy, tables } => {
+                search_command(database, &tables.tables()?, query, *limit, *fuzzy)
+            }
+            Commands::Render { database, id, output, tables } => render_command(database, &tables.tables()?, *id, output.as_deref()),
+            Commands::Show { database, id, raw, width, tables } => show_command(database, &tables.tables()?, *id, *raw, *width),
+            Commands::Open { database, id, tables } => open_command(database, &tables.tables()?, *id),
+            Commands::Thread { database, id, format, output, tables } => {
+                thread_command(database, &tables.tables()?, id, *format, output.as_deref())
+            }
+            Commands::Network { database, format, output, tables } => {
+                let conn = open_database(database)?;
+                let edges = report::correspondent_edges(&conn, &tables.tables()?)?;
+                let mut out = create_output(output.as_deref())?;
+                report::write_network(&mut out, &edges, *format)?;
+                out.flush()?;
+                Ok(())
+            }
+            Commands::Latency { database, me, limit, tables } => {
+                let conn = open_database(database)?;
+                let mut latencies = report::reply_latency(&conn, &tables.tables()?, me)?;
+                latencies.truncate(*limit);
+                report::write_latency(&mut std::io::stdout().lock(), &latencies)
+            }
+            Commands::Activity { database, me, tables } => {
+                let conn = open_database(database)?;
+                let (hours, weekdays) = report::activity(&conn, &tables.tables()?, me)?;
+                let mut out = std::io::stdout().lock();
+                report::write_activity(&mut out, "By hour of day", &hours, !me.is_empty())?;
+                writeln!(out)?;
+                report::write_activity(&mut out, "By day of week", &weekdays, !me.is_empty())
+            }
+            Commands::Attachments { database, by, tables } => {
+                let conn = open_database(database)?;
+                let stats = report::attachment_stats(&conn, &tables.tables()?, *by)?;
+                report::write_attachment_stats(&mut std::io::stdout().lock(), &stats, *by)
+            }
+            Commands::Contacts { database, me, limit, format, output, tables } => {
+                let conn = open_database(database)?;
+                let contacts = report::contacts(&conn, &tables.tables()?, me, *limit)?;
+                let mut out = create_output(output.as_deref())?;
+                report::write_contacts(&mut out, &contacts, *format)?;
+                out.flush()?;
+                Ok(())
+            }
+            Commands::Orphans { database, tables } => {
+                let conn = open_database(database)?;
+                let orphans = report::orphans(&conn, &tables.tables()?)?;
+                report::write_orphans(&mut std::io::stdout().lock(), &orphans)
+            }
+            Commands::Export { database, format, dir, output, tables } => {
+                export_command(database, &tables.tables()?, *format, dir.as_deref(), output.as_deref())
+            }
+            Commands::Manifest { database, format, output, tables } => {
+                let conn = open_database(database)?;
+                let mut out = create_output(output.as_deref())?;
+                let count = export::write_attachment_manifest(&conn, &tables.tables()?, &mut out, *format)?;
+                out.flush()?;
+                if output.is_some() {
+                    println!("{} attachments listed", count);
+                }
+                Ok(())
+            }
+            Commands::Bench { input, limit, import } => {
+                let (_, options) = import.options()?;
+                let report = bench::run_bench(input, options, *limit)?;
+                bench::write_bench(&mut std::io::stdout().lock(), &report)
+            }
+            Commands::Completions { shell } => {
+                clap_complete::generate(*shell, &mut Cli::command(), "mbox2db", &mut std::io::stdout());
+                Ok(())
+            }
+            Commands::Manpage { dir } => manpage_command(dir.as_deref()),
+            Commands::Init { path, force } => init_command(path.as_deref(), *force),
+            #[cfg(feature = "imap")]
+            Commands::Imap { server, port, user, password, oauth2_token, folder, concurrency, import } => {
+                let auth = match (password, oauth2_token) {
+                    (_, Some(token)) => sources::ImapAuth::OAuth2(token.clone()),
+                    (Some(password), None) => sources::ImapAuth::Password(password.clone()),
+                    (None, None) => anyhow::bail!("Pass --password or --oauth2-token (or set MBOX2DB_IMAP_PASSWORD / MBOX2DB_IMAP_OAUTH2_TOKEN)"),
+                };
+                let mut source = sources::ImapSource::connect(server, *port, user, auth, folder.clone(), *concurrency as usize)?;
+                return import_command(&mut source, import);
+            }
+            #[cfg(feature = "graph")]
+            Commands::Graph { mailbox, folder, access_token, tenant_id, client_id, client_secret, concurrency, import } => {
+                let auth = match (access_token, tenant_id, client_id, client_secret) {
+                    (_, Some(tenant_id), Some(client_id), Some(client_secret)) => sources::GraphAuth::ClientCredentials {
+                        tenant_id: tenant_id.clone(),
+                        client_id: client_id.clone(),
+                        client_secret: client_secret.clone(),
+                    },
+                    (Some(token), _, _, _) => sources::GraphAuth::AccessToken(token.clone()),
+                    _ => anyhow::bail!("Pass --access-token (or MBOX2DB_GRAPH_TOKEN), or --tenant-id, --client-id and --client-secret"),
+                };
+                let mut source = sources::GraphSource::connect(&auth, mailbox.clone(), folder.clone(), *concurrency as usize)?;
+                return import_command(&mut source, import);
+            }
+            #[cfg(feature = "pop3")]
+            Commands::Pop3 { server, port, user, password, delete_after_import, import } => {
+                let mut source = sources::Pop3Source::connect(server, *port, user, password, *delete_after_import)?;
+                return import_command(&mut source, import);
+            }
+        }
+        .map(|()| ExitCode::SUCCESS);
+    }
+
+    let config = cli.import.config()?;
+    let inputs = if cli.input.is_empty() { config.input.into_iter().collect() } else { cli.input.clone() };
+    if !inputs.is_empty() {
+        let mut source = sources::MboxSource::open_all(&inputs)?;
+        return import_command(&mut source, &cli.import);
+    }
+    match config.imap {
+        #[cfg(feature = "imap")]
+        Some(imap) => {
+            let auth = match (std::env::var("MBOX2DB_IMAP_PASSWORD"), std::env::var("MBOX2DB_IMAP_OAUTH2_TOKEN")) {
+                (_, Ok(token)) => sources::ImapAuth::OAuth2(token),
+                (Ok(password), Err(_)) => sources::ImapAuth::Password(password),
+                _ => anyhow::bail!("Set MBOX2DB_IMAP_PASSWORD or MBOX2DB_IMAP_OAUTH2_TOKEN for {}", imap.server),
+            };
+            let folders = if imap.folders.is_empty() { vec!["INBOX".to_string()] } else { imap.folders };
+            let mut source = sources::ImapSource::connect(
+                &imap.server,
+                imap.port.unwrap_or(993),
+                &imap.user,
+                auth,
+                folders,
+                imap.concurrency.unwrap_or(4).max(1) as usize,
+            )?;
+            import_command(&mut source, &cli.import)
+        }
+        #[cfg(not(feature = "imap"))]
+        Some(_) => anyhow::bail!("The profile imports from IMAP; build mbox2db with --features imap"),
+        None => anyhow::bail!("The profile names no input; add input = \"path/to/file.mbox\" or an imap table to it"),
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_paths() {
+        let today = format!("{}-emails.db", Local::now().format("%Y-%m-%d"));
+        let dir = Path::new("archive");
+        let cases = [
+            (Some("mail.db"), None, None, false, "mail.db".to_string()),
+            (Some("mail.db"), Some(dir), None, true, "mail.db".to_string()),
+            (None, None, None, false, today.clone()),
+            (None, Some(dir), None, false, format!("archive/{}", today)),
+            (None, None, Some(SplitBy::Year), false, "emails.db".to_string()),
+            // -d without -o, as before --if-exists
+            (None, None, None, true, "emails.db".to_string()),
+            (None, Some(dir), None, true, "archive/emails.db".to_string()),
+        ];
+        for (output, output_dir, split_by, destructive, path) in cases {
+            assert_eq!(get_output_path(output.map(PathBuf::from), output_dir, split_by, destructive), PathBuf::from(&path), "{:?}", (output, output_dir, destructive));
+        }
+    }
 }
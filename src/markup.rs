@@ -0,0 +1,624 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::text::html_to_text;
+
+static JSON_LD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?is)<script\b[^>]*\btype\s*=\s*["']?application/ld\+json["']?[^>]*>(.*?)</script\s*>"#).unwrap()
+});
+static HTML_COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<!--.*?-->").unwrap());
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<(/?)([a-zA-Z][a-zA-Z0-9-]*)([^>]*)>").unwrap());
+static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#).unwrap()
+});
+
+/// Elements that never have a closing tag.
+const VOID_ELEMENTS: &[&str] =
+    &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+
+/// Every schema.org item in an HTML body, from JSON-LD scripts and from
+/// microdata. Microdata items come back shaped like JSON-LD, with `@type`
+/// set from `itemtype`, so both read the same way.
+pub fn items(html: &str) -> Vec<Value> {
+    let mut items = Vec::new();
+    if html.contains("application/ld+json") {
+        for script in JSON_LD.captures_iter(html) {
+            if let Ok(value) = serde_json::from_str::<Value>(script[1].trim()) {
+                flatten(value, &mut items);
+            }
+        }
+    }
+    if html.contains("itemscope") {
+        items.extend(microdata(&HTML_COMMENT.replace_all(html, "")));
+    }
+    items
+}
+
+/// Unwraps arrays and `@graph` lists into individual items.
+fn flatten(value: Value, items: &mut Vec<Value>) {
+    match value {
+        Value::Array(values) => values.into_iter().for_each(|value| flatten(value, items)),
+        Value::Object(mut object) => match object.remove("@graph") {
+            Some(graph) => flatten(graph, items),
+            None => items.push(Value::Object(object)),
+        },
+        _ => {}
+    }
+}
+
+/// An element still open while scanning microdata.
+struct OpenElement {
+    tag: String,
+    /// The item this element's `itemscope` started
+    item: Option<usize>,
+    /// An `itemprop` whose value is the element's text: (item, names, where the text starts)
+    text_property: Option<(usize, Vec<String>, usize)>,
+}
+
+/// An item found in microdata, and the item and properties it's the value of.
+struct MicrodataItem {
+    properties: Map<String, Value>,
+    parent: Option<(usize, Vec<String>)>,
+}
+
+fn microdata(html: &str) -> Vec<Value> {
+    let mut found: Vec<MicrodataItem> = Vec::new();
+    let mut open: Vec<OpenElement> = Vec::new();
+
+    for tag in TAG.captures_iter(html) {
+        let whole = tag.get(0).unwrap();
+        let name = tag[2].to_lowercase();
+
+        if &tag[1] == "/" {
+            let Some(position) = open.iter().rposition(|element| element.tag == name) else {
+                continue;
+            };
+            for element in open.drain(position..).rev() {
+                if let Some((item, names, start)) = element.text_property {
+                    let text = html_to_text(&html[start..whole.start()]);
+                    add_property(&mut found[item].properties, &names, Value::String(text));
+                }
+            }
+            continue;
+        }
+
+        let attributes = &tag[3];
+        let attribute = |wanted: &str| {
+            ATTRIBUTE.captures_iter(attributes).find(|a| a[1].eq_ignore_ascii_case(wanted)).map(|a| {
+                let value = a.get(2).or(a.get(3)).or(a.get(4)).map_or("", |value| value.as_str());
+                html_to_text(value)
+            })
+        };
+        let is_void = VOID_ELEMENTS.contains(&name.as_str()) || attributes.trim_end().ends_with('/');
+        let current = open.iter().rev().find_map(|element| element.item);
+        let names: Option<Vec<String>> = attribute("itemprop").map(|names| names.split_whitespace().map(str::to_string).collect());
+
+        let mut element = OpenElement { tag: name.clone(), item: None, text_property: None };
+        if attribute("itemscope").is_some() {
+            let mut properties = Map::new();
+            if let Some(item_type) = attribute("itemtype").and_then(|types| types.split_whitespace().next().map(str::to_string)) {
+                properties.insert("@type".to_string(), Value::String(item_type));
+            }
+            let parent = current.zip(names);
+            found.push(MicrodataItem { properties, parent });
+            element.item = Some(found.len() - 1);
+        } else if let (Some(item), Some(names)) = (current, names) {
+            let value = attribute("content")
+                .or_else(|| matches!(name.as_str(), "a" | "link" | "area").then(|| attribute("href")).flatten())
+                .or_else(|| matches!(name.as_str(), "img" | "audio" | "video" | "source" | "embed").then(|| attribute("src")).flatten())
+                .or_else(|| matches!(name.as_str(), "time").then(|| attribute("datetime")).flatten())
+                .or_else(|| matches!(name.as_str(), "data" | "meter").then(|| attribute("value")).flatten());
+            match value {
+                Some(value) => add_property(&mut found[item].properties, &names, Value::String(value)),
+                None if is_void => {}
+                None => element.text_property = Some((item, names, whole.end())),
+            }
+        }
+        if !is_void {
+            open.push(element);
+        }
+    }
+
+    // Children come after their parents, so attaching from the end builds
+    // each item before it's added to its parent
+    let mut top_level = Vec::new();
+    while let Some(item) = found.pop() {
+        let value = Value::Object(item.properties);
+        match item.parent {
+            Some((parent, names)) => add_property(&mut found[parent].properties, &names, value),
+            None => top_level.insert(0, value),
+        }
+    }
+    top_level
+}
+
+/// Sets each of `names` to `value`, collecting repeated properties into an array.
+fn add_property(properties: &mut Map<String, Value>, names: &[String], value: Value) {
+    for name in names {
+        match properties.get_mut(name) {
+            Some(Value::Array(values)) => values.push(value.clone()),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value.clone()]),
+            None => {
+                properties.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// The item's schema.org type without the vocabulary URL: "Order" for
+/// "http://schema.org/Order".
+fn type_of(item: &Value) -> Option<&str> {
+    let item_type = match item.get("@type")? {
+        Value::Array(types) => types.first()?.as_str()?,
+        item_type => item_type.as_str()?,
+    };
+    Some(item_type.rsplit('/').next().unwrap_or(item_type))
+}
+
+/// A property as text: strings as they are, numbers written out, and for
+/// a nested item (a seller, a carrier) its name.
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) => Some(text.trim().to_string()).filter(|text| !text.is_empty()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Array(values) => values.iter().find_map(|value| text(Some(value))),
+        Value::Object(object) => text(object.get("name")).or_else(|| text(object.get("@id"))),
+        _ => None,
+    }
+}
+
+/// A schema.org enumeration value without the vocabulary URL: "OrderDelivered".
+fn enumeration(value: Option<&Value>) -> Option<String> {
+    text(value).map(|value| value.rsplit('/').next().unwrap_or(&value).to_string())
+}
+
+/// A price: a number, a string like "$1,299.00" or "12,50 €", or a
+/// PriceSpecification or MonetaryAmount holding one.
+fn price(value: Option<&Value>) -> Option<f64> {
+    match value? {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => parse_amount(text),
+        Value::Array(values) => values.iter().find_map(|value| price(Some(value))),
+        Value::Object(object) => price(object.get("price")).or_else(|| price(object.get("value"))),
+        _ => None,
+    }
+}
+
+/// Reads an amount written with either "." or "," as the decimal point.
+pub fn parse_amount(text: &str) -> Option<f64> {
+    let digits: String = text.chars().filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-')).collect();
+    let digits = digits.trim_matches(|c| matches!(c, '.' | ',' | '-'));
+    let negative = text.trim_start().starts_with('-');
+    // The last separator is the decimal point, unless it's followed by exactly
+    // three digits and is the only kind of separator, which groups thousands
+    // ("1,299", "1,299,000")
+    let normalized = match digits.rfind(['.', ',']) {
+        Some(last) => {
+            let decimals = digits.len() - last - 1;
+            let separator = &digits[last..last + 1];
+            let one_kind = !digits.contains(if separator == "." { ',' } else { '.' });
+            if one_kind && decimals == 3 {
+                digits.replace(['.', ','], "")
+            } else {
+                let (whole, fraction) = digits.split_at(last);
+                format!("{}.{}", whole.replace(['.', ','], ""), &fraction[1..])
+            }
+        }
+        None => digits.to_string(),
+    };
+    let amount: f64 = normalized.parse().ok()?;
+    Some(if negative { -amount } else { amount })
+}
+
+/// A purchase, from an Order item or the order a ParcelDelivery is part of.
+#[derive(Debug, Default)]
+pub struct Order {
+    pub merchant: Option<String>,
+    pub order_number: Option<String>,
+    pub total: Option<f64>,
+    pub currency: Option<String>,
+    pub order_date: Option<String>,
+    /// e.g. "OrderProcessing", "OrderInTransit", "OrderDelivered"
+    pub status: Option<String>,
+    pub tracking_number: Option<String>,
+    pub carrier: Option<String>,
+}
+
+/// The orders described by a message's schema.org items. An order marked up
+/// twice (JSON-LD and microdata, or an Order and a ParcelDelivery) is one order.
+pub fn orders(items: &[Value]) -> Vec<Order> {
+    let mut orders: Vec<Order> = Vec::new();
+    for item in items {
+        let order = match type_of(item) {
+            Some("Order") => order(item),
+            Some("ParcelDelivery") => {
+                let mut order = item.get("partOfOrder").map(order).unwrap_or_default();
+                order.tracking_number = text(item.get("trackingNumber"));
+                order.carrier = text(item.get("carrier")).or_else(|| text(item.get("provider")));
+                order.status = order.status.or_else(|| enumeration(item.get("deliveryStatus")));
+                order
+            }
+            _ => continue,
+        };
+        if order.order_number.is_none() && order.total.is_none() && order.tracking_number.is_none() {
+            continue;
+        }
+        match orders.iter_mut().find(|seen| seen.order_number.is_some() && seen.order_number == order.order_number) {
+            Some(seen) => merge(seen, order),
+            None => orders.push(order),
+        }
+    }
+    orders
+}
+
+fn order(item: &Value) -> Order {
+    let payment_due = item.get("totalPaymentDue");
+    Order {
+        merchant: text(item.get("seller")).or_else(|| text(item.get("merchant"))).or_else(|| text(item.get("broker"))),
+        order_number: text(item.get("orderNumber")),
+        total: price(item.get("price")).or_else(|| price(payment_due)),
+        currency: text(item.get("priceCurrency"))
+            .or_else(|| text(payment_due.and_then(|due| due.get("priceCurrency"))))
+            .or_else(|| text(payment_due.and_then(|due| due.get("currency")))),
+        order_date: text(item.get("orderDate")),
+        status: enumeration(item.get("orderStatus")),
+        tracking_number: None,
+        carrier: None,
+    }
+}
+
+/// Fills in what `seen` is missing from another markup of the same order.
+fn merge(seen: &mut Order, other: Order) {
+    seen.merchant = seen.merchant.take().or(other.merchant);
+    seen.total = seen.total.or(other.total);
+    seen.currency = seen.currency.take().or(other.currency);
+    seen.order_date = seen.order_date.take().or(other.order_date);
+    seen.status = seen.status.take().or(other.status);
+    seen.tracking_number = seen.tracking_number.take().or(other.tracking_number);
+    seen.carrier = seen.carrier.take().or(other.carrier);
+}
+
+/// A booking: a flight, hotel stay, rental car, train or bus trip, event
+/// ticket or table.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reservation {
+    /// "flight", "lodging", "car", "train", "bus", "event", "restaurant" or "other"
+    pub kind: &'static str,
+    pub reservation_number: Option<String>,
+    pub status: Option<String>,
+    /// The airline, rental company or train operator, or whoever took the booking
+    pub provider: Option<String>,
+    /// The hotel, event, restaurant or car
+    pub name: Option<String>,
+    pub passenger: Option<String>,
+    /// e.g. "UA123"
+    pub flight_number: Option<String>,
+    /// Where the trip starts and ends: airport codes, stations, pick-up and drop-off
+    pub from_place: Option<String>,
+    pub to_place: Option<String>,
+    /// Departure, check-in or pick-up, and arrival, check-out or drop-off
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    /// The hotel's, venue's or restaurant's address
+    pub location: Option<String>,
+    /// "markup" for schema.org items, "text" for confirmations read from the body
+    pub source: &'static str,
+}
+
+/// The reservations described by a message's schema.org items.
+pub fn reservations(items: &[Value]) -> Vec<Reservation> {
+    let mut reservations: Vec<Reservation> = Vec::new();
+    for item in items {
+        let Some(item_type) = type_of(item).filter(|item_type| item_type.ends_with("Reservation")) else {
+            continue;
+        };
+        let reservation = reservation(item, item_type);
+        if !reservations.contains(&reservation) {
+            reservations.push(reservation);
+        }
+    }
+    reservations
+}
+
+fn reservation(item: &Value, item_type: &str) -> Reservation {
+    let booked = item.get("reservationFor").unwrap_or(&Value::Null);
+    let mut reservation = Reservation {
+        kind: "other",
+        reservation_number: text(item.get("reservationNumber")).or_else(|| text(item.get("reservationId"))),
+        status: enumeration(item.get("reservationStatus")),
+        provider: text(item.get("provider")).or_else(|| text(item.get("broker"))),
+        passenger: text(item.get("underName")),
+        source: "markup",
+        ..Reservation::default()
+    };
+
+    match item_type {
+        "FlightReservation" => {
+            reservation.kind = "flight";
+            let airline = booked.get("airline");
+            reservation.provider = text(airline).or(reservation.provider);
+            let number = text(booked.get("flightNumber"));
+            let prefix = airline.and_then(|airline| text(airline.get("iataCode")));
+            reservation.flight_number = match (prefix, number) {
+                (Some(prefix), Some(number)) if number.chars().all(|c| c.is_ascii_digit()) => Some(format!("{prefix}{number}")),
+                (_, number) => number.map(|number| number.replace(' ', "")),
+            };
+            reservation.from_place = place(booked.get("departureAirport"));
+            reservation.to_place = place(booked.get("arrivalAirport"));
+            reservation.start_time = text(booked.get("departureTime"));
+            reservation.end_time = text(booked.get("arrivalTime"));
+        }
+        "LodgingReservation" => {
+            reservation.kind = "lodging";
+            reservation.name = text(booked.get("name"));
+            reservation.location = address(booked.get("address"));
+            reservation.start_time = text(item.get("checkinTime")).or_else(|| text(item.get("checkinDate")));
+            reservation.end_time = text(item.get("checkoutTime")).or_else(|| text(item.get("checkoutDate")));
+        }
+        "RentalCarReservation" => {
+            reservation.kind = "car";
+            reservation.provider = text(booked.get("rentalCompany")).or(reservation.provider);
+            reservation.name = text(booked.get("name")).or_else(|| text(booked.get("model")));
+            reservation.from_place = place(item.get("pickupLocation"));
+            reservation.to_place = place(item.get("dropoffLocation"));
+            reservation.start_time = text(item.get("pickupTime"));
+            reservation.end_time = text(item.get("dropoffTime"));
+        }
+        "TrainReservation" | "BusReservation" => {
+            let (kind, stop) = if item_type == "TrainReservation" { ("train", "Station") } else { ("bus", "BusStop") };
+            reservation.kind = kind;
+            reservation.provider = text(booked.get("provider")).or(reservation.provider);
+            reservation.name = text(booked.get("trainNumber"))
+                .or_else(|| text(booked.get("busNumber")))
+                .or_else(|| text(booked.get("name")));
+            reservation.from_place = place(booked.get(format!("departure{stop}")));
+            reservation.to_place = place(booked.get(format!("arrival{stop}")));
+            reservation.start_time = text(booked.get("departureTime"));
+            reservation.end_time = text(booked.get("arrivalTime"));
+        }
+        "EventReservation" | "FoodEstablishmentReservation" => {
+            reservation.kind = if item_type == "EventReservation" { "event" } else { "restaurant" };
+            reservation.name = text(booked.get("name"));
+            reservation.location = place(booked.get("location")).or_else(|| address(booked.get("address")));
+            reservation.start_time = text(booked.get("startDate")).or_else(|| text(item.get("startTime")));
+            reservation.end_time = text(booked.get("endDate")).or_else(|| text(item.get("endTime")));
+        }
+        _ => reservation.name = text(booked.get("name")),
+    }
+    reservation
+}
+
+/// An airport or station as its IATA code when it has one, else its name
+/// or address.
+fn place(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Object(object) => text(object.get("iataCode"))
+            .or_else(|| text(object.get("name")))
+            .or_else(|| address(object.get("address"))),
+        value => text(Some(value)),
+    }
+}
+
+/// A PostalAddress on one line.
+fn address(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Object(object) => {
+            let parts: Vec<String> = ["streetAddress", "addressLocality", "addressRegion", "postalCode", "addressCountry"]
+                .iter()
+                .filter_map(|part| text(object.get(*part)))
+                .collect();
+            Some(parts.join(", ")).filter(|address| !address.is_empty())
+        }
+        value => text(Some(value)),
+    }
+}
+
+static CONFIRMATION_CODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i:confirmation\s+(?:code|number|#)|booking\s+(?:reference|code|number)|record\s+locator|reservation\s+code|\bPNR)(?:\s+is)?\s*[:#]?\s*\b([A-Z0-9]{6})\b").unwrap()
+});
+static FLIGHT_NUMBER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i:\bflight)(?i:\s+(?:number|no\.?|#))?\s*:?\s*\b([A-Z][A-Z0-9]|[0-9][A-Z])\s?(\d{1,4})\b").unwrap());
+/// "SFO → JFK", "SFO-JFK", "San Francisco (SFO) to New York (JFK)"
+static AIRPORT_PAIR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b([A-Z]{3})\b\)?\s*(?:→|->|–|—|-|to)\s*(?:[A-Za-z .'-]{0,40}\()?\b([A-Z]{3})\b").unwrap());
+
+/// How many lines after a flight number its airports may appear on.
+const FLIGHT_DETAIL_LINES: usize = 3;
+
+/// Flights from an airline confirmation without markup: the confirmation
+/// code ("Confirmation number: ABC123", "Booking reference", "Record
+/// locator") and each "Flight UA 123" with the airport pair near it.
+/// `airline` is the sender's name.
+pub fn flight_confirmations(body: &str, airline: Option<&str>) -> Vec<Reservation> {
+    let Some(code) = CONFIRMATION_CODE.captures(body).map(|code| code[1].to_string()) else {
+        return Vec::new();
+    };
+    // A six-character code is all digits far more often in order numbers than in PNRs
+    if code.chars().all(|c| c.is_ascii_digit()) {
+        return Vec::new();
+    }
+
+    let lines: Vec<&str> = body.lines().collect();
+    let mut flights: Vec<Reservation> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        for flight in FLIGHT_NUMBER.captures_iter(line) {
+            let flight_number = format!("{}{}", flight[1].to_uppercase(), &flight[2]);
+            let airports = lines[index..lines.len().min(index + 1 + FLIGHT_DETAIL_LINES)]
+                .iter()
+                .find_map(|line| AIRPORT_PAIR.captures(line));
+            let reservation = Reservation {
+                kind: "flight",
+                reservation_number: Some(code.clone()),
+                provider: airline.map(str::to_string),
+                flight_number: Some(flight_number),
+                from_place: airports.as_ref().map(|airports| airports[1].to_string()),
+                to_place: airports.as_ref().map(|airports| airports[2].to_string()),
+                source: "text",
+                ..Reservation::default()
+            };
+            if !flights.contains(&reservation) {
+                flights.push(reservation);
+            }
+        }
+    }
+    flights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn amounts() {
+        let cases = [
+            ("$1,299.00", Some(1299.0)),
+            ("1.299,00 €", Some(1299.0)),
+            ("12,50 €", Some(12.5)),
+            ("1,299", Some(1299.0)),
+            ("1.299", Some(1299.0)),
+            ("0.995", Some(995.0)),
+            ("1,299,000", Some(1299000.0)),
+            ("1.299.000,50", Some(1299000.5)),
+            ("-4.20", Some(-4.2)),
+            ("42", Some(42.0)),
+            ("free", None),
+        ];
+        for (text, amount) in cases {
+            assert_eq!(parse_amount(text), amount, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn json_ld_items_and_graphs() {
+        let html = r#"<html><script type="application/ld+json">
+            {"@context": "http://schema.org", "@graph": [{"@type": "Order", "orderNumber": "1"}, {"@type": "Person"}]}
+        </script><script type='application/ld+json'>[{"@type": "Order", "orderNumber": "2"}]</script>
+        <script type="application/ld+json">{not json</script></html>"#;
+        let items = items(html);
+        let order_numbers: Vec<Option<&str>> = items.iter().map(|item| item["orderNumber"].as_str()).collect();
+        assert_eq!(order_numbers, [Some("1"), None, Some("2")]);
+    }
+
+    #[test]
+    fn microdata_items() {
+        let html = r#"<div itemscope itemtype="http://schema.org/Order">
+            <!-- <span itemprop="orderNumber">commented out</span> -->
+            <span itemprop="orderNumber">A-1001</span>
+            <div itemprop="seller" itemscope itemtype="http://schema.org/Organization"><b itemprop="name">Acme &amp; Co</b></div>
+            <meta itemprop="priceCurrency" content="USD"><span itemprop="price">$19.99</span>
+            <link itemprop="orderStatus" href="http://schema.org/OrderDelivered">
+            <time itemprop="orderDate" datetime="2024-01-05">Jan 5</time>
+        </div>"#;
+        let items = items(html);
+        assert_eq!(
+            items,
+            [json!({
+                "@type": "http://schema.org/Order",
+                "orderNumber": "A-1001",
+                "seller": {"@type": "http://schema.org/Organization", "name": "Acme & Co"},
+                "priceCurrency": "USD",
+                "price": "$19.99",
+                "orderStatus": "http://schema.org/OrderDelivered",
+                "orderDate": "2024-01-05",
+            })]
+        );
+        let orders = orders(&items);
+        assert_eq!(orders.len(), 1);
+        let order = &orders[0];
+        assert_eq!(order.merchant.as_deref(), Some("Acme & Co"));
+        assert_eq!((order.total, order.currency.as_deref()), (Some(19.99), Some("USD")));
+        assert_eq!((order.status.as_deref(), order.order_date.as_deref()), (Some("OrderDelivered"), Some("2024-01-05")));
+    }
+
+    #[test]
+    fn an_order_marked_up_twice_is_one_order() {
+        let items = [
+            json!({"@type": "Order", "orderNumber": "A-1", "seller": {"name": "Acme"}}),
+            json!({"@type": "ParcelDelivery", "trackingNumber": "1Z999", "carrier": {"name": "UPS"},
+                   "partOfOrder": {"@type": "Order", "orderNumber": "A-1", "totalPaymentDue": {"price": "25.00", "priceCurrency": "EUR"}}}),
+            // Nothing to go on
+            json!({"@type": "Order", "seller": {"name": "Acme"}}),
+        ];
+        let orders = orders(&items);
+        assert_eq!(orders.len(), 1);
+        let order = &orders[0];
+        assert_eq!((order.merchant.as_deref(), order.order_number.as_deref()), (Some("Acme"), Some("A-1")));
+        assert_eq!((order.total, order.currency.as_deref()), (Some(25.0), Some("EUR")));
+        assert_eq!((order.tracking_number.as_deref(), order.carrier.as_deref()), (Some("1Z999"), Some("UPS")));
+    }
+
+    #[test]
+    fn flight_and_hotel_reservations() {
+        let items = [
+            json!({
+                "@type": "FlightReservation",
+                "reservationNumber": "ABC123",
+                "reservationStatus": "http://schema.org/ReservationConfirmed",
+                "underName": {"@type": "Person", "name": "Alice Smith"},
+                "reservationFor": {
+                    "@type": "Flight",
+                    "flightNumber": "123",
+                    "airline": {"@type": "Airline", "name": "United", "iataCode": "UA"},
+                    "departureAirport": {"@type": "Airport", "name": "San Francisco", "iataCode": "SFO"},
+                    "arrivalAirport": {"@type": "Airport", "name": "John F. Kennedy"},
+                    "departureTime": "2024-03-01T08:00:00-08:00",
+                },
+            }),
+            json!({
+                "@type": "LodgingReservation",
+                "reservationId": 98765,
+                "checkinDate": "2024-03-01",
+                "checkoutDate": "2024-03-03",
+                "reservationFor": {"@type": "LodgingBusiness", "name": "Hotel Example",
+                    "address": {"streetAddress": "1 Main St", "addressLocality": "New York", "addressRegion": "NY"}},
+            }),
+        ];
+        let reservations = reservations(&items);
+        assert_eq!(
+            reservations,
+            [
+                Reservation {
+                    kind: "flight",
+                    reservation_number: Some("ABC123".to_string()),
+                    status: Some("ReservationConfirmed".to_string()),
+                    provider: Some("United".to_string()),
+                    passenger: Some("Alice Smith".to_string()),
+                    flight_number: Some("UA123".to_string()),
+                    from_place: Some("SFO".to_string()),
+                    to_place: Some("John F. Kennedy".to_string()),
+                    start_time: Some("2024-03-01T08:00:00-08:00".to_string()),
+                    source: "markup",
+                    ..Reservation::default()
+                },
+                Reservation {
+                    kind: "lodging",
+                    reservation_number: Some("98765".to_string()),
+                    name: Some("Hotel Example".to_string()),
+                    start_time: Some("2024-03-01".to_string()),
+                    end_time: Some("2024-03-03".to_string()),
+                    location: Some("1 Main St, New York, NY".to_string()),
+                    source: "markup",
+                    ..Reservation::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn flight_confirmations_from_text() {
+        let body = "Your trip is booked.\nConfirmation number: XK4P2Q\n\nFlight UA 123\nSan Francisco (SFO) to New York (JFK)\n\n\
+                    Flight UA 456\nJFK → SFO\n";
+        let flights: Vec<(Option<String>, Option<String>, Option<String>)> = flight_confirmations(body, Some("United"))
+            .into_iter()
+            .map(|flight| (flight.flight_number, flight.from_place, flight.to_place))
+            .collect();
+        let leg = |number: &str, from: &str, to: &str| (Some(number.to_string()), Some(from.to_string()), Some(to.to_string()));
+        assert_eq!(flights, [leg("UA123", "SFO", "JFK"), leg("UA456", "JFK", "SFO")]);
+
+        // An all-digit "confirmation number" is an order number
+        assert!(flight_confirmations("Confirmation number: 123456\nFlight UA 123", None).is_empty());
+        assert!(flight_confirmations("Flight UA 123 from SFO to JFK", None).is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::markup::parse_amount;
+
+static RECEIPT_WORDS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(receipt|invoice|order|payment|paid|purchase|bill|billing|statement|amount due|total|refund|subscription|rechnung|quittung|facture|reçu|factura|recibo)\b").unwrap()
+});
+/// "$12.50", "US$ 1,299", "EUR 40", "12,50 €", "40.00 USD"
+static AMOUNT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?x)
+        (?P<before>US\$|CA?\$|AU?\$|NZ\$|HK\$|R\$|[$€£¥₹]|\b(?:USD|EUR|GBP|CAD|AUD|NZD|HKD|CHF|JPY|INR|SEK|NOK|DKK|PLN|BRL|MXN)\b)
+            \s?(?P<number_before>-?\d[\d.,]*\d|\d)
+        |
+        (?P<number_after>-?\d[\d.,]*\d|\d)
+            \s?(?P<after>[€£]|(?:USD|EUR|GBP|CAD|AUD|NZD|HKD|CHF|JPY|INR|SEK|NOK|DKK|PLN|BRL|MXN|kr|zł)\b)",
+    )
+    .unwrap()
+});
+static INVOICE_NUMBER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:invoice|receipt|bill|rechnung|facture|factura)(?:\s+(?:number|no\.?|nr\.?|num\.?|id))?\s*[:#]?\s*#?\s*([a-z0-9][a-z0-9/-]{2,})").unwrap()
+});
+static DUE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(?:due\s+(?:date|on|by)|payment\s+due|due|pay\s+by|f[äa]llig(?:\s+am)?|[ée]ch[ée]ance|vencimiento)\s*:?\s*").unwrap()
+});
+/// A date right where a due date's text starts: "2024-02-15", "15.02.2024",
+/// "2/15/24", "February 15th, 2024", "15 Feb 2024"
+static DATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d{4}-\d{2}-\d{2}|\d{1,2}[./]\d{1,2}[./]\d{2,4}|[A-Za-z]{3,9}\.?\s+\d{1,2}(?:st|nd|rd|th)?,?\s+\d{4}|\d{1,2}(?:st|nd|rd|th)?\.?\s+[A-Za-z]{3,9}\.?,?\s+\d{4})").unwrap()
+});
+static ORDINAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d)(?:st|nd|rd|th)\b").unwrap());
+/// Labels that name the amount actually charged; "Subtotal" isn't one
+static TOTAL_LABEL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(grand total|\btotal\b|amount due|balance due|amount paid|amount charged|you paid|gesamt|summe|montant|importe)").unwrap()
+});
+
+/// Words of the line before an amount kept as its label.
+const LABEL_WORDS: usize = 4;
+
+/// An amount of money and the words before it on its line ("Total", "Tax").
+#[derive(Debug)]
+pub struct Amount {
+    pub amount: f64,
+    /// ISO 4217 code; "$" is read as USD
+    pub currency: Option<String>,
+    pub label: Option<String>,
+}
+
+/// What a receipt or invoice says it's for.
+#[derive(Debug, Default)]
+pub struct Receipt {
+    pub invoice_number: Option<String>,
+    /// The last amount labelled as a total or amount due, else the largest
+    pub total: Option<f64>,
+    pub currency: Option<String>,
+    /// ISO 8601 date
+    pub due_date: Option<String>,
+    pub amounts: Vec<Amount>,
+}
+
+/// Reads a message that looks like a receipt or invoice: one mentioning
+/// money and a word like "receipt", "invoice", "total" or "payment".
+pub fn receipt(subject: &str, body: &str) -> Option<Receipt> {
+    if !RECEIPT_WORDS.is_match(subject) && !RECEIPT_WORDS.is_match(body) {
+        return None;
+    }
+    let amounts = amounts(body);
+    if amounts.is_empty() {
+        return None;
+    }
+
+    let total = amounts
+        .iter()
+        .rev()
+        .find(|amount| amount.label.as_deref().is_some_and(|label| TOTAL_LABEL.is_match(label) && !label.to_lowercase().contains("subtotal")))
+        .or_else(|| amounts.iter().max_by(|a, b| a.amount.total_cmp(&b.amount)));
+    let invoice_number = [subject, body].into_iter().find_map(|text| {
+        INVOICE_NUMBER
+            .captures_iter(text)
+            .map(|number| number[1].trim_end_matches(['-', '/']).to_string())
+            .find(|number| number.chars().any(|c| c.is_ascii_digit()))
+    });
+    let due_date = DUE.find_iter(body).find_map(|due| parse_date(&body[due.end()..]));
+
+    Some(Receipt {
+        invoice_number,
+        total: total.map(|total| total.amount),
+        currency: total.and_then(|total| total.currency.clone()),
+        due_date,
+        amounts,
+    })
+}
+
+fn amounts(body: &str) -> Vec<Amount> {
+    let mut amounts = Vec::new();
+    for line in body.lines() {
+        for found in AMOUNT.captures_iter(line) {
+            let (symbol, number) = match (found.name("before"), found.name("after")) {
+                (Some(symbol), _) => (symbol, &found["number_before"]),
+                (None, Some(symbol)) => (symbol, &found["number_after"]),
+                (None, None) => continue,
+            };
+            let Some(amount) = parse_amount(number) else {
+                continue;
+            };
+            let before = &line[..found.get(0).unwrap().start()];
+            let words: Vec<&str> = before.split_whitespace().collect();
+            let label = words[words.len().saturating_sub(LABEL_WORDS)..]
+                .join(" ")
+                .trim_end_matches(|c: char| c.is_whitespace() || matches!(c, ':' | '-' | '.' | '=' | '|' | '*'))
+                .to_string();
+            amounts.push(Amount {
+                amount,
+                currency: currency_code(symbol.as_str()),
+                label: Some(label).filter(|label| !label.is_empty()),
+            });
+        }
+    }
+    amounts
+}
+
+fn currency_code(symbol: &str) -> Option<String> {
+    let code = match symbol {
+        "$" | "US$" => "USD",
+        "C$" | "CA$" => "CAD",
+        "A$" | "AU$" => "AUD",
+        "NZ$" => "NZD",
+        "HK$" => "HKD",
+        "R$" => "BRL",
+        "€" => "EUR",
+        "£" => "GBP",
+        "¥" => "JPY",
+        "₹" => "INR",
+        "zł" => "PLN",
+        // Swedish, Norwegian or Danish
+        "kr" => return None,
+        code => code,
+    };
+    Some(code.to_string())
+}
+
+/// The date `text` starts with, as YYYY-MM-DD. Slashed dates are read
+/// month first unless that can't be right; dotted ones are day first.
+fn parse_date(text: &str) -> Option<String> {
+    let found = DATE.find(text.trim_start())?.as_str();
+    let cleaned = ORDINAL.replace_all(found, "$1").replace([',', '.'], " ");
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let date = if found.contains(['/', '.']) && !found.contains(char::is_whitespace) {
+        let parts: Vec<u32> = found.split(['/', '.']).filter_map(|part| part.parse().ok()).collect();
+        let [first, second, year] = parts[..] else {
+            return None;
+        };
+        let year = if year < 100 { 2000 + year } else { year } as i32;
+        let (month, day) = if found.contains('.') || first > 12 { (second, first) } else { (first, second) };
+        NaiveDate::from_ymd_opt(year, month, day)?
+    } else {
+        ["%Y-%m-%d", "%B %d %Y", "%b %d %Y", "%d %B %Y", "%d %b %Y"]
+            .iter()
+            .find_map(|format| NaiveDate::parse_from_str(&cleaned, format).ok())?
+    };
+    Some(date.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_and_currencies() {
+        let cases = [
+            ("Total: $12.50", 12.5, Some("USD"), Some("Total")),
+            ("US$ 1,299", 1299.0, Some("USD"), None),
+            ("Betrag 12,50 €", 12.5, Some("EUR"), Some("Betrag")),
+            ("Amount paid 40.00 USD", 40.0, Some("USD"), Some("Amount paid")),
+            ("Grand total = £1.234,56", 1234.56, Some("GBP"), Some("Grand total")),
+            ("Refund - $5", 5.0, Some("USD"), Some("Refund")),
+            ("Summa 199 kr", 199.0, None, Some("Summa")),
+            ("Razem 10 zł", 10.0, Some("PLN"), Some("Razem")),
+            ("Your card was charged C$ 20", 20.0, Some("CAD"), Some("Your card was charged")),
+        ];
+        for (line, amount, currency, label) in cases {
+            let found = amounts(line);
+            assert_eq!(found.len(), 1, "{:?}", line);
+            assert_eq!((found[0].amount, found[0].currency.as_deref(), found[0].label.as_deref()), (amount, currency, label), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn receipts() {
+        let body = "Thanks for your order!\n\nInvoice #INV-2024-0042\nSubtotal: $40.00\nTax: $3.20\nTotal: $43.20\n\nPayment due: March 1st, 2024";
+        let receipt = receipt("Your receipt", body).unwrap();
+        assert_eq!(receipt.invoice_number.as_deref(), Some("INV-2024-0042"));
+        assert_eq!((receipt.total, receipt.currency.as_deref()), (Some(43.2), Some("USD")));
+        assert_eq!(receipt.due_date.as_deref(), Some("2024-03-01"));
+        assert_eq!(receipt.amounts.len(), 3);
+    }
+
+    #[test]
+    fn largest_amount_without_a_total() {
+        let receipt = receipt("Payment received", "We received €15 and €120 for the subscription.").unwrap();
+        assert_eq!((receipt.total, receipt.currency.as_deref()), (Some(120.0), Some("EUR")));
+    }
+
+    #[test]
+    fn not_receipts() {
+        assert!(receipt("Lunch?", "Are you free at noon? It's $12 a plate.").is_none());
+        assert!(receipt("Your invoice", "Your invoice is attached.").is_none());
+    }
+
+    #[test]
+    fn due_dates() {
+        let cases = [
+            ("2024-02-15", Some("2024-02-15")),
+            ("15.02.2024", Some("2024-02-15")),
+            ("2/15/24", Some("2024-02-15")),
+            // Month first, unless the first number can't be a month
+            ("3/4/2024", Some("2024-03-04")),
+            ("15/2/2024", Some("2024-02-15")),
+            ("February 15th, 2024", Some("2024-02-15")),
+            ("Feb. 15, 2024", Some("2024-02-15")),
+            ("15 Feb 2024", Some("2024-02-15")),
+            ("  1st March 2024 at the latest", Some("2024-03-01")),
+            ("31/31/2024", None),
+            ("upon receipt", None),
+        ];
+        for (text, date) in cases {
+            assert_eq!(parse_date(text).as_deref(), date, "{:?}", text);
+        }
+    }
+}
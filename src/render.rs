@@ -0,0 +1,232 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use rusqlite::{Connection, OptionalExtension};
+use std::borrow::Cow;
+
+use crate::report;
+use crate::schema::{self, Tables};
+use crate::text;
+
+static SANITIZER: Lazy<ammonia::Builder<'static>> = Lazy::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        // Keep inline images displayable
+        .add_url_schemes(["cid", "data"])
+        // Remote images are how trackers phone home; drop their source entirely
+        .attribute_filter(|element, attribute, value| {
+            let lower = value.trim_start().to_lowercase();
+            let is_remote = lower.starts_with("http:") || lower.starts_with("https:") || lower.starts_with("//");
+            if element == "img" && attribute == "src" && is_remote {
+                None
+            } else {
+                Some(Cow::Borrowed(value))
+            }
+        });
+    builder
+});
+
+/// Strips scripts, event handlers, and remote (tracking) images from an HTML body.
+pub fn sanitize_html(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Rewrites `cid:` references to the given inline image as a data: URI.
+fn resolve_cid(html: &str, content_id: &str, data_uri: &str) -> String {
+    ["cid:", "CID:", "Cid:"]
+        .iter()
+        .fold(html.to_string(), |html, prefix| {
+            html.replace(&format!("{}{}", prefix, content_id), data_uri)
+        })
+}
+
+/// Renders a stored email as a standalone HTML document: a small header block
+/// followed by the HTML body (or the plain body when there's no HTML part),
+/// with `cid:` references replaced by the matching inline images so the
+/// message displays correctly outside a mail client. With `sanitize`, the HTML
+/// body goes through [`sanitize_html`] first.
+pub fn render_message(conn: &Connection, tables: &Tables, email_id: i64, sanitize: bool) -> Result<String> {
+    let (subject, from, to, date, body_plain, body_html): (String, String, String, String, String, String) = conn
+        .query_row(
+            &format!(
+                "SELECT COALESCE(subject, ''), COALESCE(from_addr, ''), COALESCE(to_addr, ''), COALESCE(date, ''),
+                        COALESCE(body_plain, ''), COALESCE(body_html, '')
+                 FROM {} WHERE id = ?1",
+                tables.emails
+            ),
+            [email_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)),
+        )
+        .optional()?
+        .with_context(|| format!("No email with id {}", email_id))?;
+
+    let body = if body_html.trim().is_empty() {
+        format!("<pre style=\"white-space: pre-wrap\">{}</pre>", escape_html(&body_plain))
+    } else if sanitize {
+        sanitize_html(&body_html)
+    } else {
+        body_html
+    };
+    let body = embed_inline_images(conn, tables, email_id, body)?;
+
+    Ok(format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{subject}</title>
+</head>
+<body>
+<div style=\"font-family: sans-serif; border-bottom: 1px solid #ccc; margin-bottom: 1em; padding-bottom: 0.5em\">
+<div><b>From:</b> {from}</div>
+<div><b>To:</b> {to}</div>
+<div><b>Date:</b> {date}</div>
+<div><b>Subject:</b> {subject}</div>
+</div>
+{body}
+</body>
+</html>
+",
+        subject = escape_html(&subject),
+        from = escape_html(&from),
+        to = escape_html(&to),
+        date = escape_html(&date),
+        body = body,
+    ))
+}
+
+/// Replaces `cid:` references in `body` with the email's stored inline images.
+fn embed_inline_images(conn: &Connection, tables: &Tables, email_id: i64, mut body: String) -> Result<String> {
+    // Databases imported with --schema flat don't store inline images
+    if !schema::table_exists(conn, &tables.inline_images)? {
+        return Ok(body);
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT content_id, content_type, data FROM {} WHERE email_id = ?1",
+        tables.inline_images
+    ))?;
+    let images = stmt.query_map([email_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Vec<u8>>(2)?))
+    })?;
+    for image in images {
+        let (content_id, content_type, data) = image?;
+        let data_uri = format!(
+            "data:{};base64,{}",
+            content_type,
+            base64::engine::general_purpose::STANDARD.encode(&data)
+        );
+        body = resolve_cid(&body, &content_id, &data_uri);
+    }
+
+    Ok(body)
+}
+
+/// Formats a stored email for the terminal: aligned headers, the clean body
+/// (quoted replies and signature removed) wrapped at `width` columns, and the
+/// attachments with their sizes.
+pub fn show_message(conn: &Connection, tables: &Tables, email_id: i64, width: usize) -> Result<String> {
+    let (subject, from, to, cc, date, body_clean, body_plain, body_html): (String, String, String, String, String, String, String, String) = conn
+        .query_row(
+            &format!(
+                "SELECT COALESCE(subject, ''), COALESCE(from_addr, ''), COALESCE(to_addr, ''), COALESCE(cc, ''),
+                        COALESCE(date, ''), COALESCE(body_clean, ''), COALESCE(body_plain, ''), COALESCE(body_html, '')
+                 FROM {} WHERE id = ?1",
+                tables.emails
+            ),
+            [email_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?)),
+        )
+        .optional()?
+        .with_context(|| format!("No email with id {}", email_id))?;
+
+    let labels: Vec<String> = if schema::table_exists(conn, &tables.labels)? {
+        conn.prepare(&format!("SELECT label FROM {} WHERE email_id = ?1 ORDER BY id", tables.labels))?
+            .query_map([email_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    let mut shown = String::new();
+    for (name, value) in [("From", &from), ("To", &to), ("Cc", &cc), ("Date", &date), ("Subject", &subject), ("Labels", &labels.join(", "))] {
+        if !value.is_empty() {
+            shown.push_str(&format!("{:<8} {}\n", format!("{}:", name), value));
+        }
+    }
+    shown.push('\n');
+
+    let body = [body_clean, body_plain].into_iter().find(|body| !body.trim().is_empty()).unwrap_or_else(|| text::html_to_text(&body_html));
+    for line in body.trim_end().lines() {
+        wrap_line(&mut shown, line.trim_end(), width);
+    }
+
+    // Databases imported with --schema flat don't list attachments
+    if schema::table_exists(conn, &tables.attachments)? {
+        let attachments: Vec<(String, String, i64)> = conn
+            .prepare(&format!(
+                "SELECT COALESCE(filename, '(unnamed)'), COALESCE(content_type, ''), size FROM {} WHERE email_id = ?1 ORDER BY id",
+                tables.attachments
+            ))?
+            .query_map([email_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        if !attachments.is_empty() {
+            shown.push_str(&format!("\nAttachments ({}):\n", attachments.len()));
+            for (filename, content_type, size) in attachments {
+                shown.push_str(&format!("  {}  {}  {}\n", filename, content_type, report::format_bytes(size)));
+            }
+        }
+    }
+    Ok(shown)
+}
+
+/// Appends `line` to `out` broken at spaces so no piece is wider than `width`
+/// characters; words longer than that (URLs) are left whole.
+fn wrap_line(out: &mut String, line: &str, width: usize) {
+    if line.chars().count() <= width {
+        out.push_str(line);
+        out.push('\n');
+        return;
+    }
+    // Continuation lines keep the indentation and quote markers of the first
+    let prefix_len = line.len() - line.trim_start_matches(|c: char| c == '>' || c.is_whitespace()).len();
+    let (prefix, rest) = line.split_at(prefix_len);
+    let prefix_width = prefix.chars().count();
+    let mut current = prefix.to_string();
+    let mut current_width = prefix_width;
+    for word in rest.split(' ').filter(|word| !word.is_empty()) {
+        let word_width = word.chars().count();
+        if current_width > prefix_width && current_width + 1 + word_width > width {
+            out.push_str(&current);
+            out.push('\n');
+            current = prefix.to_string();
+            current_width = prefix_width;
+        }
+        if current_width > prefix_width {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(word);
+        current_width += word_width;
+    }
+    out.push_str(&current);
+    out.push('\n');
+}
+
+/// The message exactly as it was imported. Needs a database imported with
+/// `--schema full`, the only profile that keeps it.
+pub fn raw_message(conn: &Connection, tables: &Tables, email_id: i64) -> Result<Vec<u8>> {
+    if !schema::table_exists(conn, &tables.raw_messages)? {
+        bail!("The database has no raw messages; import it with --schema full to use --raw");
+    }
+    conn.query_row(&format!("SELECT raw FROM {} WHERE email_id = ?1", tables.raw_messages), [email_id], |row| row.get(0))
+        .optional()?
+        .with_context(|| format!("No raw message stored for email id {}", email_id))
+}
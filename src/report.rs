@@ -0,0 +1,596 @@
+//! Read-only analytics over an imported database, behind the report-style
+//! subcommands (`network`, `latency`, `activity`, `attachments`, `contacts`,
+//! `orphans`).
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::render::escape_html;
+use crate::schema::{self, Tables};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NetworkFormat {
+    /// GraphML, for Gephi, Cytoscape, networkx, ...
+    Graphml,
+    /// Edge list with Source,Target,Weight columns (Gephi's spreadsheet import)
+    Csv,
+}
+
+/// Messages from one correspondent to another (each recipient counts once per message).
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub weight: i64,
+    pub first_contact: Option<String>,
+    pub last_contact: Option<String>,
+}
+
+fn require_table(conn: &Connection, table: &str) -> Result<()> {
+    if !schema::table_exists(conn, table)? {
+        bail!("The database has no {} table; import it with --schema normalized or full", table);
+    }
+    Ok(())
+}
+
+/// Sender→recipient edges, by canonical contact so --aliases merges people.
+pub fn correspondent_edges(conn: &Connection, tables: &Tables) -> Result<Vec<Edge>> {
+    require_table(conn, &tables.recipients)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.canonical_contact, r.canonical_contact, COUNT(DISTINCT e.id), MIN(e.date_parsed), MAX(e.date_parsed)
+         FROM {emails} e JOIN {recipients} r ON r.email_id = e.id
+         WHERE e.canonical_contact IS NOT NULL AND r.canonical_contact IS NOT NULL
+           AND e.canonical_contact <> r.canonical_contact
+         GROUP BY e.canonical_contact, r.canonical_contact
+         ORDER BY 3 DESC, 1, 2",
+        emails = tables.emails,
+        recipients = tables.recipients,
+    ))?;
+    let edges = stmt
+        .query_map([], |row| {
+            Ok(Edge {
+                source: row.get(0)?,
+                target: row.get(1)?,
+                weight: row.get(2)?,
+                first_contact: row.get(3)?,
+                last_contact: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(edges)
+}
+
+pub fn write_network(out: &mut impl Write, edges: &[Edge], format: NetworkFormat) -> Result<()> {
+    match format {
+        NetworkFormat::Csv => {
+            writeln!(out, "Source,Target,Weight,FirstContact,LastContact")?;
+            for edge in edges {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv_field(&edge.source),
+                    csv_field(&edge.target),
+                    edge.weight,
+                    csv_field(edge.first_contact.as_deref().unwrap_or("")),
+                    csv_field(edge.last_contact.as_deref().unwrap_or("")),
+                )?;
+            }
+        }
+        NetworkFormat::Graphml => {
+            writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+            writeln!(out, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>"#)?;
+            writeln!(out, r#"  <key id="first" for="edge" attr.name="first_contact" attr.type="string"/>"#)?;
+            writeln!(out, r#"  <key id="last" for="edge" attr.name="last_contact" attr.type="string"/>"#)?;
+            writeln!(out, r#"  <graph id="correspondents" edgedefault="directed">"#)?;
+
+            let mut nodes: Vec<&str> = edges.iter().flat_map(|edge| [edge.source.as_str(), edge.target.as_str()]).collect();
+            nodes.sort_unstable();
+            nodes.dedup();
+            for node in nodes {
+                writeln!(out, r#"    <node id="{}"/>"#, escape_html(node))?;
+            }
+            for edge in edges {
+                writeln!(out, r#"    <edge source="{}" target="{}">"#, escape_html(&edge.source), escape_html(&edge.target))?;
+                writeln!(out, r#"      <data key="weight">{}</data>"#, edge.weight)?;
+                if let Some(first) = &edge.first_contact {
+                    writeln!(out, r#"      <data key="first">{}</data>"#, escape_html(first))?;
+                }
+                if let Some(last) = &edge.last_contact {
+                    writeln!(out, r#"      <data key="last">{}</data>"#, escape_html(last))?;
+                }
+                writeln!(out, "    </edge>")?;
+            }
+
+            writeln!(out, "  </graph>")?;
+            writeln!(out, "</graphml>")?;
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// How quickly replies go back and forth with one correspondent.
+pub struct ContactLatency {
+    pub contact: String,
+    /// Median seconds before I answered them, over `my_replies` replies
+    pub my_median: Option<i64>,
+    pub my_replies: usize,
+    /// Median seconds before they answered me, over `their_replies` replies
+    pub their_median: Option<i64>,
+    pub their_replies: usize,
+}
+
+/// Reply latency per correspondent, from messages whose In-Reply-To points at
+/// another message in the database. Auto-replies aren't counted: a vacation
+/// responder answers in seconds. `me` lists my own addresses.
+pub fn reply_latency(conn: &Connection, tables: &Tables, me: &[String]) -> Result<Vec<ContactLatency>> {
+    let me: Vec<String> = me.iter().map(|address| address.trim().to_lowercase()).collect();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.canonical_contact, r.canonical_contact,
+                CAST(round((julianday(r.date_parsed) - julianday(p.date_parsed)) * 86400) AS INTEGER)
+         FROM {emails} r JOIN {emails} p ON p.message_id = trim(r.in_reply_to)
+         WHERE r.date_parsed IS NOT NULL AND p.date_parsed IS NOT NULL
+           AND r.canonical_contact IS NOT NULL AND p.canonical_contact IS NOT NULL
+           AND r.canonical_contact <> p.canonical_contact
+           AND NOT r.is_auto_reply",
+        emails = tables.emails,
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let mut by_contact: HashMap<String, (Vec<i64>, Vec<i64>)> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let (parent_from, reply_from, seconds): (String, String, i64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        // A negative delay means one of the clocks was wrong
+        if seconds < 0 {
+            continue;
+        }
+        if me.contains(&reply_from) && !me.contains(&parent_from) {
+            by_contact.entry(parent_from).or_default().0.push(seconds);
+        } else if me.contains(&parent_from) && !me.contains(&reply_from) {
+            by_contact.entry(reply_from).or_default().1.push(seconds);
+        }
+    }
+
+    let mut latencies: Vec<ContactLatency> = by_contact
+        .into_iter()
+        .map(|(contact, (mut mine, mut theirs))| ContactLatency {
+            contact,
+            my_replies: mine.len(),
+            my_median: median(&mut mine),
+            their_replies: theirs.len(),
+            their_median: median(&mut theirs),
+        })
+        .collect();
+    latencies.sort_by(|a, b| {
+        (b.my_replies + b.their_replies).cmp(&(a.my_replies + a.their_replies)).then_with(|| a.contact.cmp(&b.contact))
+    });
+    Ok(latencies)
+}
+
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let middle = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) { (values[middle - 1] + values[middle]) / 2 } else { values[middle] })
+}
+
+pub fn write_latency(out: &mut impl Write, latencies: &[ContactLatency]) -> Result<()> {
+    let width = latencies.iter().map(|row| row.contact.chars().count()).max().unwrap_or(0).max("Contact".len());
+    writeln!(out, "{:<width$}  {:>14}  {:>7}  {:>14}  {:>7}", "Contact", "I reply in", "replies", "They reply in", "replies")?;
+    for row in latencies {
+        writeln!(
+            out,
+            "{:<width$}  {:>14}  {:>7}  {:>14}  {:>7}",
+            row.contact,
+            row.my_median.map(format_duration).unwrap_or_else(|| "-".to_string()),
+            row.my_replies,
+            row.their_median.map(format_duration).unwrap_or_else(|| "-".to_string()),
+            row.their_replies,
+        )?;
+    }
+    Ok(())
+}
+
+/// A duration in its two largest units, e.g. "3d 4h" or "12m 5s".
+fn format_duration(seconds: i64) -> String {
+    let units = [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |rest, &(unit, size)| {
+            let count = *rest / size;
+            *rest %= size;
+            Some((count, unit))
+        })
+        .skip_while(|&(count, _)| count == 0)
+        .take(2)
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect();
+    if parts.is_empty() { "0s".to_string() } else { parts.join(" ") }
+}
+
+/// Message counts for one hour of the day or day of the week.
+pub struct ActivityBucket {
+    pub label: String,
+    pub sent: i64,
+    pub received: i64,
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Messages per hour of the day and per weekday, split into sent (from one of
+/// `me`) and received. Times are the sender's local time, as written in each
+/// Date header.
+pub fn activity(conn: &Connection, tables: &Tables, me: &[String]) -> Result<(Vec<ActivityBucket>, Vec<ActivityBucket>)> {
+    let me: Vec<String> = me.iter().map(|address| address.trim().to_lowercase()).collect();
+    let placeholders = vec!["?"; me.len()].join(", ");
+    // date_parsed is UTC; databases from before date_offset existed stored local times
+    let local_time = if schema::column_exists(conn, &tables.emails, "date_offset")? {
+        "COALESCE(datetime(date_parsed, date_offset), date_parsed)"
+    } else {
+        "date_parsed"
+    };
+
+    let count = |bucket: &str| -> Result<HashMap<i64, (i64, i64)>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT CAST(strftime('{bucket}', {local_time}) AS INTEGER),
+                    SUM(canonical_contact IN ({placeholders})), SUM(canonical_contact IS NULL OR canonical_contact NOT IN ({placeholders}))
+             FROM {emails}
+             WHERE date_parsed IS NOT NULL
+             GROUP BY 1",
+            bucket = bucket,
+            local_time = local_time,
+            placeholders = placeholders,
+            emails = tables.emails,
+        ))?;
+        let params = rusqlite::params_from_iter(me.iter().chain(me.iter()));
+        let rows = stmt
+            .query_map(params, |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(rows)
+    };
+
+    let hours = count("%H")?;
+    let weekdays = count("%w")?;
+    let bucket = |counts: &HashMap<i64, (i64, i64)>, key: i64, label: String| {
+        let (sent, received) = counts.get(&key).copied().unwrap_or_default();
+        ActivityBucket { label, sent, received }
+    };
+    Ok((
+        (0..24).map(|hour| bucket(&hours, hour, format!("{:02}:00", hour))).collect(),
+        // Monday first
+        [1, 2, 3, 4, 5, 6, 0].into_iter().map(|day| bucket(&weekdays, day, WEEKDAYS[day as usize].to_string())).collect(),
+    ))
+}
+
+pub fn write_activity(out: &mut impl Write, title: &str, buckets: &[ActivityBucket], show_sent: bool) -> Result<()> {
+    const BAR_WIDTH: i64 = 40;
+    let busiest = buckets.iter().map(|bucket| bucket.sent + bucket.received).max().unwrap_or(0).max(1);
+
+    writeln!(out, "{}", title)?;
+    if show_sent {
+        writeln!(out, "{:<6} {:>8} {:>8}", "", "Sent", "Received")?;
+    }
+    for bucket in buckets {
+        let total = bucket.sent + bucket.received;
+        let bar = "#".repeat((total * BAR_WIDTH / busiest) as usize);
+        let line = if show_sent {
+            format!("{:<6} {:>8} {:>8}  {}", bucket.label, bucket.sent, bucket.received, bar)
+        } else {
+            format!("{:<6} {:>8}  {}", bucket.label, total, bar)
+        };
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AttachmentGrouping {
+    /// MIME type, e.g. application/pdf
+    Type,
+    /// File name extension, e.g. pdf
+    Extension,
+}
+
+/// Attachments of one type (or extension) across the archive.
+pub struct AttachmentStat {
+    pub key: String,
+    pub count: i64,
+    pub bytes: i64,
+}
+
+/// Attachment counts and sizes, largest total first.
+pub fn attachment_stats(conn: &Connection, tables: &Tables, grouping: AttachmentGrouping) -> Result<Vec<AttachmentStat>> {
+    require_table(conn, &tables.attachments)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT COALESCE(filename, ''), COALESCE(content_type, ''), size FROM {}",
+        tables.attachments
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let mut stats: HashMap<String, (i64, i64)> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let (filename, content_type, size): (String, String, i64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        let key = match grouping {
+            AttachmentGrouping::Type => content_type.split(';').next().unwrap_or("").trim().to_lowercase(),
+            AttachmentGrouping::Extension => match filename.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => extension.to_lowercase(),
+                _ => String::new(),
+            },
+        };
+        let key = if key.is_empty() { "(none)".to_string() } else { key };
+        let entry = stats.entry(key).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+    }
+
+    let mut stats: Vec<AttachmentStat> =
+        stats.into_iter().map(|(key, (count, bytes))| AttachmentStat { key, count, bytes }).collect();
+    stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+    Ok(stats)
+}
+
+pub fn write_attachment_stats(out: &mut impl Write, stats: &[AttachmentStat], grouping: AttachmentGrouping) -> Result<()> {
+    let heading = match grouping {
+        AttachmentGrouping::Type => "Type",
+        AttachmentGrouping::Extension => "Extension",
+    };
+    let total: i64 = stats.iter().map(|stat| stat.bytes).sum();
+    let width = stats.iter().map(|stat| stat.key.chars().count()).max().unwrap_or(0).max(heading.len());
+
+    writeln!(out, "{:<width$}  {:>7}  {:>10}  {:>6}", heading, "Count", "Size", "Share")?;
+    for stat in stats {
+        let share = if total > 0 { stat.bytes as f64 * 100.0 / total as f64 } else { 0.0 };
+        writeln!(out, "{:<width$}  {:>7}  {:>10}  {:>5.1}%", stat.key, stat.count, format_bytes(stat.bytes), share)?;
+    }
+    writeln!(out, "{:<width$}  {:>7}  {:>10}", "Total", stats.iter().map(|stat| stat.count).sum::<i64>(), format_bytes(total))?;
+    Ok(())
+}
+
+/// A size in the largest binary unit that keeps it at or above 1, e.g. "3.2 MiB".
+pub(crate) fn format_bytes(bytes: i64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TableFormat {
+    /// Aligned columns for the terminal
+    Table,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// Someone you've exchanged mail with.
+pub struct Contact {
+    pub address: String,
+    pub name: Option<String>,
+    /// Messages they sent (that are in the archive)
+    pub received: i64,
+    /// Messages addressed to them, only counting ones from `me` when it's given
+    pub sent: i64,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+}
+
+/// Correspondents ranked by the number of messages exchanged; a `limit` of 0
+/// returns all of them.
+pub fn contacts(conn: &Connection, tables: &Tables, me: &[String], limit: usize) -> Result<Vec<Contact>> {
+    require_table(conn, &tables.recipients)?;
+
+    let me: Vec<String> = me.iter().map(|address| address.trim().to_lowercase()).collect();
+    let placeholders = vec!["?"; me.len()].join(", ");
+    let from_me = if me.is_empty() { String::new() } else { format!("AND e.canonical_contact IN ({})", placeholders) };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT contact, MAX(name), SUM(received), SUM(sent), MIN(seen), MAX(seen)
+         FROM (
+             SELECT canonical_contact AS contact, NULL AS name, 1 AS received, 0 AS sent, date_parsed AS seen
+             FROM {emails} WHERE canonical_contact IS NOT NULL
+             UNION ALL
+             SELECT r.canonical_contact, MAX(NULLIF(r.name, '')), 0, 1, e.date_parsed
+             FROM {emails} e JOIN {recipients} r ON r.email_id = e.id
+             WHERE r.canonical_contact IS NOT NULL {from_me}
+             GROUP BY e.id, r.canonical_contact
+         )
+         WHERE contact NOT IN ({placeholders})
+         GROUP BY contact
+         ORDER BY SUM(received) + SUM(sent) DESC, contact
+         LIMIT {limit}",
+        emails = tables.emails,
+        recipients = tables.recipients,
+        from_me = from_me,
+        placeholders = placeholders,
+        // SQLite reads a negative LIMIT as no limit
+        limit = if limit == 0 { -1 } else { limit as i64 },
+    ))?;
+    let params = rusqlite::params_from_iter(me.iter().chain(me.iter()));
+    let contacts = stmt
+        .query_map(params, |row| {
+            Ok(Contact {
+                address: row.get(0)?,
+                name: row.get(1)?,
+                received: row.get(2)?,
+                sent: row.get(3)?,
+                first_seen: row.get(4)?,
+                last_seen: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(contacts)
+}
+
+pub fn write_contacts(out: &mut impl Write, contacts: &[Contact], format: TableFormat) -> Result<()> {
+    // Dates only; the time of the first and last message is noise here
+    let day = |date: &Option<String>| date.as_deref().map(|date| date.get(..10).unwrap_or(date)).unwrap_or("").to_string();
+
+    match format {
+        TableFormat::Csv => {
+            writeln!(out, "address,name,total,sent,received,first_seen,last_seen")?;
+            for contact in contacts {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&contact.address),
+                    csv_field(contact.name.as_deref().unwrap_or("")),
+                    contact.sent + contact.received,
+                    contact.sent,
+                    contact.received,
+                    day(&contact.first_seen),
+                    day(&contact.last_seen),
+                )?;
+            }
+        }
+        TableFormat::Table => {
+            let address_width = contacts.iter().map(|c| c.address.chars().count()).max().unwrap_or(0).max("Contact".len());
+            let name_width = contacts
+                .iter()
+                .map(|c| c.name.as_deref().unwrap_or("").chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("Name".len());
+            writeln!(
+                out,
+                "{:<address_width$}  {:<name_width$}  {:>6}  {:>6}  {:>8}  {:<10}  {:<10}",
+                "Contact", "Name", "Total", "Sent", "Received", "First", "Last"
+            )?;
+            for contact in contacts {
+                writeln!(
+                    out,
+                    "{:<address_width$}  {:<name_width$}  {:>6}  {:>6}  {:>8}  {:<10}  {:<10}",
+                    contact.address,
+                    contact.name.as_deref().unwrap_or(""),
+                    contact.sent + contact.received,
+                    contact.sent,
+                    contact.received,
+                    day(&contact.first_seen),
+                    day(&contact.last_seen),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A message that refers to mail the archive doesn't have.
+pub struct Orphan {
+    pub id: i64,
+    pub thread_id: Option<String>,
+    pub date: Option<String>,
+    pub from: String,
+    pub subject: String,
+    /// Number of messages its References/In-Reply-To name
+    pub referenced: usize,
+    /// The ones that aren't in the archive, oldest first
+    pub missing: Vec<String>,
+    /// Whether the message it directly replies to is among them
+    pub parent_missing: bool,
+}
+
+/// Messages whose References or In-Reply-To name a Message-ID that isn't in
+/// the archive, i.e. the places where a conversation is incomplete. Oldest first.
+pub fn orphans(conn: &Connection, tables: &Tables) -> Result<Vec<Orphan>> {
+    require_table(conn, &tables.message_references)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.id, t.thread_id, e.date_parsed, COALESCE(e.from_addr, ''), COALESCE(e.subject, ''),
+                r.referenced_message_id,
+                EXISTS(SELECT 1 FROM {emails} p WHERE p.message_id = r.referenced_message_id)
+         FROM {references} r
+         JOIN {emails} e ON e.id = r.email_id
+         LEFT JOIN {threads} t ON t.email_id = e.id
+         WHERE e.id IN (
+             SELECT m.email_id FROM {references} m
+             WHERE NOT EXISTS (SELECT 1 FROM {emails} p WHERE p.message_id = m.referenced_message_id)
+         )
+         ORDER BY e.date_parsed, e.id, r.position",
+        emails = tables.emails,
+        references = tables.message_references,
+        threads = tables.threads,
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let mut orphans: Vec<Orphan> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let referenced: String = row.get(5)?;
+        let present: bool = row.get(6)?;
+        if orphans.last().is_none_or(|orphan| orphan.id != id) {
+            orphans.push(Orphan {
+                id,
+                thread_id: row.get(1)?,
+                date: row.get(2)?,
+                from: row.get(3)?,
+                subject: row.get(4)?,
+                referenced: 0,
+                missing: Vec::new(),
+                parent_missing: false,
+            });
+        }
+        let orphan = orphans.last_mut().unwrap();
+        orphan.referenced += 1;
+        // Rows come in position order, so the last one seen is the parent
+        orphan.parent_missing = !present;
+        if !present {
+            orphan.missing.push(referenced);
+        }
+    }
+    Ok(orphans)
+}
+
+pub fn write_orphans(out: &mut impl Write, orphans: &[Orphan]) -> Result<()> {
+    for orphan in orphans {
+        writeln!(
+            out,
+            "#{}  {}  {}  {}",
+            orphan.id,
+            orphan.date.as_deref().unwrap_or("(no date)"),
+            orphan.from,
+            orphan.subject
+        )?;
+        writeln!(
+            out,
+            "    {} of {} referenced messages missing{}",
+            orphan.missing.len(),
+            orphan.referenced,
+            if orphan.parent_missing { ", including the one it replies to" } else { "" }
+        )?;
+        for message_id in &orphan.missing {
+            writeln!(out, "    {}", message_id)?;
+        }
+    }
+
+    if orphans.is_empty() {
+        writeln!(out, "Every referenced message is in the archive")?;
+        return Ok(());
+    }
+    let threads: HashSet<_> = orphans.iter().filter_map(|orphan| orphan.thread_id.as_deref()).collect();
+    let plural = |count: usize, noun: &str| format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" });
+    writeln!(
+        out,
+        "\n{} referring to mail that isn't in the archive, in {}",
+        plural(orphans.len(), "message"),
+        plural(threads.len(), "conversation")
+    )?;
+    Ok(())
+}
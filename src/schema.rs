@@ -0,0 +1,1053 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{Connection, ErrorCode, LoadExtensionGuard};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static IDENTIFIER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap());
+/// A pragma value: a number, a keyword like WAL or OFF, or a size
+static PRAGMA_VALUE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^-?[A-Za-z0-9_.]+$").unwrap());
+
+/// Which tables an import creates, chosen with `--schema`. Each profile
+/// includes everything in the one before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchemaProfile {
+    /// Just the emails table (plus import_runs, insert_errors and metadata)
+    Flat,
+    /// Adds recipients, labels, attachments, links and threads tables
+    Normalized,
+    /// Adds every header and the raw message
+    Full,
+}
+
+impl SchemaProfile {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::Normalized => "normalized",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// FTS5 tokenizer used for the full-text index, chosen with `--fts-tokenizer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FtsTokenizer {
+    /// Splits on Unicode word boundaries (the FTS5 default)
+    Unicode61,
+    /// unicode61 plus English stemming, so "running" matches "run"
+    Porter,
+    /// Indexes every 3-character sequence: substring search, works for CJK text
+    Trigram,
+}
+
+impl FtsTokenizer {
+    /// The FTS5 `tokenize` option for this tokenizer.
+    pub fn spec(self, remove_diacritics: Option<u8>) -> Result<String> {
+        let base = match self {
+            Self::Unicode61 => "unicode61",
+            Self::Porter => "porter unicode61",
+            Self::Trigram => "trigram",
+        };
+        match remove_diacritics {
+            None => Ok(base.to_string()),
+            Some(2) if self == Self::Trigram => {
+                anyhow::bail!("The trigram tokenizer only supports --fts-remove-diacritics 0 or 1")
+            }
+            Some(level) => Ok(format!("{} remove_diacritics {}", base, level)),
+        }
+    }
+}
+
+/// Names of the tables and indexes mbox2db creates, after applying
+/// `--table-prefix` and `--table-name`.
+#[derive(Clone, Debug)]
+pub struct Tables {
+    prefix: String,
+    pub emails: String,
+    pub emails_fts: String,
+    pub import_runs: String,
+    pub insert_errors: String,
+    pub recipients: String,
+    pub links: String,
+    pub attachment_text: String,
+    pub attachment_manifest: String,
+    pub inline_images: String,
+    pub metadata: String,
+    pub labels: String,
+    pub attachments: String,
+    pub attachment_blobs: String,
+    pub threads: String,
+    pub message_references: String,
+    pub arc_hops: String,
+    pub spam_rules: String,
+    pub orders: String,
+    pub reservations: String,
+    pub receipts: String,
+    pub amounts: String,
+    pub chats: String,
+    pub chat_lines: String,
+    pub thread_summaries: String,
+    pub vectors: String,
+    pub vec_emails: String,
+    pub headers: String,
+    pub raw_messages: String,
+}
+
+impl Tables {
+    /// Every table gets `prefix`; `emails_name` replaces "emails" for the main table.
+    pub fn new(prefix: &str, emails_name: Option<&str>) -> Result<Self> {
+        let name = |table: &str| format!("{}{}", prefix, table);
+        let emails = name(emails_name.unwrap_or("emails"));
+        let tables = Self {
+            prefix: prefix.to_string(),
+            emails_fts: format!("{}_fts", emails),
+            emails,
+            import_runs: name("import_runs"),
+            insert_errors: name("insert_errors"),
+            recipients: name("recipients"),
+            links: name("links"),
+            attachment_text: name("attachment_text"),
+            attachment_manifest: name("attachment_manifest"),
+            inline_images: name("inline_images"),
+            metadata: name("metadata"),
+            labels: name("labels"),
+            attachments: name("attachments"),
+            attachment_blobs: name("attachment_blobs"),
+            threads: name("threads"),
+            message_references: name("message_references"),
+            arc_hops: name("arc_hops"),
+            spam_rules: name("spam_rules"),
+            orders: name("orders"),
+            reservations: name("reservations"),
+            receipts: name("receipts"),
+            amounts: name("amounts"),
+            chats: name("chats"),
+            chat_lines: name("chat_lines"),
+            thread_summaries: name("thread_summaries"),
+            vectors: name("vectors"),
+            vec_emails: name("vec_emails"),
+            headers: name("headers"),
+            raw_messages: name("raw_messages"),
+        };
+
+        // Names are spliced into SQL, so only plain identifiers are allowed
+        if !IDENTIFIER.is_match(&tables.emails) || !IDENTIFIER.is_match(&tables.import_runs) {
+            anyhow::bail!(
+                "Invalid table prefix or name: {} (use letters, digits and underscores)",
+                tables.emails
+            );
+        }
+        Ok(tables)
+    }
+
+    pub fn index(&self, name: &str) -> String {
+        format!("{}idx_{}", self.prefix, name)
+    }
+
+    pub fn view(&self, name: &str) -> String {
+        format!("{}v_{}", self.prefix, name)
+    }
+}
+
+/// Parses a `--pragma` setting such as "synchronous=OFF" or "cache_size=-200000".
+pub fn parse_pragma(value: &str) -> std::result::Result<(String, String), String> {
+    let (name, setting) = value.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, got {:?}", value))?;
+    let (name, setting) = (name.trim(), setting.trim());
+    if !IDENTIFIER.is_match(name) || !PRAGMA_VALUE.is_match(setting) {
+        return Err(format!("invalid pragma {:?}", value));
+    }
+    Ok((name.to_string(), setting.to_string()))
+}
+
+/// Opens (creating if needed) the database and its tables. `pragmas` are set
+/// after mbox2db's own (WAL, synchronous=NORMAL, ...), so they can override them.
+pub fn create_database(
+    db_path: &Path,
+    tables: &Tables,
+    profile: SchemaProfile,
+    extensions: &[PathBuf],
+    pragmas: &[(String, String)],
+    busy_timeout: Duration,
+) -> Result<Connection> {
+    if let Some(parent) = db_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+    }
+
+    let conn = Connection::open(db_path)
+        .with_context(|| format!("Failed to create database: {}", db_path.display()))?;
+    // Wait for other connections (a Datasette reading it, another import) instead of failing
+    conn.busy_timeout(busy_timeout)?;
+
+    conn.execute_batch(
+        "PRAGMA journal_mode=WAL;
+         PRAGMA synchronous=NORMAL;
+         PRAGMA cache_size=-64000;
+         PRAGMA temp_store=MEMORY;
+         PRAGMA mmap_size=30000000000;"
+    )?;
+    for (name, value) in pragmas {
+        conn.execute_batch(&format!("PRAGMA {}={};", name, value))
+            .with_context(|| format!("Failed to set PRAGMA {}={}", name, value))?;
+    }
+
+    load_extensions(&conn, extensions)?;
+    check_existing_tables(&conn, db_path, tables, profile)?;
+    create_tables(&conn, tables, profile)?;
+    Ok(conn)
+}
+
+/// Appending to a database an older mbox2db made would fail on the first
+/// column it didn't have yet; this says so up front instead.
+fn check_existing_tables(conn: &Connection, db_path: &Path, tables: &Tables, profile: SchemaProfile) -> Result<()> {
+    if !table_exists(conn, &tables.emails)? {
+        return Ok(());
+    }
+    let expected = Connection::open_in_memory()?;
+    create_tables(&expected, tables, profile)?;
+    let names: Vec<String> = expected
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    // Each column's name and whether it's generated (which can't be written)
+    let columns = |conn: &Connection, table: &str| -> Result<HashSet<(String, bool)>> {
+        Ok(conn
+            .prepare("SELECT name, hidden IN (2, 3) FROM pragma_table_xinfo(?1)")?
+            .query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?)
+    };
+    let mut missing = Vec::new();
+    for table in names {
+        let existing = columns(conn, &table)?;
+        if existing.is_empty() {
+            continue;
+        }
+        let mut absent: Vec<String> = columns(&expected, &table)?.difference(&existing).map(|(column, _)| column.clone()).collect();
+        absent.sort();
+        missing.extend(absent.into_iter().map(|column| format!("{}.{}", table, column)));
+    }
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "{} was created by an older mbox2db and lacks columns this version writes, or has them in an older form ({}); \
+             re-import everything with --if-exists overwrite, or append to a new database",
+            db_path.display(),
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Creates whichever of the profile's tables and indexes don't exist yet.
+fn create_tables(conn: &Connection, tables: &Tables, profile: SchemaProfile) -> Result<()> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {emails} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                from_addr TEXT COLLATE NOCASE,
+                from_folded TEXT,
+                canonical_contact TEXT,
+                from_domain TEXT,
+                to_addr TEXT,
+                cc TEXT,
+                bcc TEXT,
+                original_to TEXT,
+                delivered_to TEXT,
+                subject TEXT COLLATE NOCASE,
+                subject_folded TEXT,
+                date TEXT,
+                date_parsed TEXT,
+                date_offset TEXT,
+                date_strategy TEXT,
+                date_display TEXT,
+                date_source TEXT,
+                message_id TEXT,
+                in_reply_to TEXT,
+                refs TEXT,
+                content_type TEXT,
+                has_attachments INTEGER NOT NULL DEFAULT 0,
+                attachment_count INTEGER NOT NULL DEFAULT 0,
+                body_plain TEXT,
+                body_html TEXT,
+                body_plain_raw BLOB,
+                body_html_raw BLOB,
+                body_clean TEXT,
+                body_word_count INTEGER,
+                body_char_count INTEGER,
+                signature TEXT,
+                is_signed INTEGER NOT NULL DEFAULT 0,
+                is_encrypted INTEGER NOT NULL DEFAULT 0,
+                is_decrypted INTEGER NOT NULL DEFAULT 0,
+                is_bulk INTEGER NOT NULL DEFAULT 0,
+                is_automated INTEGER NOT NULL DEFAULT 0,
+                is_auto_reply INTEGER NOT NULL DEFAULT 0,
+                importance INTEGER,
+                read_receipt_to TEXT,
+                priority TEXT,
+                mailer TEXT,
+                spam_score REAL,
+                detected_charset TEXT,
+                gmail_category TEXT,
+                calendar_method TEXT,
+                account TEXT,
+                import_run_id INTEGER REFERENCES {import_runs}(id),
+                message_hash TEXT
+            )",
+            emails = tables.emails,
+            import_runs = tables.import_runs,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(from_addr)", tables.index("from"), tables.emails),
+        [],
+    )?;
+    
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(from_folded)", tables.index("from_folded"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(canonical_contact)", tables.index("canonical_contact"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(from_domain)", tables.index("from_domain"), tables.emails),
+        [],
+    )?;
+    
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(date)", tables.index("date"), tables.emails),
+        [],
+    )?;
+    
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(date_parsed)", tables.index("date_parsed"), tables.emails),
+        [],
+    )?;
+    
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(subject)", tables.index("subject"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(subject_folded)", tables.index("subject_folded"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(account)", tables.index("account"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(gmail_category)", tables.index("gmail_category"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(importance)", tables.index("importance"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(message_id)", tables.index("message_id"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(message_hash)", tables.index("message_hash"), tables.emails),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {import_runs} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tool_version TEXT NOT NULL,
+                arguments TEXT NOT NULL,
+                input_path TEXT,
+                input_sha256 TEXT,
+                input_bytes INTEGER,
+                started_at TEXT NOT NULL,
+                finished_at TEXT,
+                imported_count INTEGER,
+                skipped_count INTEGER,
+                duplicate_count INTEGER,
+                failed_count INTEGER
+            )",
+            import_runs = tables.import_runs,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {insert_errors} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                import_run_id INTEGER REFERENCES {import_runs}(id),
+                message_number INTEGER NOT NULL,
+                message_id TEXT,
+                from_addr TEXT,
+                subject TEXT,
+                error TEXT NOT NULL
+            )",
+            insert_errors = tables.insert_errors,
+            import_runs = tables.import_runs,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {metadata} (
+                key TEXT PRIMARY KEY,
+                value TEXT
+            )",
+            metadata = tables.metadata,
+        ),
+        [],
+    )?;
+
+    record_profile(conn, tables, profile)?;
+    if profile >= SchemaProfile::Normalized {
+        create_normalized_tables(conn, tables)?;
+    }
+    if profile == SchemaProfile::Full {
+        create_full_tables(conn, tables)?;
+    }
+    Ok(())
+}
+
+/// Tables added by `--schema normalized`: one row per recipient, label, attachment,
+/// link and so on, plus each message's thread.
+fn create_normalized_tables(conn: &Connection, tables: &Tables) -> Result<()> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {recipients} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                kind TEXT NOT NULL,
+                address TEXT NOT NULL,
+                name TEXT,
+                canonical_contact TEXT
+            )",
+            recipients = tables.recipients,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(address)", tables.index("recipients_address"), tables.recipients),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("recipients_email_id"), tables.recipients),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(canonical_contact)", tables.index("recipients_canonical_contact"), tables.recipients),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {links} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                url TEXT NOT NULL,
+                domain TEXT,
+                anchor_text TEXT
+            )",
+            links = tables.links,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(domain)", tables.index("links_domain"), tables.links),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("links_email_id"), tables.links),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {attachment_text} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                filename TEXT,
+                content_type TEXT,
+                text TEXT NOT NULL
+            )",
+            attachment_text = tables.attachment_text,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("attachment_text_email_id"), tables.attachment_text),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {attachment_manifest} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                archive_filename TEXT,
+                entry_name TEXT NOT NULL,
+                size INTEGER
+            )",
+            attachment_manifest = tables.attachment_manifest,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(entry_name)", tables.index("attachment_manifest_entry_name"), tables.attachment_manifest),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {inline_images} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                content_id TEXT NOT NULL,
+                filename TEXT,
+                content_type TEXT,
+                data BLOB
+            )",
+            inline_images = tables.inline_images,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("inline_images_email_id"), tables.inline_images),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {labels} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                label TEXT NOT NULL
+            )",
+            labels = tables.labels,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(label)", tables.index("labels_label"), tables.labels),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("labels_email_id"), tables.labels),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {attachments} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                filename TEXT,
+                content_type TEXT,
+                content_id TEXT,
+                size INTEGER NOT NULL,
+                sha256 TEXT
+            )",
+            attachments = tables.attachments,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("attachments_email_id"), tables.attachments),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                sha256 TEXT PRIMARY KEY,
+                data BLOB NOT NULL
+            )",
+            tables.attachment_blobs
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(sha256)", tables.index("attachments_sha256"), tables.attachments),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {threads} (
+                email_id INTEGER PRIMARY KEY REFERENCES {emails}(id),
+                thread_id TEXT NOT NULL
+            )",
+            threads = tables.threads,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(thread_id)", tables.index("threads_thread_id"), tables.threads),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {message_references} (
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                position INTEGER NOT NULL,
+                referenced_message_id TEXT NOT NULL,
+                PRIMARY KEY (email_id, position)
+            )",
+            message_references = tables.message_references,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {}(referenced_message_id)",
+            tables.index("message_references_referenced_message_id"),
+            tables.message_references
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {arc_hops} (
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                instance INTEGER NOT NULL,
+                authserv_id TEXT,
+                sealed_by TEXT,
+                chain_validation TEXT,
+                dkim TEXT,
+                spf TEXT,
+                dmarc TEXT,
+                arc TEXT,
+                results TEXT,
+                PRIMARY KEY (email_id, instance)
+            )",
+            arc_hops = tables.arc_hops,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {spam_rules} (
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                rule TEXT NOT NULL,
+                score REAL,
+                description TEXT,
+                PRIMARY KEY (email_id, rule)
+            )",
+            spam_rules = tables.spam_rules,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(rule)", tables.index("spam_rules_rule"), tables.spam_rules),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {orders} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                merchant TEXT,
+                order_number TEXT,
+                total REAL,
+                currency TEXT,
+                order_date TEXT,
+                status TEXT,
+                tracking_number TEXT,
+                carrier TEXT
+            )",
+            orders = tables.orders,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("orders_email_id"), tables.orders),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(merchant, order_number)", tables.index("orders_merchant"), tables.orders),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {reservations} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                kind TEXT NOT NULL,
+                reservation_number TEXT,
+                status TEXT,
+                provider TEXT,
+                name TEXT,
+                passenger TEXT,
+                flight_number TEXT,
+                from_place TEXT,
+                to_place TEXT,
+                start_time TEXT,
+                end_time TEXT,
+                location TEXT,
+                source TEXT NOT NULL
+            )",
+            reservations = tables.reservations,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("reservations_email_id"), tables.reservations),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(kind, start_time)", tables.index("reservations_kind"), tables.reservations),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {receipts} (
+                email_id INTEGER PRIMARY KEY REFERENCES {emails}(id),
+                invoice_number TEXT,
+                total REAL,
+                currency TEXT,
+                due_date TEXT
+            )",
+            receipts = tables.receipts,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {amounts} (
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                position INTEGER NOT NULL,
+                amount REAL NOT NULL,
+                currency TEXT,
+                label TEXT,
+                PRIMARY KEY (email_id, position)
+            )",
+            amounts = tables.amounts,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {chats} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id TEXT,
+                subject TEXT,
+                date_parsed TEXT,
+                participants TEXT,
+                account TEXT,
+                import_run_id INTEGER REFERENCES {import_runs}(id),
+                message_hash TEXT
+            )",
+            chats = tables.chats,
+            import_runs = tables.import_runs,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {chat_lines} (
+                chat_id INTEGER NOT NULL REFERENCES {chats}(id),
+                position INTEGER NOT NULL,
+                time TEXT,
+                sender TEXT NOT NULL,
+                text TEXT NOT NULL,
+                PRIMARY KEY (chat_id, position)
+            )",
+            chat_lines = tables.chat_lines,
+            chats = tables.chats,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(date_parsed)", tables.index("chats_date_parsed"), tables.chats),
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Tables added by `--schema full`: every header and the raw message.
+fn create_full_tables(conn: &Connection, tables: &Tables) -> Result<()> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {headers} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL REFERENCES {emails}(id),
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                value TEXT
+            )",
+            headers = tables.headers,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(email_id)", tables.index("headers_email_id"), tables.headers),
+        [],
+    )?;
+
+    conn.execute(
+        &format!("CREATE INDEX IF NOT EXISTS {} ON {}(name)", tables.index("headers_name"), tables.headers),
+        [],
+    )?;
+
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {raw_messages} (
+                email_id INTEGER PRIMARY KEY REFERENCES {emails}(id),
+                raw BLOB NOT NULL
+            )",
+            raw_messages = tables.raw_messages,
+            emails = tables.emails,
+        ),
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// True for SQLite's "database is locked" errors.
+pub fn is_busy(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(error, _)) if matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// True for errors after which nothing more can be written: a full disk, I/O
+/// failures, corruption, running out of memory.
+pub fn is_fatal(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(error, _)) if matches!(
+            error.code,
+            ErrorCode::DiskFull | ErrorCode::SystemIoFailure | ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase | ErrorCode::OutOfMemory
+        )
+    )
+}
+
+/// Runs `op` again, with growing pauses, while it fails because another
+/// connection holds a lock, for up to `timeout` in all. For the cases where
+/// SQLite reports busy without waiting out the busy timeout itself.
+pub fn retry_busy<T>(timeout: Duration, mut op: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let started = Instant::now();
+    let mut pause = Duration::from_millis(50);
+    loop {
+        match op() {
+            Err(rusqlite::Error::SqliteFailure(error, _))
+                if matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) && started.elapsed() < timeout =>
+            {
+                thread::sleep(pause.min(timeout.saturating_sub(started.elapsed())));
+                pause = (pause * 2).min(Duration::from_secs(2));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Loads SQLite extensions (sqlite-vec, ICU, spellfix, ...) into the connection
+/// so its functions, collations and virtual tables are available during import.
+pub(crate) fn load_extensions(conn: &Connection, extensions: &[PathBuf]) -> Result<()> {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+
+    // SAFETY: loading an extension runs its native code; the user asked for these explicitly.
+    // The guard turns extension loading off again however this returns.
+    unsafe {
+        let _guard = LoadExtensionGuard::new(conn)?;
+        for path in extensions {
+            conn.load_extension(path, None)
+                .with_context(|| format!("Failed to load SQLite extension: {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Stores the profile in the metadata table, refusing to mix profiles in one database.
+fn record_profile(conn: &Connection, tables: &Tables, profile: SchemaProfile) -> Result<()> {
+    conn.execute(
+        &format!("INSERT OR IGNORE INTO {} (key, value) VALUES ('schema', ?1)", tables.metadata),
+        [profile.as_str()],
+    )?;
+    let existing: String = conn.query_row(
+        &format!("SELECT value FROM {} WHERE key = 'schema'", tables.metadata),
+        [],
+        |row| row.get(0),
+    )?;
+    if existing != profile.as_str() {
+        anyhow::bail!(
+            "Database was created with --schema {}; pass the same --schema to append to it",
+            existing
+        );
+    }
+    Ok(())
+}
+
+/// (Re)creates the convenience views: `v_senders` (per-sender counts and date
+/// ranges), `v_recent` (the latest 100 messages) and, when the threads table
+/// exists, `v_conversations` (one row per thread).
+pub fn create_views(tx: &Connection, tables: &Tables, profile: SchemaProfile) -> Result<()> {
+    tx.execute_batch(&format!(
+        "DROP VIEW IF EXISTS {senders};
+         CREATE VIEW {senders} AS
+         SELECT COALESCE(canonical_contact, from_addr) AS sender,
+                COUNT(*) AS emails,
+                MIN(date_parsed) AS first_email,
+                MAX(date_parsed) AS last_email
+         FROM {emails}
+         GROUP BY sender;
+
+         DROP VIEW IF EXISTS {recent};
+         CREATE VIEW {recent} AS
+         SELECT id, date_parsed, from_addr, subject, body_clean
+         FROM {emails}
+         WHERE date_parsed IS NOT NULL
+         ORDER BY date_parsed DESC
+         LIMIT 100;",
+        senders = tables.view("senders"),
+        recent = tables.view("recent"),
+        emails = tables.emails,
+    ))?;
+
+    if profile >= SchemaProfile::Normalized {
+        tx.execute_batch(&format!(
+            "DROP VIEW IF EXISTS {conversations};
+             CREATE VIEW {conversations} AS
+             SELECT t.thread_id,
+                    (SELECT e2.subject FROM {threads} t2 JOIN {emails} e2 ON e2.id = t2.email_id
+                     WHERE t2.thread_id = t.thread_id ORDER BY e2.date_parsed LIMIT 1) AS subject,
+                    COUNT(*) AS messages,
+                    COUNT(DISTINCT COALESCE(e.canonical_contact, e.from_addr)) AS participants,
+                    MIN(e.date_parsed) AS started,
+                    MAX(e.date_parsed) AS last_activity
+             FROM {threads} t
+             JOIN {emails} e ON e.id = t.email_id
+             GROUP BY t.thread_id;",
+            conversations = tables.view("conversations"),
+            threads = tables.threads,
+            emails = tables.emails,
+        ))?;
+    }
+    Ok(())
+}
+
+/// Returns true when `name` is a table in the database.
+pub fn table_exists(conn: &Connection, name: &str) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+/// (Re)builds the full-text index from the emails and attachment_text tables.
+/// The table is recreated each time so a new `tokenize` spec takes effect.
+pub fn build_fts_index(tx: &Connection, tables: &Tables, profile: SchemaProfile, tokenize: &str) -> Result<()> {
+    // Flat databases have no attachment_text table to pull from
+    let attachment_text = if profile >= SchemaProfile::Normalized {
+        format!(
+            "(SELECT group_concat(t.text, char(10)) FROM {} t WHERE t.email_id = e.id)",
+            tables.attachment_text
+        )
+    } else {
+        "NULL".to_string()
+    };
+    tx.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {fts};
+         CREATE VIRTUAL TABLE {fts} USING fts5(subject, from_addr, to_addr, body, attachments, tokenize = '{tokenize}');
+         INSERT INTO {fts} (rowid, subject, from_addr, to_addr, body, attachments)
+         SELECT e.id, e.subject, e.from_addr, e.to_addr, COALESCE(NULLIF(e.body_plain, ''), e.body_clean),
+                {attachment_text}
+         FROM {emails} e;",
+        fts = tables.emails_fts,
+        tokenize = tokenize,
+        attachment_text = attachment_text,
+        emails = tables.emails,
+    ))?;
+    tx.execute(
+        &format!("INSERT OR REPLACE INTO {} (key, value) VALUES ('fts_tokenizer', ?1)", tables.metadata),
+        [tokenize],
+    )?;
+    Ok(())
+}
+
+/// Returns true when `table` has a column called `column` (false for databases
+/// imported before the column was added).
+pub fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
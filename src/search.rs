@@ -0,0 +1,299 @@
+//! Keyword search behind the `search` subcommand: the FTS5 index when the
+//! database was imported with `--fts`, a LIKE scan of the emails table otherwise.
+
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::io::Write;
+
+use crate::schema::{self, Tables};
+use crate::text;
+
+static WORD: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w+").unwrap());
+
+/// Tokens of context FTS5 keeps around the matched terms in a snippet.
+const SNIPPET_TOKENS: usize = 16;
+/// Characters of context kept before the first match by the LIKE fallback.
+const SNIPPET_LEAD: usize = 40;
+/// Length of a LIKE-fallback snippet, in characters.
+const SNIPPET_CHARS: usize = 120;
+/// Trigram similarity (0 to 1) a word needs to count as a fuzzy match. The
+/// same default as PostgreSQL's pg_trgm; low enough for a swapped pair of
+/// letters in a short word ("reciept").
+const FUZZY_THRESHOLD: f64 = 0.3;
+
+/// Strings wrapped around the matched terms in a snippet, e.g. ANSI bold.
+#[derive(Clone, Copy)]
+pub struct Highlight<'a> {
+    pub start: &'a str,
+    pub end: &'a str,
+}
+
+pub struct SearchHit {
+    pub id: i64,
+    pub date: Option<String>,
+    pub from: String,
+    pub subject: String,
+    pub snippet: String,
+}
+
+/// Messages containing every word of `query`, best matches first.
+pub fn search(conn: &Connection, tables: &Tables, query: &str, limit: usize, highlight: Highlight) -> Result<Vec<SearchHit>> {
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    if terms.is_empty() {
+        bail!("The search query is empty");
+    }
+
+    if schema::table_exists(conn, &tables.emails_fts)? {
+        fts_search(conn, tables, &terms, limit, highlight)
+    } else {
+        like_search(conn, tables, &terms, limit, highlight)
+    }
+}
+
+fn fts_search(conn: &Connection, tables: &Tables, terms: &[&str], limit: usize, highlight: Highlight) -> Result<Vec<SearchHit>> {
+    // Each word as a quoted string, so punctuation and words like OR or NOT
+    // aren't read as FTS5 query syntax
+    let query = terms.iter().map(|term| format!("\"{}\"", term.replace('"', "\"\""))).collect::<Vec<_>>().join(" ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.id, e.date_parsed, COALESCE(e.from_addr, ''), COALESCE(e.subject, ''),
+                snippet({fts}, -1, ?2, ?3, '…', {tokens})
+         FROM {fts} JOIN {emails} e ON e.id = {fts}.rowid
+         WHERE {fts} MATCH ?1
+         ORDER BY rank
+         LIMIT ?4",
+        fts = tables.emails_fts,
+        emails = tables.emails,
+        tokens = SNIPPET_TOKENS,
+    ))?;
+    let hits = stmt
+        .query_map(rusqlite::params![query, highlight.start, highlight.end, limit as i64], |row| {
+            let snippet: String = row.get(4)?;
+            Ok(SearchHit {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                from: row.get(2)?,
+                subject: row.get(3)?,
+                snippet: collapse_whitespace(&snippet),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(hits)
+}
+
+/// Without an FTS index: every word must appear in the subject, sender or
+/// body. Messages with more of the words in their subject rank first, then
+/// the newest.
+fn like_search(conn: &Connection, tables: &Tables, terms: &[&str], limit: usize, highlight: Highlight) -> Result<Vec<SearchHit>> {
+    let body = "COALESCE(NULLIF(e.body_plain, ''), e.body_clean, '')";
+    let matches = (1..=terms.len())
+        .map(|i| format!("(e.subject LIKE ?{i} ESCAPE '\\' OR e.from_addr LIKE ?{i} ESCAPE '\\' OR {body} LIKE ?{i} ESCAPE '\\')"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let subject_score = (1..=terms.len()).map(|i| format!("(e.subject LIKE ?{i} ESCAPE '\\')")).collect::<Vec<_>>().join(" + ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.id, e.date_parsed, COALESCE(e.from_addr, ''), COALESCE(e.subject, ''), {body}
+         FROM {emails} e
+         WHERE {matches}
+         ORDER BY {subject_score} DESC, e.date_parsed DESC
+         LIMIT {limit}",
+        emails = tables.emails,
+        limit = limit,
+    ))?;
+
+    let patterns: Vec<String> = terms.iter().map(|term| format!("%{}%", escape_like(term))).collect();
+    let term_pattern = RegexBuilder::new(&terms.iter().map(|term| regex::escape(term)).collect::<Vec<_>>().join("|"))
+        .case_insensitive(true)
+        .build()?;
+    let hits = stmt
+        .query_map(rusqlite::params_from_iter(&patterns), |row| {
+            let body: String = row.get(4)?;
+            Ok(SearchHit {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                from: row.get(2)?,
+                subject: row.get(3)?,
+                snippet: snippet(&collapse_whitespace(&body), &term_pattern, highlight),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(hits)
+}
+
+/// Typo-tolerant search over names and subjects: every word of `query` has to
+/// be similar to some word of the subject, sender or recipients, measured by
+/// shared trigrams. Accents and case are ignored. Best average similarity first.
+///
+/// This reads every message, and bodies aren't searched; it's meant for
+/// "the message from Jon Jonson about the reciept", not for phrases.
+pub fn fuzzy_search(conn: &Connection, tables: &Tables, query: &str, limit: usize, highlight: Highlight) -> Result<Vec<SearchHit>> {
+    let terms: Vec<HashSet<String>> = WORD.find_iter(query).map(|word| trigrams(&text::fold(word.as_str()))).collect();
+    if terms.is_empty() {
+        bail!("The search query is empty");
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, date_parsed, COALESCE(from_addr, ''), COALESCE(subject, ''), COALESCE(to_addr, '') FROM {}",
+        tables.emails
+    ))?;
+    let mut rows = stmt.query([])?;
+    let mut scored = Vec::new();
+    while let Some(row) = rows.next()? {
+        let (from, subject, to): (String, String, String) = (row.get(2)?, row.get(3)?, row.get(4)?);
+        let fields = [("subject", &subject), ("from", &from), ("to", &to)];
+
+        // For each query word, its closest word in the message: (similarity, field, span)
+        let mut best: Vec<(f64, usize, std::ops::Range<usize>)> = vec![(0.0, 0, 0..0); terms.len()];
+        for (field, (_, value)) in fields.iter().enumerate() {
+            for word in WORD.find_iter(value) {
+                let word_trigrams = trigrams(&text::fold(word.as_str()));
+                for (term, best) in terms.iter().zip(best.iter_mut()) {
+                    let similarity = similarity(term, &word_trigrams);
+                    if similarity > best.0 {
+                        *best = (similarity, field, word.range());
+                    }
+                }
+            }
+        }
+        if best.iter().any(|(similarity, _, _)| *similarity < FUZZY_THRESHOLD) {
+            continue;
+        }
+
+        let score = best.iter().map(|(similarity, _, _)| similarity).sum::<f64>() / terms.len() as f64;
+        let snippet = fields
+            .iter()
+            .enumerate()
+            .filter_map(|(field, (name, value))| {
+                let mut spans: Vec<_> = best.iter().filter(|(_, f, _)| *f == field).map(|(_, _, span)| span.clone()).collect();
+                if spans.is_empty() {
+                    return None;
+                }
+                spans.sort_by_key(|span| span.start);
+                spans.dedup();
+                let mut marked = String::new();
+                let mut last = 0;
+                for span in spans {
+                    marked.push_str(&value[last..span.start]);
+                    marked.push_str(&format!("{}{}{}", highlight.start, &value[span.clone()], highlight.end));
+                    last = span.end;
+                }
+                marked.push_str(&value[last..]);
+                Some(format!("{}: {}", name, collapse_whitespace(&marked)))
+            })
+            .collect::<Vec<_>>()
+            .join(" · ");
+
+        scored.push((score, SearchHit { id: row.get(0)?, date: row.get(1)?, from, subject, snippet }));
+    }
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| b.1.date.cmp(&a.1.date)));
+    Ok(scored.into_iter().take(limit).map(|(_, hit)| hit).collect())
+}
+
+/// The word's three-character windows, padded like pg_trgm ("  w", " wo", ..., "rd ")
+/// so the start and end of a word weigh a little more.
+fn trigrams(word: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {} ", word).chars().collect();
+    padded.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// Shared trigrams over all trigrams of the two words (Jaccard similarity).
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let shared = a.intersection(b).count();
+    let total = a.len() + b.len() - shared;
+    if total == 0 {
+        0.0
+    } else {
+        shared as f64 / total as f64
+    }
+}
+
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// A window of `text` around the first match of `terms`, with every match in
+/// it highlighted. Falls back to the start of the text when nothing matches
+/// (the words were in the subject or sender).
+fn snippet(text: &str, terms: &Regex, highlight: Highlight) -> String {
+    let first = terms.find(text).map_or(0, |m| m.start());
+    let mut start = text[..first].char_indices().rev().nth(SNIPPET_LEAD - 1).map_or(0, |(i, _)| i);
+    // Start on a word rather than halfway through one (the text's first word is whole)
+    if start > 0 {
+        if let Some(space) = text[start..first].find(' ') {
+            start += space + 1;
+        }
+    }
+    let end = text[start..].char_indices().nth(SNIPPET_CHARS).map_or(text.len(), |(i, _)| start + i);
+
+    let window = terms.replace_all(&text[start..end], |caps: &regex::Captures| {
+        format!("{}{}{}", highlight.start, &caps[0], highlight.end)
+    });
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(&window);
+    if end < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn write_hits(out: &mut impl Write, hits: &[SearchHit]) -> Result<()> {
+    for hit in hits {
+        writeln!(out, "#{}  {}  {}", hit.id, hit.date.as_deref().unwrap_or("(no date)"), hit.from)?;
+        writeln!(out, "    {}", hit.subject)?;
+        if !hit.snippet.is_empty() {
+            writeln!(out, "    {}", hit.snippet)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRACKETS: Highlight = Highlight { start: "[", end: "]" };
+
+    fn terms(words: &str) -> Regex {
+        RegexBuilder::new(words).case_insensitive(true).build().unwrap()
+    }
+
+    #[test]
+    fn snippets() {
+        let filler = "lorem ipsum dolor sit amet ".repeat(8);
+        let late = format!("{filler}the invoice is attached");
+        let long = format!("Invoice attached. {filler}");
+        let cases = [
+            ("Quarterly numbers attached for review", "review", "Quarterly numbers attached for [review]"),
+            // Every match in the window, whatever its case
+            ("Invoice 12: see the INVOICE below", "invoice", "[Invoice] 12: see the [INVOICE] below"),
+            ("Budget and forecast", "forecast|budget", "[Budget] and [forecast]"),
+            // A match far in: about 40 characters before it, starting on a word
+            (late.as_str(), "invoice", "…amet lorem ipsum dolor sit amet the [invoice] is attached"),
+            // Cut off after 120 characters
+            (long.as_str(), "invoice", "[Invoice] attached. lorem ipsum dolor sit amet lorem ipsum dolor sit amet lorem ipsum dolor sit amet lorem ipsum dolor sit…"),
+            // No match in the body: its start
+            ("Nothing relevant here", "invoice", "Nothing relevant here"),
+            ("Café crème brûlée recette", "recette", "Café crème brûlée [recette]"),
+        ];
+        for (text, words, expected) in cases {
+            assert_eq!(snippet(text, &terms(words), BRACKETS), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn snippet_windows_fall_on_char_boundaries() {
+        let text = format!("{}needle", "é".repeat(60));
+        assert_eq!(snippet(&text, &terms("needle"), BRACKETS), format!("…{}[needle]", "é".repeat(40)));
+    }
+}
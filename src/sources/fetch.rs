@@ -0,0 +1,54 @@
+//! Plumbing shared by the network sources that fetch on tokio (IMAP, Graph).
+//!
+//! Downloads run concurrently on a background runtime; the SQLite writer stays
+//! on the calling thread and drains a bounded channel. When the writer falls
+//! behind, the channel fills up and the fetchers wait rather than buffering the
+//! whole mailbox in memory.
+
+use super::{InputDigest, RawMessage};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+/// Downloaded messages allowed to wait for the writer before fetchers block.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// A message handed from a fetch task to the writer.
+pub struct Fetched {
+    pub data: Vec<u8>,
+    pub folder: Option<String>,
+}
+
+pub type Sender = mpsc::Sender<Fetched>;
+
+pub fn runtime() -> Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")
+}
+
+/// Runs `fetch` on `runtime`, passing every message it sends to `sink` on the
+/// current thread, and returns once `fetch` has finished and the channel is empty.
+pub fn drain<F, Fut>(runtime: &Runtime, fetch: F, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest>
+where
+    F: FnOnce(Sender) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let task = runtime.spawn(fetch(sender));
+
+    let mut hasher = Sha256::new();
+    let mut bytes = 0u64;
+    // Returning early drops the receiver, which stops the fetchers at their next send
+    while let Some(message) = receiver.blocking_recv() {
+        hasher.update(&message.data);
+        bytes += message.data.len() as u64;
+        sink(RawMessage { data: &message.data, folder: message.folder.as_deref(), offset: None, skipped: &[] })?;
+    }
+    runtime.block_on(task).context("Fetch task panicked")??;
+
+    Ok(InputDigest { sha256: format!("{:x}", hasher.finalize()), bytes })
+}
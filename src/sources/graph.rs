@@ -0,0 +1,242 @@
+use super::fetch::{self, Fetched, Sender};
+use super::{InputDigest, MessageSource, RawMessage};
+use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
+use reqwest::StatusCode;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+const GRAPH_ROOT: &str = "https://graph.microsoft.com/v1.0";
+
+/// Message ids requested per page when listing a mailbox.
+const PAGE_SIZE: usize = 100;
+
+/// How often a throttled (429/503) request is retried before giving up.
+const MAX_RETRIES: u32 = 6;
+
+pub enum GraphAuth {
+    /// A ready-made access token, e.g. from `az account get-access-token`
+    AccessToken(String),
+    /// App-only access with an Azure AD app registration (client credentials flow)
+    ClientCredentials { tenant_id: String, client_id: String, client_secret: String },
+}
+
+/// Messages pulled from an Exchange Online mailbox through Microsoft Graph,
+/// downloaded in their original MIME form (`/messages/{id}/$value`). Up to
+/// `concurrency` messages download at once, across all folders.
+pub struct GraphSource {
+    runtime: Runtime,
+    client: Arc<Client>,
+    mailbox: Option<String>,
+    folders: Vec<String>,
+    concurrency: usize,
+}
+
+/// An authenticated HTTP client for one mailbox, shared by the download tasks.
+struct Client {
+    http: reqwest::Client,
+    token: String,
+    mailbox_url: String,
+    /// Once the import has started, where retry notices go
+    progress: OnceLock<ProgressBar>,
+}
+
+impl GraphSource {
+    /// Obtains a token (for client credentials) and checks it against the
+    /// mailbox, so bad credentials fail before the database is touched.
+    pub fn connect(auth: &GraphAuth, mailbox: Option<String>, folders: Vec<String>, concurrency: usize) -> Result<Self> {
+        let runtime = fetch::runtime()?;
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(Duration::from_secs(300))
+            .build()?;
+        let mailbox_url = match &mailbox {
+            Some(mailbox) => format!("{}/users/{}", GRAPH_ROOT, mailbox),
+            None => format!("{}/me", GRAPH_ROOT),
+        };
+
+        let client = runtime.block_on(async {
+            let token = match auth {
+                GraphAuth::AccessToken(token) => token.clone(),
+                GraphAuth::ClientCredentials { tenant_id, client_id, client_secret } => {
+                    let response = http
+                        .post(format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", tenant_id))
+                        .form(&[
+                            ("grant_type", "client_credentials"),
+                            ("client_id", client_id),
+                            ("client_secret", client_secret),
+                            ("scope", "https://graph.microsoft.com/.default"),
+                        ])
+                        .send()
+                        .await?;
+                    let response: serde_json::Value = check_status(response)
+                        .await
+                        .context("Failed to get a Microsoft Graph access token")?
+                        .json()
+                        .await?;
+                    response["access_token"]
+                        .as_str()
+                        .context("Token response had no access_token")?
+                        .to_string()
+                }
+            };
+
+            let client = Client { http, token, mailbox_url, progress: OnceLock::new() };
+            client
+                .get(&format!("{}/mailFolders/inbox?$select=id", client.mailbox_url))
+                .await
+                .context("Microsoft Graph rejected the credentials or mailbox")?;
+            anyhow::Ok(client)
+        })?;
+
+        Ok(Self { runtime, client: Arc::new(client), mailbox, folders, concurrency: concurrency.max(1) })
+    }
+}
+
+impl Client {
+    /// GET with the bearer token, waiting out throttling (429/503 with Retry-After).
+    async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            let response = self.http.get(url).bearer_auth(&self.token).send().await?;
+            let status = response.status();
+            if (status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE) && attempt < MAX_RETRIES {
+                let wait = response
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|seconds| seconds.to_str().ok()?.parse().ok())
+                    .unwrap_or(2u64.pow(attempt));
+                let notice = format!("Microsoft Graph returned {}; retrying in {}s", status.as_u16(), wait);
+                match self.progress.get() {
+                    Some(progress) => progress.println(notice),
+                    // Still connecting, before there's a progress line
+                    None => eprintln!("{}", notice),
+                }
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                attempt += 1;
+                continue;
+            }
+            return check_status(response).await;
+        }
+    }
+
+    /// Ids of every message in a folder (or the whole mailbox), following @odata.nextLink.
+    async fn message_ids(&self, folder: Option<&str>) -> Result<Vec<String>> {
+        let mut url = match folder {
+            Some(folder) => format!("{}/mailFolders/{}/messages", self.mailbox_url, folder),
+            None => format!("{}/messages", self.mailbox_url),
+        };
+        url.push_str(&format!("?$select=id&$top={}", PAGE_SIZE));
+
+        let mut ids = Vec::new();
+        loop {
+            let page: serde_json::Value = self.get(&url).await?.json().await?;
+            for message in page["value"].as_array().into_iter().flatten() {
+                if let Some(id) = message["id"].as_str() {
+                    ids.push(id.to_string());
+                }
+            }
+            match page["@odata.nextLink"].as_str() {
+                // The link carries our token along, so it must point back at Graph
+                Some(next) if next.starts_with(GRAPH_ROOT) => url = next.to_string(),
+                Some(next) => bail!("Unexpected paging link from Microsoft Graph: {}", next),
+                None => return Ok(ids),
+            }
+        }
+    }
+
+    async fn download(&self, id: &str) -> Result<Vec<u8>> {
+        let response = self.get(&format!("{}/messages/{}/$value", self.mailbox_url, id)).await?;
+        let message = response.bytes().await.with_context(|| format!("Failed to download message {}", id))?;
+        Ok(message.to_vec())
+    }
+}
+
+impl MessageSource for GraphSource {
+    fn describe(&self) -> String {
+        let mailbox = self.mailbox.as_deref().unwrap_or("me");
+        if self.folders.is_empty() {
+            format!("graph://{}", mailbox)
+        } else {
+            format!("graph://{}/{}", mailbox, self.folders.join(","))
+        }
+    }
+
+    fn set_progress(&mut self, progress: &ProgressBar) {
+        let _ = self.client.progress.set(progress.clone());
+    }
+
+    fn read_messages(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest> {
+        let client = self.client.clone();
+        let folders = if self.folders.is_empty() {
+            vec![None]
+        } else {
+            self.folders.iter().cloned().map(Some).collect()
+        };
+        let concurrency = self.concurrency;
+        fetch::drain(&self.runtime, |sender| fetch_folders(client, folders, concurrency, sender), sink)
+    }
+}
+
+/// Lists the folders one after another while their messages download in the
+/// background, at most `concurrency` at a time.
+async fn fetch_folders(client: Arc<Client>, folders: Vec<Option<String>>, concurrency: usize, sender: Sender) -> Result<()> {
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut downloads = JoinSet::new();
+    for folder in folders {
+        let label = folder.as_deref().map(folder_label).map(String::from);
+        for id in client.message_ids(folder.as_deref()).await? {
+            let permit = permits.clone().acquire_owned().await?;
+            // Surface failures as they happen instead of after the whole listing
+            while let Some(result) = downloads.try_join_next() {
+                result??;
+            }
+            let (client, sender, label) = (client.clone(), sender.clone(), label.clone());
+            downloads.spawn(async move {
+                let data = client.download(&id).await?;
+                // A closed channel means the writer stopped; its error is the one reported
+                let _ = sender.send(Fetched { data, folder: label }).await;
+                // Held until the writer has room, so at most `concurrency` messages wait in memory
+                drop(permit);
+                anyhow::Ok(())
+            });
+        }
+    }
+    while let Some(result) = downloads.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Label recorded for a folder; Exchange's junk and deleted folders map onto the
+/// Spam/Trash labels so the usual filtering applies.
+fn folder_label(folder: &str) -> &str {
+    match folder.to_lowercase().as_str() {
+        "junkemail" => "Spam",
+        "deleteditems" => "Trash",
+        _ => folder,
+    }
+}
+
+/// Turns an error status into an error carrying Graph's JSON error message,
+/// which says far more than the status code.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|json| {
+            json["error"]["message"]
+                .as_str()
+                .or_else(|| json["error_description"].as_str())
+                .map(String::from)
+        })
+        .unwrap_or(body);
+    bail!("HTTP {}: {}", status.as_u16(), message)
+}
@@ -0,0 +1,234 @@
+use super::fetch::{self, Fetched, Sender};
+use super::tls::{self, AsyncTlsStream, NETWORK_TIMEOUT};
+use super::{InputDigest, MessageSource, RawMessage};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Messages requested per UID FETCH; large enough to amortize round trips,
+/// small enough that a batch of big messages stays comfortably in memory.
+const FETCH_BATCH: usize = 100;
+
+static LITERAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(\d+)\}\r?\n$").unwrap());
+
+pub enum ImapAuth {
+    Password(String),
+    /// SASL XOAUTH2 with an access token (Gmail, Outlook.com, Microsoft 365)
+    OAuth2(String),
+}
+
+/// Where and how to log in; every folder worker opens its own connection.
+struct Login {
+    server: String,
+    port: u16,
+    username: String,
+    auth: ImapAuth,
+}
+
+/// Messages fetched over IMAPS. Folders are opened read-only (EXAMINE) and
+/// bodies fetched with BODY.PEEK, so nothing on the server changes, not even
+/// \Seen flags. Up to `concurrency` folders download at once, each over its
+/// own connection.
+pub struct ImapSource {
+    runtime: Runtime,
+    login: Arc<Login>,
+    folders: Vec<String>,
+    concurrency: usize,
+}
+
+impl ImapSource {
+    /// Connects and logs in, so bad credentials fail before the database is touched.
+    pub fn connect(
+        server: &str,
+        port: u16,
+        username: &str,
+        auth: ImapAuth,
+        folders: Vec<String>,
+        concurrency: usize,
+    ) -> Result<Self> {
+        let login = Arc::new(Login {
+            server: server.to_string(),
+            port,
+            username: username.to_string(),
+            auth,
+        });
+        let runtime = fetch::runtime()?;
+        runtime.block_on(async {
+            let mut session = Session::login(&login).await?;
+            // The check is done; workers log in again with their own sessions
+            let _ = session.command("LOGOUT").await;
+            anyhow::Ok(())
+        })?;
+
+        Ok(Self { runtime, login, folders, concurrency: concurrency.max(1) })
+    }
+}
+
+impl MessageSource for ImapSource {
+    fn describe(&self) -> String {
+        format!("imaps://{}@{}/{}", self.login.username, self.login.server, self.folders.join(","))
+    }
+
+    fn read_messages(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest> {
+        let login = self.login.clone();
+        let folders = self.folders.clone();
+        let concurrency = self.concurrency;
+        fetch::drain(&self.runtime, |sender| fetch_folders(login, folders, concurrency, sender), sink)
+    }
+}
+
+async fn fetch_folders(login: Arc<Login>, folders: Vec<String>, concurrency: usize, sender: Sender) -> Result<()> {
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut workers = JoinSet::new();
+    for folder in folders {
+        let (login, permits, sender) = (login.clone(), permits.clone(), sender.clone());
+        workers.spawn(async move {
+            let _permit = permits.acquire_owned().await?;
+            fetch_folder(&login, &folder, &sender)
+                .await
+                .with_context(|| format!("Failed to fetch IMAP folder {}", folder))
+        });
+    }
+    // The first failure aborts the remaining workers when the set is dropped
+    while let Some(result) = workers.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+async fn fetch_folder(login: &Login, folder: &str, sender: &Sender) -> Result<()> {
+    let mut session = Session::login(login).await?;
+    session.command(&format!("EXAMINE {}", quote(folder))).await?;
+
+    let search = session.command("UID SEARCH ALL").await?;
+    let uids: Vec<String> = search
+        .lines
+        .iter()
+        .filter_map(|line| line.strip_prefix("* SEARCH"))
+        .flat_map(|rest| rest.split_whitespace().map(String::from))
+        .collect();
+
+    for batch in uids.chunks(FETCH_BATCH) {
+        let response = session.command(&format!("UID FETCH {} (BODY.PEEK[])", batch.join(","))).await?;
+        for data in response.literals {
+            let message = Fetched { data, folder: Some(folder.to_string()) };
+            if sender.send(message).await.is_err() {
+                // The writer has stopped; its error is the one reported
+                return Ok(());
+            }
+        }
+    }
+
+    let _ = session.command("LOGOUT").await;
+    Ok(())
+}
+
+/// Untagged lines and literals (message bodies) returned for one command.
+struct Response {
+    lines: Vec<String>,
+    literals: Vec<Vec<u8>>,
+}
+
+/// A minimal async IMAP4rev1 client: just enough to log in, open a folder,
+/// search and fetch.
+struct Session {
+    stream: BufReader<AsyncTlsStream>,
+    next_tag: u32,
+}
+
+impl Session {
+    async fn login(login: &Login) -> Result<Self> {
+        let mut session = Self {
+            stream: BufReader::new(tls::connect_async(&login.server, login.port).await?),
+            next_tag: 1,
+        };
+        let greeting = session.read_line().await?;
+        let greeting = String::from_utf8_lossy(&greeting);
+        if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+            bail!("Unexpected IMAP greeting from {}: {}", login.server, greeting.trim_end());
+        }
+
+        match &login.auth {
+            ImapAuth::Password(password) => {
+                session.command(&format!("LOGIN {} {}", quote(&login.username), quote(password))).await
+            }
+            ImapAuth::OAuth2(token) => {
+                let sasl = format!("user={}\x01auth=Bearer {}\x01\x01", login.username, token);
+                let encoded = base64::engine::general_purpose::STANDARD.encode(sasl);
+                session.command(&format!("AUTHENTICATE XOAUTH2 {}", encoded)).await
+            }
+        }
+        .with_context(|| format!("IMAP login failed for {}", login.username))?;
+        Ok(session)
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        tokio::time::timeout(NETWORK_TIMEOUT, self.stream.read_until(b'\n', &mut line))
+            .await
+            .context("IMAP server stopped responding")??;
+        if line.is_empty() {
+            bail!("IMAP server closed the connection");
+        }
+        Ok(line)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(data).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Sends one command and reads until its tagged completion, failing on NO/BAD.
+    async fn command(&mut self, command: &str) -> Result<Response> {
+        let tag = format!("A{:04}", self.next_tag);
+        self.next_tag += 1;
+        self.write(format!("{} {}\r\n", tag, command).as_bytes()).await?;
+
+        let mut response = Response { lines: Vec::new(), literals: Vec::new() };
+        loop {
+            let line = self.read_line().await?;
+            let text = String::from_utf8_lossy(&line).into_owned();
+
+            if let Some(caps) = LITERAL.captures(&text) {
+                let size: usize = caps[1].parse()?;
+                let mut literal = vec![0; size];
+                tokio::time::timeout(NETWORK_TIMEOUT, self.stream.read_exact(&mut literal))
+                    .await
+                    .context("IMAP server stopped responding")??;
+                response.literals.push(literal);
+                // The rest of the response line (e.g. " UID 42)") follows the literal
+                let rest = self.read_line().await?;
+                response.lines.push(format!("{}{}", text.trim_end(), String::from_utf8_lossy(&rest).trim_end()));
+                continue;
+            }
+
+            if text.starts_with("+ ") || text.trim_end() == "+" {
+                // A continuation here means the server rejected SASL and wants an empty reply
+                self.write(b"\r\n").await?;
+                continue;
+            }
+
+            if let Some(status) = text.strip_prefix(&format!("{} ", tag)) {
+                if status.starts_with("OK") {
+                    return Ok(response);
+                }
+                bail!("IMAP server replied: {}", status.trim_end());
+            }
+
+            response.lines.push(text.trim_end().to_string());
+        }
+    }
+}
+
+/// Quotes a string for use as an IMAP command argument.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
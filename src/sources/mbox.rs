@@ -0,0 +1,432 @@
+use super::{InputDigest, MessageSource, RawMessage};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Size of the reads from an mbox file.
+const READ_CHUNK: usize = 64 * 1024;
+/// How much of a skipped prefix is shown in the warning about it.
+const SKIPPED_PREVIEW_CHARS: usize = 60;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// "Name: value", the first line of a message without a "From " line.
+static HEADER_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[!-9;-~]+:").unwrap());
+
+/// Messages from one or more mbox files, split on "From " lines and read
+/// one file after another.
+pub struct MboxSource {
+    paths: Vec<PathBuf>,
+    files: Vec<File>,
+}
+
+impl MboxSource {
+    /// Opens the file up front so a bad path fails before the database is touched.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_all(&[path.to_path_buf()])
+    }
+
+    /// Opens every file, in the order they'll be read. Each message from one
+    /// of several files has the file's name (less `.mbox`) as its folder, the
+    /// label it stands for in a per-label Takeout export.
+    pub fn open_all(paths: &[PathBuf]) -> Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| File::open(path).with_context(|| format!("Failed to open input file: {}", path.display())))
+            .collect::<Result<_>>()?;
+        Ok(Self { paths: paths.to_vec(), files })
+    }
+}
+
+impl MessageSource for MboxSource {
+    fn describe(&self) -> String {
+        self.paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    fn input_bytes(&self) -> Option<u64> {
+        self.paths.iter().map(|path| std::fs::metadata(path).ok().map(|metadata| metadata.len())).sum()
+    }
+
+    fn has_copies(&self) -> bool {
+        self.paths.len() > 1
+    }
+
+    /// Counts "From " lines in a quick byte scan, then rewinds for the real read.
+    fn count_messages(&mut self) -> Result<Option<u64>> {
+        if self.files.is_empty() {
+            anyhow::bail!("mbox file was already read");
+        }
+        let mut count = 0;
+        for file in &mut self.files {
+            count += count_from_lines(file)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        Ok(Some(count))
+    }
+
+    fn read_messages(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest> {
+        if self.files.is_empty() {
+            anyhow::bail!("mbox file was already read");
+        }
+        let files = std::mem::take(&mut self.files);
+        let several = files.len() > 1;
+        let mut stream = MboxStream::default();
+        let mut buffer = vec![0; READ_CHUNK];
+        for (path, mut file) in self.paths.iter().zip(files) {
+            let label = path.file_stem().map(|stem| stem.to_string_lossy()).filter(|_| several);
+            let mut sink = |message: RawMessage| sink(RawMessage { folder: label.as_deref(), ..message });
+            loop {
+                let n = file.read(&mut buffer)?;
+                if n == 0 {
+                    break;
+                }
+                stream.feed(&buffer[..n], &mut sink)?;
+            }
+            stream.end_file(&mut sink)?;
+        }
+        stream.finish(sink)
+    }
+}
+
+/// The number of lines starting with "From " (after LF, CRLF or CR, or a
+/// byte order mark), which is the number of messages `MboxStream` finds
+/// unless something precedes the first one.
+fn count_from_lines(reader: &mut impl Read) -> Result<u64> {
+    const FROM: &[u8] = b"From ";
+    let mut count = 0;
+    // Bytes of "From " matched at the start of the current line; past the
+    // end of it once the line can't be (or already was) a match
+    let mut matched = 0;
+    let mut first_chunk = true;
+    let mut buffer = vec![0; READ_CHUNK];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(count);
+        }
+        let mut chunk = &buffer[..n];
+        if first_chunk {
+            first_chunk = false;
+            chunk = chunk.strip_prefix(UTF8_BOM).unwrap_or(chunk);
+        }
+        for &byte in chunk {
+            if byte == b'\n' || byte == b'\r' {
+                matched = 0;
+            } else if matched < FROM.len() {
+                matched = if byte == FROM[matched] { matched + 1 } else { FROM.len() + 1 };
+                if matched == FROM.len() {
+                    count += 1;
+                }
+            }
+        }
+    }
+}
+
+/// An mbox split into messages as it arrives in arbitrary chunks, from a file
+/// or from the C API's feed call.
+///
+/// Lines may end in LF, CRLF or a bare CR (old Mac tools, broken MTAs), mixed
+/// freely within one file; every message is passed on with LF line endings.
+///
+/// A UTF-8 byte order mark, and anything before the first "From " line that
+/// isn't a message's headers (a stray banner, a truncated message), is
+/// skipped and passed on with the next message for the importer to warn
+/// about. Input with no "From " line at all is passed on whole as one message.
+#[derive(Default)]
+pub struct MboxStream {
+    line: Vec<u8>,
+    current_email: Vec<u8>,
+    /// The first line has ended (a byte order mark can only come before it).
+    past_first_line: bool,
+    /// The first "From " line has been seen.
+    started: bool,
+    /// The last chunk ended in CR, so an LF at the start of the next one
+    /// completes that CRLF rather than ending an empty line.
+    after_cr: bool,
+    hasher: Sha256,
+    bytes: u64,
+    /// Input offsets where `line` and `current_email` start
+    line_start: u64,
+    email_start: u64,
+    /// Input offset of the current file, when there are several
+    file_start: u64,
+    /// Dropped since the last message was passed on
+    skipped: Vec<Skipped>,
+}
+
+/// Input an mbox had that isn't part of any message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Skipped {
+    ByteOrderMark,
+    /// Bytes before the first "From " line, and the start of them as text
+    Prefix { bytes: usize, preview: String },
+}
+
+impl MboxStream {
+    /// Passes every message completed by `chunk` to `sink`.
+    pub fn feed(&mut self, chunk: &[u8], sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<()> {
+        self.hasher.update(chunk);
+        let mut position = self.bytes;
+        self.bytes += chunk.len() as u64;
+
+        let mut rest = chunk;
+        if self.after_cr && rest.first() == Some(&b'\n') {
+            rest = &rest[1..];
+            position += 1;
+            self.line_start = position;
+        }
+        self.after_cr = false;
+
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n' || byte == b'\r') {
+            self.line.extend_from_slice(&rest[..end]);
+            self.end_line(sink)?;
+            let crlf = rest[end] == b'\r' && rest.get(end + 1) == Some(&b'\n');
+            if rest[end] == b'\r' && end + 1 == rest.len() {
+                self.after_cr = true;
+            }
+            let consumed = end + if crlf { 2 } else { 1 };
+            rest = &rest[consumed..];
+            position += consumed as u64;
+            self.line_start = position;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(())
+    }
+
+    /// Passes on the final message once the input has ended.
+    pub fn finish(mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest> {
+        self.end_file(sink)?;
+        Ok(InputDigest { sha256: format!("{:x}", self.hasher.finalize()), bytes: self.bytes })
+    }
+
+    /// Passes on the final message of one file, so the next starts afresh
+    /// (the digest covers them all).
+    pub fn end_file(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<()> {
+        if !self.line.is_empty() {
+            self.end_line(sink)?;
+        }
+        if !self.current_email.is_empty() {
+            self.pass_on(sink)?;
+        }
+        *self = Self {
+            hasher: std::mem::take(&mut self.hasher),
+            bytes: self.bytes,
+            line_start: self.bytes,
+            file_start: self.bytes,
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    fn end_line(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<()> {
+        if !self.past_first_line {
+            self.past_first_line = true;
+            if self.line.starts_with(UTF8_BOM) {
+                self.line.drain(..UTF8_BOM.len());
+                self.line_start += UTF8_BOM.len() as u64;
+                self.skipped.push(Skipped::ByteOrderMark);
+            }
+        }
+        if !self.started && self.line.starts_with(b"From ") {
+            self.started = true;
+            self.skip_prefix();
+        }
+
+        if self.line.starts_with(b"From ") && !self.current_email.is_empty() {
+            self.pass_on(sink)?;
+            self.current_email.clear();
+        }
+        if self.current_email.is_empty() {
+            self.email_start = self.line_start;
+        }
+        self.current_email.append(&mut self.line);
+        self.current_email.push(b'\n');
+        Ok(())
+    }
+
+    /// Drops what came before the first "From " line, unless it's the headers
+    /// of a message that simply lacks a "From " line.
+    fn skip_prefix(&mut self) {
+        let prefix = String::from_utf8_lossy(&self.current_email);
+        let text = prefix.trim();
+        if text.is_empty() {
+            self.current_email.clear();
+            return;
+        }
+        if HEADER_LINE.is_match(text) {
+            return;
+        }
+
+        let mut preview: String = text.chars().take(SKIPPED_PREVIEW_CHARS).collect();
+        if preview.len() < text.len() {
+            preview.push('…');
+        }
+        self.skipped.push(Skipped::Prefix { bytes: self.current_email.len(), preview });
+        self.current_email.clear();
+    }
+
+    fn pass_on(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<()> {
+        let offset = Some(self.email_start - self.file_start);
+        sink(RawMessage { data: &self.current_email, folder: None, offset, skipped: &self.skipped })?;
+        self.skipped.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The messages `chunks` splits into, fed one chunk at a time.
+    fn split(chunks: &[&[u8]]) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut sink = |message: RawMessage| {
+            messages.push(String::from_utf8(message.data.to_vec()).unwrap());
+            Ok(())
+        };
+        let mut stream = MboxStream::default();
+        for chunk in chunks {
+            stream.feed(chunk, &mut sink).unwrap();
+        }
+        stream.finish(&mut sink).unwrap();
+        messages
+    }
+
+    const MIXED: &[u8] = b"From a@example.com Mon Jan  1 00:00:00 2024\r\nSubject: one\r\n\r\nfirst\r\n\
+        From b@example.com Mon Jan  1 00:00:00 2024\rSubject: two\r\rsecond\r\
+        From c@example.com Mon Jan  1 00:00:00 2024\nSubject: three\n\nthird\r\n";
+
+    #[test]
+    fn mixed_line_endings_become_lf() {
+        assert_eq!(
+            split(&[MIXED]),
+            [
+                "From a@example.com Mon Jan  1 00:00:00 2024\nSubject: one\n\nfirst\n",
+                "From b@example.com Mon Jan  1 00:00:00 2024\nSubject: two\n\nsecond\n",
+                "From c@example.com Mon Jan  1 00:00:00 2024\nSubject: three\n\nthird\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn chunk_boundaries_dont_matter() {
+        let whole = split(&[MIXED]);
+        for at in 1..MIXED.len() {
+            assert_eq!(split(&[&MIXED[..at], &MIXED[at..]]), whole, "split at byte {}", at);
+        }
+        let bytes: Vec<&[u8]> = MIXED.chunks(1).collect();
+        assert_eq!(split(&bytes), whole);
+    }
+
+    #[test]
+    fn crlf_split_between_chunks_is_one_line_end() {
+        assert_eq!(split(&[b"From x\r", b"\nSubject: s\r", b"\n\r", b"\nbody"]), ["From x\nSubject: s\n\nbody\n"]);
+    }
+
+    #[test]
+    fn blank_lines_in_cr_files_are_kept() {
+        assert_eq!(split(&[b"From x\rSubject: s\r\rpara one\r\r\rpara two\r"]), ["From x\nSubject: s\n\npara one\n\n\npara two\n"]);
+    }
+
+    #[test]
+    fn headers_parse_after_normalizing() {
+        for (message, subject) in split(&[MIXED]).iter().zip(["one", "two", "three"]) {
+            let (_, rfc822) = message.split_once('\n').unwrap();
+            let parsed = mailparse::parse_mail(rfc822.as_bytes()).unwrap();
+            assert_eq!(parsed.headers.len(), 1, "{:?}", message);
+            assert_eq!(parsed.headers[0].get_value(), subject);
+            assert!(!parsed.get_body().unwrap().contains('\r'));
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_and_leading_garbage_are_skipped() {
+        assert_eq!(split(&[b"\xEF\xBB\xBFFrom x\nSubject: s\n\nbody\n"]), ["From x\nSubject: s\n\nbody\n"]);
+        assert_eq!(split(&[b"\xEF", b"\xBB\xBF\r\n\r\nFrom x\nSubject: s\n"]), ["From x\nSubject: s\n"]);
+        assert_eq!(
+            split(&[b"exported by MailTool 2.1\n\x00\x00 junk\nFrom x\nSubject: s\n\nbody\n"]),
+            ["From x\nSubject: s\n\nbody\n"]
+        );
+    }
+
+    #[test]
+    fn skipped_input_comes_with_the_next_message() {
+        let mut skipped = Vec::new();
+        let mut sink = |message: RawMessage| {
+            skipped.push(message.skipped.to_vec());
+            Ok(())
+        };
+        let mut stream = MboxStream::default();
+        stream.feed(b"\xEF\xBB\xBFbanner\nFrom x\nSubject: s\n\nFrom y\nSubject: t\n", &mut sink).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(
+            skipped,
+            [vec![Skipped::ByteOrderMark, Skipped::Prefix { bytes: 7, preview: "banner".to_string() }], vec![]]
+        );
+    }
+
+    #[test]
+    fn messages_without_from_lines_are_kept() {
+        assert_eq!(split(&[b"Subject: lone\n\nbody\n"]), ["Subject: lone\n\nbody\n"]);
+        assert_eq!(
+            split(&[b"Subject: first\n\nbody\nFrom x\nSubject: second\n"]),
+            ["Subject: first\n\nbody\n", "From x\nSubject: second\n"]
+        );
+    }
+
+    /// Where each message `chunks` splits into starts in the input.
+    fn offsets(chunks: &[&[u8]]) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        let mut sink = |message: RawMessage| {
+            offsets.push(message.offset.unwrap());
+            Ok(())
+        };
+        let mut stream = MboxStream::default();
+        for chunk in chunks {
+            stream.feed(chunk, &mut sink).unwrap();
+        }
+        stream.finish(&mut sink).unwrap();
+        offsets
+    }
+
+    #[test]
+    fn offsets_point_at_from_lines() {
+        let from_lines: Vec<u64> = (0..MIXED.len()).filter(|&at| MIXED[at..].starts_with(b"From ")).map(|at| at as u64).collect();
+        assert_eq!(offsets(&[MIXED]), from_lines);
+        for at in 1..MIXED.len() {
+            assert_eq!(offsets(&[&MIXED[..at], &MIXED[at..]]), from_lines, "split at byte {}", at);
+        }
+        assert_eq!(offsets(&[b"\xEF\xBB\xBFFrom x\n"]), [3]);
+        assert_eq!(offsets(&[b"junk\r\n\r\nFrom x\nSubject: s\n"]), [8]);
+    }
+
+    #[test]
+    fn from_line_count_matches_split() {
+        assert_eq!(count_from_lines(&mut &MIXED[..]).unwrap(), split(&[MIXED]).len() as u64);
+        let tricky = b"\xEF\xBB\xBFFrom a\n\nsee From here\n>From quoted\nFrom\n\rFrom b\r\nFrom c";
+        assert_eq!(count_from_lines(&mut &tricky[..]).unwrap(), 3);
+    }
+
+    #[test]
+    fn file_source_matches_stream() {
+        let path = std::env::temp_dir().join(format!("mbox2db-mixed-{}.mbox", std::process::id()));
+        std::fs::write(&path, MIXED).unwrap();
+        let mut source = MboxSource::open(&path).unwrap();
+        let mut messages = Vec::new();
+        let digest = source
+            .read_messages(&mut |message| {
+                messages.push(String::from_utf8(message.data.to_vec()).unwrap());
+                Ok(())
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(messages, split(&[MIXED]));
+        assert_eq!(digest.bytes, MIXED.len() as u64);
+        assert_eq!(digest.sha256, format!("{:x}", Sha256::digest(MIXED)));
+    }
+}
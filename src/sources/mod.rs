@@ -0,0 +1,77 @@
+//! Where raw messages come from: an mbox file or, with the `imap`/`pop3`/`graph`
+//! features, a mail server. Every source feeds the same import pipeline.
+
+#[cfg(any(feature = "imap", feature = "graph"))]
+mod fetch;
+#[cfg(feature = "graph")]
+mod graph;
+#[cfg(feature = "imap")]
+mod imap;
+mod mbox;
+#[cfg(feature = "pop3")]
+mod pop3;
+#[cfg(any(feature = "imap", feature = "pop3"))]
+mod tls;
+
+#[cfg(feature = "graph")]
+pub use graph::{GraphAuth, GraphSource};
+#[cfg(feature = "imap")]
+pub use imap::{ImapAuth, ImapSource};
+pub use mbox::{MboxSource, MboxStream, Skipped};
+#[cfg(feature = "pop3")]
+pub use pop3::Pop3Source;
+
+use anyhow::Result;
+use indicatif::ProgressBar;
+
+/// One raw RFC 5322 message, plus the folder it was fetched from for sources
+/// that have folders.
+pub struct RawMessage<'a> {
+    pub data: &'a [u8],
+    pub folder: Option<&'a str>,
+    /// Where the message starts in an mbox, in bytes, for pointing at it in warnings
+    pub offset: Option<u64>,
+    /// What an mbox had before the message that isn't part of any, to warn about
+    pub skipped: &'a [Skipped],
+}
+
+/// SHA-256 and size of everything a source read, recorded in import_runs.
+pub struct InputDigest {
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+pub trait MessageSource {
+    /// Human-readable location of the input, stored as import_runs.input_path.
+    fn describe(&self) -> String;
+
+    /// Size of the input in bytes, when known before reading it, for the
+    /// free disk space check.
+    fn input_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    /// How many messages `read_messages` will pass on, found without parsing
+    /// them (for --two-pass); None when the source can't tell cheaply.
+    fn count_messages(&mut self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Whether one message can come from several of the source's inputs (the
+    /// per-label mbox files of a Takeout), to be imported once.
+    fn has_copies(&self) -> bool {
+        false
+    }
+
+    /// The import's progress line, for notices printed while reading so they
+    /// don't break it up.
+    fn set_progress(&mut self, _progress: &ProgressBar) {}
+
+    /// Passes every message to `sink` in order, stopping at the first error.
+    fn read_messages(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest>;
+
+    /// Called once the imported messages have been committed to the database.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use super::tls::{self, TlsStream};
+use super::{InputDigest, MessageSource, RawMessage};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+
+/// Messages downloaded from a POP3 mailbox over TLS (POP3S).
+///
+/// Messages are left on the server unless `delete` is set. Even then they're
+/// only marked with DELE while downloading; the server removes them at QUIT,
+/// which is sent after the import has been committed. If the import fails the
+/// connection is dropped without QUIT and nothing is deleted.
+pub struct Pop3Source {
+    server: String,
+    username: String,
+    delete: bool,
+    stream: BufReader<TlsStream>,
+}
+
+impl Pop3Source {
+    /// Connects and logs in, so bad credentials fail before the database is touched.
+    pub fn connect(server: &str, port: u16, username: &str, password: &str, delete: bool) -> Result<Self> {
+        let mut source = Self {
+            server: server.to_string(),
+            username: username.to_string(),
+            delete,
+            stream: BufReader::new(tls::connect(server, port)?),
+        };
+        source.read_status().context("Unexpected POP3 greeting")?;
+        source.command(&format!("USER {}", username))?;
+        source
+            .command(&format!("PASS {}", password))
+            .with_context(|| format!("POP3 login failed for {}", username))?;
+        Ok(source)
+    }
+
+    /// Reads a single-line response, failing on -ERR.
+    fn read_status(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        self.stream.read_until(b'\n', &mut line)?;
+        if line.is_empty() {
+            bail!("POP3 server closed the connection");
+        }
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        match line.strip_prefix("+OK") {
+            Some(rest) => Ok(rest.trim().to_string()),
+            None => bail!("POP3 server replied: {}", line),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", command).as_bytes())?;
+        stream.flush()?;
+        self.read_status()
+    }
+
+    /// Reads a multi-line response body up to the terminating ".", undoing dot-stuffing.
+    fn read_multiline(&mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            self.stream.read_until(b'\n', &mut line)?;
+            if line.is_empty() {
+                bail!("POP3 server closed the connection mid-message");
+            }
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(body);
+            }
+            let line = line.strip_prefix(b".").unwrap_or(&line);
+            body.extend_from_slice(line);
+        }
+    }
+}
+
+impl MessageSource for Pop3Source {
+    fn describe(&self) -> String {
+        format!("pop3s://{}@{}", self.username, self.server)
+    }
+
+    fn read_messages(&mut self, sink: &mut dyn FnMut(RawMessage) -> Result<()>) -> Result<InputDigest> {
+        let stat = self.command("STAT")?;
+        let count: usize = stat
+            .split_whitespace()
+            .next()
+            .and_then(|count| count.parse().ok())
+            .with_context(|| format!("Unexpected STAT response: {}", stat))?;
+
+        let mut hasher = Sha256::new();
+        let mut bytes = 0u64;
+        for number in 1..=count {
+            self.command(&format!("RETR {}", number))?;
+            let message = self.read_multiline()?;
+            hasher.update(&message);
+            bytes += message.len() as u64;
+            sink(RawMessage { data: &message, folder: None, offset: None, skipped: &[] })?;
+
+            if self.delete {
+                self.command(&format!("DELE {}", number))?;
+            }
+        }
+
+        Ok(InputDigest { sha256: format!("{:x}", hasher.finalize()), bytes })
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // QUIT is what makes the server apply the DELE marks
+        self.command("QUIT").context("POP3 QUIT failed; messages were not deleted from the server")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const NETWORK_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[cfg(feature = "pop3")]
+pub type TlsStream = rustls::StreamOwned<rustls::ClientConnection, std::net::TcpStream>;
+
+#[cfg(feature = "imap")]
+pub type AsyncTlsStream = tokio_rustls::client::TlsStream<tokio::net::TcpStream>;
+
+/// Client config verifying servers against the bundled Mozilla root certificates.
+fn client_config() -> Result<Arc<rustls::ClientConfig>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn server_name(server: &str) -> Result<ServerName<'static>> {
+    ServerName::try_from(server.to_string()).with_context(|| format!("Invalid server name: {}", server))
+}
+
+/// Opens a blocking TLS connection.
+#[cfg(feature = "pop3")]
+pub fn connect(server: &str, port: u16) -> Result<TlsStream> {
+    let tcp = std::net::TcpStream::connect((server, port))
+        .with_context(|| format!("Failed to connect to {}:{}", server, port))?;
+    tcp.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    tcp.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+
+    let tls = rustls::ClientConnection::new(client_config()?, server_name(server)?)?;
+    Ok(rustls::StreamOwned::new(tls, tcp))
+}
+
+/// Opens a TLS connection on the tokio runtime.
+#[cfg(feature = "imap")]
+pub async fn connect_async(server: &str, port: u16) -> Result<AsyncTlsStream> {
+    let tcp = tokio::time::timeout(NETWORK_TIMEOUT, tokio::net::TcpStream::connect((server, port)))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|connected| Ok(connected?))
+        .with_context(|| format!("Failed to connect to {}:{}", server, port))?;
+
+    let connector = tokio_rustls::TlsConnector::from(client_config()?);
+    let tls = tokio::time::timeout(NETWORK_TIMEOUT, connector.connect(server_name(server)?, tcp))
+        .await
+        .context("TLS handshake timed out")??;
+    Ok(tls)
+}
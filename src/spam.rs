@@ -0,0 +1,122 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static STATUS_SCORE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b(?:score|hits)=(-?\d+(?:\.\d+)?)").unwrap());
+static STATUS_TESTS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\btests=(.*?)(?:\s+[a-z_]+=|$)").unwrap());
+/// A line of X-Spam-Report once split at its "*" bullets: " 3.5 BAYES_99 BODY: Bayes spam probability ..."
+static REPORT_LINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*(-?\d+(?:\.\d+)?)\s+([A-Z_][A-Z0-9_]*)\b\s*(.*?)\s*$").unwrap());
+
+/// What SpamAssassin made of a message, from its X-Spam-* headers.
+#[derive(Debug, Default)]
+pub struct SpamCheck {
+    pub score: Option<f64>,
+    pub rules: Vec<SpamRule>,
+}
+
+/// A SpamAssassin rule that fired.
+#[derive(Debug)]
+pub struct SpamRule {
+    pub name: String,
+    /// Points it added, when the headers say
+    pub score: Option<f64>,
+    pub description: Option<String>,
+}
+
+/// Reads X-Spam-Status ("Yes, score=7.3 required=5.0 tests=BAYES_99,RDNS_NONE ..."),
+/// X-Spam-Score and X-Spam-Report (one "* 3.5 BAYES_99 description" line per rule).
+pub fn spam_check(headers: &[(String, String)]) -> SpamCheck {
+    let mut check = SpamCheck::default();
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("x-spam-status") {
+            if let Some(score) = STATUS_SCORE.captures(value) {
+                check.score = check.score.or(score[1].parse().ok());
+            }
+            if let Some(tests) = STATUS_TESTS.captures(value) {
+                for test in tests[1].split(',').map(str::trim).filter(|test| !test.is_empty() && !test.eq_ignore_ascii_case("none")) {
+                    let (rule, score) = match test.split_once('=') {
+                        Some((rule, score)) => (rule, score.parse().ok()),
+                        None => (test, None),
+                    };
+                    add_rule(&mut check.rules, rule, score, None);
+                }
+            }
+        } else if name.eq_ignore_ascii_case("x-spam-score") {
+            check.score = check.score.or(value.trim().parse().ok());
+        } else if name.eq_ignore_ascii_case("x-spam-report") {
+            for line in value.split('*') {
+                if let Some(hit) = REPORT_LINE.captures(line) {
+                    let description = Some(hit[3].to_string()).filter(|description| !description.is_empty());
+                    add_rule(&mut check.rules, &hit[2], hit[1].parse().ok(), description);
+                }
+            }
+        }
+    }
+    check
+}
+
+/// Adds a rule, or fills in what an earlier header didn't say about it.
+fn add_rule(rules: &mut Vec<SpamRule>, name: &str, score: Option<f64>, description: Option<String>) {
+    match rules.iter_mut().find(|rule| rule.name == name) {
+        Some(rule) => {
+            rule.score = rule.score.or(score);
+            rule.description = rule.description.take().or(description);
+        }
+        None => rules.push(SpamRule { name: name.to_string(), score, description }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(headers: &[(&str, &str)]) -> SpamCheck {
+        let headers: Vec<(String, String)> = headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        spam_check(&headers)
+    }
+
+    /// Rules as (name, score, description).
+    fn rules(check: &SpamCheck) -> Vec<(&str, Option<f64>, Option<&str>)> {
+        check.rules.iter().map(|rule| (rule.name.as_str(), rule.score, rule.description.as_deref())).collect()
+    }
+
+    #[test]
+    fn scores() {
+        let cases = [
+            (vec![("X-Spam-Status", "Yes, score=7.3 required=5.0 tests=BAYES_99 autolearn=no")], Some(7.3)),
+            (vec![("X-Spam-Status", "No, hits=-1.9 required=5.0")], Some(-1.9)),
+            (vec![("x-spam-score", " 4 ")], Some(4.0)),
+            // X-Spam-Status wins over X-Spam-Score, whichever comes first
+            (vec![("X-Spam-Score", "2.5"), ("X-Spam-Status", "No, score=1.0")], Some(2.5)),
+            (vec![("X-Spam-Flag", "YES")], None),
+        ];
+        for (headers, score) in cases {
+            assert_eq!(check(&headers).score, score, "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn status_tests() {
+        let status = check(&[(
+            "X-Spam-Status",
+            "Yes, score=7.3 required=5.0 tests=BAYES_99=3.5,\tRDNS_NONE, URIBL_BLOCKED autolearn=no version=3.4.6",
+        )]);
+        assert_eq!(rules(&status), [("BAYES_99", Some(3.5), None), ("RDNS_NONE", None, None), ("URIBL_BLOCKED", None, None)]);
+        assert!(check(&[("X-Spam-Status", "No, score=0.0 tests=none")]).rules.is_empty());
+    }
+
+    #[test]
+    fn report_fills_in_status() {
+        let spam = check(&[
+            ("X-Spam-Status", "Yes, score=4.3 tests=BAYES_99,RDNS_NONE"),
+            ("X-Spam-Report", "* 3.5 BAYES_99 BODY: Bayes spam probability is 99 to 100%\n* 0.8 RDNS_NONE Delivered to internal network by a host with no rDNS\n* -0.0 SPF_PASS"),
+        ]);
+        assert_eq!(
+            rules(&spam),
+            [
+                ("BAYES_99", Some(3.5), Some("BODY: Bayes spam probability is 99 to 100%")),
+                ("RDNS_NONE", Some(0.8), Some("Delivered to internal network by a host with no rDNS")),
+                ("SPF_PASS", Some(-0.0), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! One-paragraph thread summaries from a language model (`--summarize`),
+//! stored in `thread_summaries`.
+//!
+//! Any endpoint speaking the OpenAI chat completions API works: Ollama,
+//! llama.cpp's server, vLLM, LM Studio, or a hosted one. Each summary is
+//! committed as soon as it arrives, and threads whose summary already covers
+//! their latest message are skipped, so an interrupted run picks up where it
+//! stopped.
+
+use anyhow::{bail, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::import;
+use crate::llm::Endpoint;
+use crate::schema::{self, Tables};
+use crate::thread::{self, ThreadFormat};
+
+pub const DEFAULT_URL: &str = "http://localhost:11434/v1/chat/completions";
+
+const PROMPT: &str = "You summarize email conversations. Reply with one paragraph of at most five sentences \
+saying who was involved, what was discussed or decided, and anything left open. Reply with the summary only.";
+
+/// Threads with fewer messages aren't worth a summary.
+const MIN_THREAD_MESSAGES: i64 = 2;
+
+/// Longest thread text sent, in characters; the rest is cut off.
+const MAX_THREAD_CHARS: usize = 24_000;
+
+pub struct Summarizer {
+    endpoint: Endpoint,
+    model: String,
+}
+
+#[derive(Default)]
+pub struct SummaryCounts {
+    pub summarized: usize,
+    /// Threads the endpoint refused (too long, bad content); they're tried again next run
+    pub failed: usize,
+    /// Threads left for the next run after a Ctrl-C
+    pub remaining: usize,
+}
+
+impl Summarizer {
+    /// `per_minute` caps the requests sent each minute.
+    pub fn new(url: &str, model: &str, api_key: Option<String>, per_minute: u32) -> Result<Self> {
+        Ok(Self { endpoint: Endpoint::new(url, api_key, per_minute)?, model: model.to_string() })
+    }
+
+    /// Asks for a summary of `conversation`. Ok(None) means the endpoint
+    /// refused this one thread, which is noted above `progress`.
+    fn summarize(&self, conversation: &str, progress: &ProgressBar) -> Result<Option<String>> {
+        let body = json!({
+            "model": self.model,
+            "temperature": 0.2,
+            "messages": [
+                {"role": "system", "content": PROMPT},
+                {"role": "user", "content": conversation},
+            ],
+        });
+        let reply = match self.endpoint.post(&body)? {
+            Ok(reply) => reply,
+            Err(refused) => {
+                progress.println(format!("Warning: {} refused a thread (HTTP {}): {}", self.endpoint.url, refused.status, refused.message));
+                return Ok(None);
+            }
+        };
+        let summary = reply["choices"][0]["message"]["content"]
+            .as_str()
+            .context("The summary response had no choices[0].message.content")?;
+        Ok(Some(summary.trim().to_string()))
+    }
+}
+
+/// Summarizes every thread of two or more messages in `database` that has
+/// no summary yet, or whose summary predates its latest message.
+pub fn summarize_threads(database: &Path, tables: &Tables, summarizer: &Summarizer) -> Result<SummaryCounts> {
+    let conn = Connection::open(database).with_context(|| format!("Failed to open database: {}", database.display()))?;
+    conn.busy_timeout(Duration::from_secs(30))?;
+    if !schema::table_exists(&conn, &tables.threads)? {
+        bail!("--summarize needs the threads table, which --schema flat doesn't create");
+    }
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {thread_summaries} (
+                thread_id TEXT PRIMARY KEY,
+                summary TEXT NOT NULL,
+                message_count INTEGER NOT NULL,
+                last_email_id INTEGER NOT NULL,
+                model TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            thread_summaries = tables.thread_summaries,
+        ),
+        [],
+    )?;
+
+    let pending: Vec<(String, i64, i64)> = conn
+        .prepare(&format!(
+            "WITH counted AS (
+                 SELECT thread_id, COUNT(*) AS messages, MAX(email_id) AS last_email_id FROM {threads} GROUP BY thread_id
+             )
+             SELECT c.thread_id, c.messages, c.last_email_id
+             FROM counted c LEFT JOIN {thread_summaries} s ON s.thread_id = c.thread_id
+             WHERE c.messages >= ?1 AND (s.thread_id IS NULL OR s.last_email_id <> c.last_email_id)
+             ORDER BY c.last_email_id",
+            threads = tables.threads,
+            thread_summaries = tables.thread_summaries,
+        ))?
+        .query_map([MIN_THREAD_MESSAGES], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let progress = ProgressBar::new(pending.len() as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} threads summarized ({eta})")
+            .unwrap(),
+    );
+
+    let mut counts = SummaryCounts::default();
+    let total = pending.len();
+    for (done, (thread_id, message_count, last_email_id)) in pending.into_iter().enumerate() {
+        if import::interrupted() {
+            counts.remaining = total - done;
+            break;
+        }
+        let mut conversation = thread::render_thread(&conn, tables, &thread_id, ThreadFormat::Md)?;
+        if conversation.len() > MAX_THREAD_CHARS {
+            let mut end = MAX_THREAD_CHARS;
+            while !conversation.is_char_boundary(end) {
+                end -= 1;
+            }
+            conversation.truncate(end);
+            conversation.push_str("\n\n[The rest of the thread is cut off]");
+        }
+
+        let summary = summarizer.summarize(&conversation, &progress).with_context(|| {
+            format!("Summarizing stopped after {} threads; run again to carry on from there", counts.summarized)
+        })?;
+
+        match summary {
+            Some(summary) => {
+                conn.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO {} (thread_id, summary, message_count, last_email_id, model, created_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))",
+                        tables.thread_summaries
+                    ),
+                    params![&thread_id, &summary, message_count, last_email_id, &summarizer.model],
+                )?;
+                counts.summarized += 1;
+            }
+            None => counts.failed += 1,
+        }
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    Ok(counts)
+}
@@ -0,0 +1,233 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+
+use crate::import::message_ids;
+use crate::schema::{self, Tables};
+use crate::text;
+
+static REPLY_PREFIX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^\s*((re|fwd?|aw|sv|wg)\s*(\[\d+\])?\s*:\s*)+").unwrap());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ThreadFormat {
+    /// Markdown: one section per message, oldest first, quoted replies removed
+    Md,
+    /// Reply tree: one line per message with date and sender, indented under the message it answers
+    Tree,
+}
+
+/// One message of a conversation, as shown when rendering a thread.
+struct ThreadMessage {
+    id: i64,
+    message_id: String,
+    in_reply_to: String,
+    references: String,
+    from: String,
+    to: String,
+    date: String,
+    subject: String,
+    body: String,
+}
+
+/// Finds the thread `id` belongs to. `id` may be a thread id from the threads
+/// table (the root Message-ID), the Message-ID of any message in the thread
+/// (with or without angle brackets), or an email id (emails.id).
+fn resolve_thread_id(conn: &Connection, tables: &Tables, id: &str) -> Result<String> {
+    let id = id.trim().trim_start_matches('<').trim_end_matches('>');
+
+    let is_thread: bool = conn.query_row(
+        &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE thread_id = ?1)", tables.threads),
+        [id],
+        |row| row.get(0),
+    )?;
+    if is_thread {
+        return Ok(id.to_string());
+    }
+
+    let by_message_id = format!(
+        "SELECT t.thread_id FROM {} t JOIN {} e ON e.id = t.email_id WHERE e.message_id = ?1",
+        tables.threads, tables.emails
+    );
+    if let Some(thread_id) = conn.query_row(&by_message_id, [format!("<{}>", id)], |row| row.get(0)).optional()? {
+        return Ok(thread_id);
+    }
+
+    if let Ok(email_id) = id.parse::<i64>() {
+        let by_email_id = format!("SELECT thread_id FROM {} WHERE email_id = ?1", tables.threads);
+        if let Some(thread_id) = conn.query_row(&by_email_id, [email_id], |row| row.get(0)).optional()? {
+            return Ok(thread_id);
+        }
+    }
+
+    bail!("No thread, message or email id matches {}", id)
+}
+
+/// The messages of a thread, oldest first.
+fn thread_messages(conn: &Connection, tables: &Tables, thread_id: &str) -> Result<Vec<ThreadMessage>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.id, COALESCE(e.message_id, ''), COALESCE(e.in_reply_to, ''), COALESCE(e.refs, ''),
+                COALESCE(e.from_addr, ''), COALESCE(e.to_addr, ''), COALESCE(e.date_parsed, e.date, ''), COALESCE(e.subject, ''),
+                COALESCE(e.body_clean, ''), COALESCE(e.body_plain, ''), COALESCE(e.body_html, '')
+         FROM {threads} t JOIN {emails} e ON e.id = t.email_id
+         WHERE t.thread_id = ?1
+         ORDER BY e.date_parsed, e.id",
+        threads = tables.threads,
+        emails = tables.emails,
+    ))?;
+    let messages = stmt
+        .query_map([thread_id], |row| {
+            let (clean, plain, html): (String, String, String) = (row.get(8)?, row.get(9)?, row.get(10)?);
+            // body_clean has quoted replies and the signature removed already
+            let body = if !clean.trim().is_empty() {
+                clean
+            } else if !plain.trim().is_empty() {
+                plain
+            } else {
+                text::html_to_text(&html)
+            };
+            Ok(ThreadMessage {
+                id: row.get(0)?,
+                message_id: row.get(1)?,
+                in_reply_to: row.get(2)?,
+                references: row.get(3)?,
+                from: row.get(4)?,
+                to: row.get(5)?,
+                date: row.get(6)?,
+                subject: row.get(7)?,
+                body,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if messages.is_empty() {
+        bail!("No thread with id {}", thread_id);
+    }
+    Ok(messages)
+}
+
+/// Renders the conversation `id` belongs to; see [`resolve_thread_id`] for
+/// the ids accepted.
+pub fn render_thread(conn: &Connection, tables: &Tables, id: &str, format: ThreadFormat) -> Result<String> {
+    if !schema::table_exists(conn, &tables.threads)? {
+        bail!("The database has no threads table; import it with --schema normalized or full");
+    }
+    let thread_id = resolve_thread_id(conn, tables, id)?;
+    let messages = thread_messages(conn, tables, &thread_id)?;
+    match format {
+        ThreadFormat::Md => Ok(markdown(&messages)),
+        ThreadFormat::Tree => Ok(tree(&messages)),
+    }
+}
+
+fn markdown(messages: &[ThreadMessage]) -> String {
+    let mut out = format!("# {}\n", markdown_line(&messages[0].subject));
+    for message in messages {
+        out.push_str(&format!("\n## {} — {}\n\n", markdown_line(&message.from), message.date));
+        if !message.to.is_empty() {
+            out.push_str(&format!("**To:** {}\n\n", markdown_line(&message.to)));
+        }
+        if message.subject != messages[0].subject {
+            out.push_str(&format!("**Subject:** {}\n\n", markdown_line(&message.subject)));
+        }
+        out.push_str(message.body.trim());
+        out.push('\n');
+    }
+    out
+}
+
+/// Escapes the characters that would otherwise turn a header value into
+/// markup (an address in angle brackets reads as an HTML tag).
+fn markdown_line(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The index of the message each one replies to: In-Reply-To if that message
+/// is in the thread, else the nearest ancestor from References that is.
+fn parents(messages: &[ThreadMessage]) -> Vec<Option<usize>> {
+    let by_message_id: HashMap<String, usize> = messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, message)| message_ids(&message.message_id).into_iter().next().map(|id| (id, i)))
+        .collect();
+
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, message)| {
+            message_ids(&message.in_reply_to)
+                .into_iter()
+                .chain(message_ids(&message.references).into_iter().rev())
+                .filter_map(|id| by_message_id.get(&id).copied())
+                .find(|&parent| parent != i)
+        })
+        .collect()
+}
+
+fn tree(messages: &[ThreadMessage]) -> String {
+    let parents = parents(messages);
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); messages.len()];
+    let mut roots = Vec::new();
+    for (i, parent) in parents.iter().enumerate() {
+        match parent {
+            Some(parent) => children[*parent].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    let mut out = format!("{} ({} messages)\n", messages[0].subject, messages.len());
+    let mut visited = vec![false; messages.len()];
+    for root in roots {
+        tree_lines(messages, &children, root, "", None, &mut visited, &mut out);
+    }
+    // Messages whose replies form a loop (bad headers) have no root; list them at the top level
+    while let Some(rest) = visited.iter().position(|&seen| !seen) {
+        tree_lines(messages, &children, rest, "", None, &mut visited, &mut out);
+    }
+    out
+}
+
+/// Writes `index` and, indented beneath it, its replies. `last` is whether it's
+/// the last reply to its parent (None for top-level messages, which get no connector).
+fn tree_lines(
+    messages: &[ThreadMessage],
+    children: &[Vec<usize>],
+    index: usize,
+    prefix: &str,
+    last: Option<bool>,
+    visited: &mut [bool],
+    out: &mut String,
+) {
+    if visited[index] {
+        return;
+    }
+    visited[index] = true;
+
+    let message = &messages[index];
+    let (connector, child_prefix) = match last {
+        None => ("", prefix.to_string()),
+        Some(false) => ("├─ ", format!("{}│  ", prefix)),
+        Some(true) => ("└─ ", format!("{}   ", prefix)),
+    };
+    let date = message.date.get(..16).unwrap_or(&message.date);
+    out.push_str(&format!("{}{}{}  {}  #{}", prefix, connector, date, message.from, message.id));
+    // Only a subject that was actually changed, not one that just gained "Re:"
+    if REPLY_PREFIX.replace(&message.subject, "") != REPLY_PREFIX.replace(&messages[0].subject, "") {
+        out.push_str(&format!("  ({})", message.subject));
+    }
+    out.push('\n');
+
+    let replies = &children[index];
+    for (n, &reply) in replies.iter().enumerate() {
+        tree_lines(messages, children, reply, &child_prefix, Some(n + 1 == replies.len()), visited, out);
+    }
+}